@@ -0,0 +1,9 @@
+-- Per cart encryption keys used for crypto-shredding personal data held in events.
+-- A NULL key marks a cart that has been forgotten. The row is kept as a tombstone so a new key
+-- is never issued for a forgotten cart.
+CREATE TABLE cart_key (
+    cart_id UUID PRIMARY KEY,
+    key BYTEA,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    forgotten_at TIMESTAMPTZ
+);
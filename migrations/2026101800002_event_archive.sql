@@ -0,0 +1,11 @@
+-- Events of long-finished carts moved out of the event store by the archival job.
+-- Holds the columns of the event table needed to read an archived event back.
+CREATE TABLE event_archive (
+    event_id BIGINT PRIMARY KEY,
+    event_type VARCHAR(255) NOT NULL,
+    payload BYTEA NOT NULL,
+    cart_id UUID NOT NULL,
+    inserted_at TIMESTAMP NOT NULL,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX index_event_archive_cart_id ON event_archive (cart_id);
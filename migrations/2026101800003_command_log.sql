@@ -0,0 +1,15 @@
+-- Every command dispatched through the CommandBus, including those the domain rejected.
+CREATE TABLE command_log (
+    command_log_id BIGSERIAL PRIMARY KEY,
+    command_name VARCHAR(255) NOT NULL,
+    origin VARCHAR(32) NOT NULL,
+    cart_id UUID,
+    payload JSONB NOT NULL,
+    outcome VARCHAR(32) NOT NULL,
+    event_ids BIGINT[] NOT NULL DEFAULT '{}',
+    error TEXT,
+    latency_us BIGINT NOT NULL,
+    dispatched_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX index_command_log_cart_id ON command_log (cart_id);
+CREATE INDEX index_command_log_dispatched_at ON command_log (dispatched_at);
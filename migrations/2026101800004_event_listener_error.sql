@@ -0,0 +1,7 @@
+-- The last error each event listener failed with, recorded by the ListenerMonitor.
+CREATE TABLE event_listener_error (
+    id TEXT PRIMARY KEY,
+    event_id BIGINT NOT NULL,
+    error TEXT NOT NULL,
+    failed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
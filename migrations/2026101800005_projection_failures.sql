@@ -0,0 +1,11 @@
+-- Events an event listener failed to handle and parked so that it could move on.
+CREATE TABLE projection_failures (
+    projection_failure_id BIGSERIAL PRIMARY KEY,
+    listener_id TEXT NOT NULL,
+    event_id BIGINT NOT NULL,
+    error TEXT NOT NULL,
+    attempts INT NOT NULL,
+    parked_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    replayed_at TIMESTAMPTZ,
+    UNIQUE (listener_id, event_id)
+);
@@ -0,0 +1,14 @@
+-- The last event applied by each projection, advanced in the same transaction as its writes.
+CREATE TABLE projection_checkpoint (
+    projection_id TEXT PRIMARY KEY,
+    last_event_id BIGINT NOT NULL
+);
+
+-- The last event a projection applied for each key, e.g. each cart, so that an event replayed out
+-- of order is not applied over the changes of later events for the same key.
+CREATE TABLE projection_key_version (
+    projection_id TEXT NOT NULL,
+    key TEXT NOT NULL,
+    last_event_id BIGINT NOT NULL,
+    PRIMARY KEY (projection_id, key)
+);
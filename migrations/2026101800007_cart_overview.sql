@@ -0,0 +1,20 @@
+-- One row per cart for listing carts. The items are kept so that removing one adjusts the totals.
+CREATE TABLE cart_overview (
+    cart_id UUID PRIMARY KEY,
+    status TEXT NOT NULL,
+    item_count INT NOT NULL,
+    total_price NUMERIC NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    last_event_id BIGINT NOT NULL
+);
+CREATE INDEX index_cart_overview_status ON cart_overview (status);
+CREATE INDEX index_cart_overview_created_at ON cart_overview (created_at, cart_id);
+CREATE INDEX index_cart_overview_updated_at ON cart_overview (updated_at, cart_id);
+
+CREATE TABLE cart_overview_items (
+    cart_id UUID NOT NULL,
+    item_id UUID NOT NULL,
+    price NUMERIC NOT NULL,
+    PRIMARY KEY (cart_id, item_id)
+);
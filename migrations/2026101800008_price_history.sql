@@ -0,0 +1,8 @@
+CREATE TABLE price_history (
+    product_id UUID NOT NULL,
+    event_id BIGINT NOT NULL,
+    old_price NUMERIC NOT NULL,
+    new_price NUMERIC NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (product_id, event_id)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE inventory_history (
+    product_id UUID NOT NULL,
+    event_id BIGINT NOT NULL,
+    inventory INT NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (product_id, event_id)
+);
+
+CREATE INDEX index_inventories_inventory ON inventories (inventory);
@@ -0,0 +1,17 @@
+-- Submitted carts, aggregated by the sales analytics endpoints. Days are UTC days.
+CREATE TABLE sales_orders (
+    cart_id UUID PRIMARY KEY,
+    order_date DATE NOT NULL,
+    total_price NUMERIC NOT NULL,
+    published BOOLEAN NOT NULL,
+    submitted_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX index_sales_orders_order_date ON sales_orders (order_date);
+
+CREATE TABLE sales_order_products (
+    cart_id UUID NOT NULL,
+    product_id UUID NOT NULL,
+    quantity INT NOT NULL,
+    revenue NUMERIC NOT NULL,
+    PRIMARY KEY (cart_id, product_id)
+);
@@ -0,0 +1,9 @@
+-- The publication status of each submitted cart.
+CREATE TABLE cart_publication (
+    cart_id UUID PRIMARY KEY,
+    status TEXT NOT NULL,
+    attempts INT NOT NULL,
+    submitted_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    last_event_id BIGINT NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Cart items searchable by description.
+CREATE TABLE cart_item_search (
+    cart_id UUID NOT NULL,
+    item_id UUID NOT NULL,
+    product_id UUID NOT NULL,
+    description TEXT NOT NULL,
+    search_vector TSVECTOR GENERATED ALWAYS AS (to_tsvector('english', description)) STORED,
+    PRIMARY KEY (cart_id, item_id)
+);
+CREATE INDEX index_cart_item_search_search_vector ON cart_item_search USING GIN (search_vector);
@@ -0,0 +1,12 @@
+-- Snapshots of command state, one per state query and stream query.
+-- `shape` is the version of the state query's fields the payload was stored with, so a snapshot
+-- stored before a state query changed is replaced rather than loaded.
+CREATE TABLE state_snapshot (
+    name TEXT NOT NULL,
+    query TEXT NOT NULL,
+    shape INT NOT NULL,
+    version BIGINT NOT NULL,
+    payload TEXT NOT NULL,
+    inserted_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (name, query)
+);
@@ -3,13 +3,17 @@
 use anyhow::Context;
 use axum::Json;
 use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Response};
 use disintegrate::{Decision, StateMutate, StateQuery};
 use rust_decimal::Decimal;
 use std::path::PathBuf;
 use uuid::Uuid;
 
 use crate::domain::helpers::device_fingerprint_calculator::calculate_device_fingerprint;
-use crate::domain::{CartStream, DecisionMaker, DomainEvent};
+use crate::domain::{
+    CartStream, Command, CommandBus, CommandOrigin, DomainEvent, DryRunParam, ExpectedEventId,
+    REDACTED,
+};
 use crate::infra::ClientError;
 
 use super::{CartError, CartId, ItemId, ProductId};
@@ -27,10 +31,12 @@ pub struct AddItemPayload {
 }
 
 pub async fn add_item_endpoint(
-    State(decider): State<DecisionMaker>,
+    State(command_bus): State<CommandBus>,
     Path(cart_id): Path<Uuid>,
+    dry_run: DryRunParam,
+    expected_event_id: ExpectedEventId,
     Json(payload): Json<AddItemPayload>,
-) -> Result<Json<(Uuid, i64)>, ClientError> {
+) -> Result<Response, ClientError> {
     if cart_id != payload.cart_id {
         return Err(ClientError::Payload(
             "Path CartId does not match payload CartId.".to_owned(),
@@ -40,7 +46,13 @@ pub async fn add_item_endpoint(
     let mut decision: AddItemCommand = payload.try_into()?;
     decision.fingerprint = calculate_device_fingerprint();
 
-    let events = decider.make(decision).await?;
+    if dry_run.0 {
+        return Ok(Json(command_bus.dry_run(decision, expected_event_id).await?).into_response());
+    }
+
+    let events = command_bus
+        .dispatch_expecting(decision, CommandOrigin::Http, expected_event_id)
+        .await?;
 
     let last_event_id = events
         .into_iter()
@@ -48,12 +60,12 @@ pub async fn add_item_endpoint(
         .map(|e| e.id())
         .context("No event returned for AddItemCommand!")?;
 
-    Ok(Json((cart_id, last_event_id)))
+    Ok(Json((cart_id, last_event_id)).into_response())
 }
 
 //------------------------- Command ----------------------------
 
-#[derive(Debug, Clone)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct AddItemCommand {
     pub cart_id: CartId,
     pub description: String,
@@ -61,9 +73,26 @@ pub struct AddItemCommand {
     pub price: Decimal,
     pub item_id: ItemId,
     pub product_id: ProductId,
+    /// Personal data, so it is not serialised. Empty when deserialised.
+    #[serde(skip)]
     pub fingerprint: String,
 }
 
+/// Written by hand so that the fingerprint, being personal data, is never logged.
+impl std::fmt::Debug for AddItemCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AddItemCommand")
+            .field("cart_id", &self.cart_id)
+            .field("description", &self.description)
+            .field("image", &self.image)
+            .field("price", &self.price)
+            .field("item_id", &self.item_id)
+            .field("product_id", &self.product_id)
+            .field("fingerprint", &REDACTED)
+            .finish()
+    }
+}
+
 impl TryFrom<AddItemPayload> for AddItemCommand {
     type Error = ClientError;
 
@@ -83,6 +112,12 @@ impl TryFrom<AddItemPayload> for AddItemCommand {
     }
 }
 
+impl Command for AddItemCommand {
+    fn cart_id(&self) -> Option<CartId> {
+        Some(self.cart_id)
+    }
+}
+
 impl Decision for AddItemCommand {
     type Event = DomainEvent;
     type StateQuery = AddItemState;
@@ -94,10 +129,15 @@ impl Decision for AddItemCommand {
             cart_exists: false,
             item_count: 0,
             submitted: false,
+            forgotten: false,
         }
     }
 
     fn process(&self, state: &Self::StateQuery) -> Result<Vec<Self::Event>, Self::Error> {
+        if state.forgotten {
+            return Err(CartError::CartForgotten);
+        }
+
         if state.submitted {
             return Err(CartError::CartCannotBeAltered);
         }
@@ -137,6 +177,14 @@ pub struct AddItemState {
     cart_exists: bool,
     item_count: u8,
     submitted: bool,
+    #[serde(default)]
+    forgotten: bool,
+}
+
+impl AddItemState {
+    pub(super) fn item_count(&self) -> u8 {
+        self.item_count
+    }
 }
 
 impl StateMutate for AddItemState {
@@ -160,6 +208,9 @@ impl StateMutate for AddItemState {
             CartStream::CartSubmitted { .. } => {
                 self.submitted = true;
             }
+            CartStream::CartForgotten { .. } => {
+                self.forgotten = true;
+            }
         }
     }
 }
@@ -172,6 +223,7 @@ mod tests {
     use crate::domain::fake::{FingerPrint, Price};
 
     use super::*;
+    use crate::domain::helpers::command_bus::CommandPayload;
     use disintegrate::TestHarness;
     use fake::{Fake, Faker};
 
@@ -279,4 +331,31 @@ mod tests {
         })
         .then_err(CartError::CannotAddItemCartFull);
     }
+
+    #[test]
+    fn item_should_not_be_added_if_cart_is_forgotten() {
+        let cart_id = CartId::new();
+
+        TestHarness::given([
+            DomainEvent::CartCreated { cart_id },
+            DomainEvent::CartForgotten { cart_id },
+        ])
+        .when(AddItemCommand {
+            cart_id,
+            ..Faker.fake()
+        })
+        .then_err(CartError::CartForgotten);
+    }
+
+    #[test]
+    fn fingerprint_should_not_be_logged() {
+        let command = AddItemCommand {
+            fingerprint: "device-1234".to_owned(),
+            ..Faker.fake()
+        };
+
+        let logged = format!("{command:?} {}", command.payload());
+
+        assert!(!logged.contains("device-1234"));
+    }
 }
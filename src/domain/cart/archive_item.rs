@@ -1,19 +1,42 @@
-use disintegrate::{Decision, StateMutate, StateQuery};
+use std::time::Duration;
+
+use anyhow::bail;
+use async_trait::async_trait;
+use disintegrate::{
+    Decision, DecisionError, EventListener, PersistedEvent, StateMutate, StateQuery, StreamQuery,
+    query,
+};
 use sqlx::PgPool;
-use tracing::error;
+use tracing::{error, info};
 
-use crate::domain::{CartStream, DecisionMaker, DomainEvent};
+use crate::{
+    domain::{CartStream, Command, CommandBus, CommandOrigin, DomainEvent, PricingStream},
+    subsystems::work_queue::{
+        TaskArgs, TaskDomainArgs, TaskLimit, TaskOutcome, TaskTrigger, WorkQueue,
+    },
+};
 
-use super::{CartError, CartId, ItemId, ProductId, carts_with_products::find_by_product_id};
+use super::{CartError, CartId, ItemId, ProductId, carts_with_products::find_added_before};
 
 //------------------------- Command ----------------------------
 
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct ArchiveItemCommand {
     pub cart_id: CartId,
     pub item_id: ItemId,
     pub price_changed_event_id: i64,
 }
 
+impl Command for ArchiveItemCommand {
+    fn origins(&self) -> &'static [CommandOrigin] {
+        &[CommandOrigin::Processor]
+    }
+
+    fn cart_id(&self) -> Option<CartId> {
+        Some(self.cart_id)
+    }
+}
+
 impl Decision for ArchiveItemCommand {
     type Event = DomainEvent;
     type StateQuery = ArchiveItemState;
@@ -27,11 +50,16 @@ impl Decision for ArchiveItemCommand {
             cart_exists: false,
             item_exists: false,
             submitted: false,
+            forgotten: false,
             price_change_already_processed: false,
         }
     }
 
     fn process(&self, state: &Self::StateQuery) -> Result<Vec<Self::Event>, Self::Error> {
+        if state.forgotten {
+            return Err(CartError::CartForgotten);
+        }
+
         if state.submitted {
             return Err(CartError::CartCannotBeAltered);
         }
@@ -60,6 +88,7 @@ pub struct ArchiveItemState {
     cart_exists: bool,
     item_exists: bool,
     submitted: bool,
+    forgotten: bool,
     price_change_already_processed: bool,
 }
 
@@ -97,49 +126,131 @@ impl StateMutate for ArchiveItemState {
             CartStream::CartSubmitted { .. } => {
                 self.submitted = true;
             }
+            CartStream::CartForgotten { .. } => {
+                self.forgotten = true;
+            }
+        }
+    }
+}
+
+//------------ Event Handler for triggering Processor -----------
+
+/// Queues the archiving of a product's items for every PriceChanged event. The work queue only
+/// holds one task per event, so handling an event again queues nothing.
+pub struct PriceChangedEventHandler {
+    query: StreamQuery<i64, PricingStream>,
+    queue: WorkQueue,
+}
+
+impl PriceChangedEventHandler {
+    pub fn new(queue: WorkQueue) -> Self {
+        Self {
+            queue,
+            query: query!(PricingStream),
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener<i64, PricingStream> for PriceChangedEventHandler {
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        "price_changed"
+    }
+
+    fn query(&self) -> &StreamQuery<i64, PricingStream> {
+        &self.query
+    }
+
+    async fn handle(&self, event: PersistedEvent<i64, PricingStream>) -> Result<(), Self::Error> {
+        let event_id = event.id();
+        match event.into_inner() {
+            PricingStream::PriceChanged { product_id, .. } => {
+                let task_args = TaskArgs {
+                    trigger: TaskTrigger::Event(event_id),
+                    limits: TaskLimit::TimeoutAfter(Duration::from_secs(3600)),
+                    domain_args: TaskDomainArgs::ArchiveProduct(ArchiveProductProcessorArgs {
+                        product_id,
+                        triggering_event_id: event_id,
+                    }),
+                };
+
+                self.queue
+                    .push(task_args)
+                    .await
+                    .inspect_err(|e| error!("PriceChangedEventHandler: Failed to queue ArchiveProduct task for event {event_id} due to {e}."))?;
+            }
         }
+
+        Ok(())
     }
 }
 
 //--------------------------- Processor -----------------------------
 
+/// How long archiving a product waits for the CartsWithProducts read model to catch up before it
+/// checks again.
+const CATCH_UP_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ArchiveProductProcessorArgs {
+    pub product_id: ProductId,
+    pub triggering_event_id: i64,
+}
+
+/// Archives the items with the product that were added before the PriceChanged event. Is
+/// deferred until the CartsWithProducts read model has caught up with the event, and fails, to be
+/// retried by the work queue, while any archiving fails. ArchiveItemCommand ignores price changes
+/// it has already processed, so a retry archives nothing twice.
 pub async fn archive_product_processor(
     pool: &PgPool,
-    decider: &DecisionMaker,
-    product_id: ProductId,
-    triggering_event_id: i64,
-) {
-    match find_by_product_id(pool, &product_id).await {
-        Ok(cart_items) => {
-            let mut error_count = 0;
-
-            for cart_item in cart_items {
-                let decision = ArchiveItemCommand {
-                    cart_id: cart_item.cart_id,
-                    item_id: cart_item.item_id,
-                    price_changed_event_id: triggering_event_id,
-                };
-                if let Err(error) = decider.make(decision).await {
-                    error!(
-                        "ArchiveProductProcessor: ArchiveItemCommand failed for cart {} item {} with error: {error:?}",
-                        cart_item.cart_id, cart_item.item_id,
-                    );
-                    error_count += 1;
-                }
-            }
+    command_bus: &CommandBus,
+    args: ArchiveProductProcessorArgs,
+) -> Result<TaskOutcome, anyhow::Error> {
+    let ArchiveProductProcessorArgs {
+        product_id,
+        triggering_event_id,
+    } = args;
+    let Some(cart_items) = find_added_before(pool, &product_id, triggering_event_id).await? else {
+        return Ok(TaskOutcome::Deferred(CATCH_UP_DELAY));
+    };
 
-            if error_count > 0 {
+    let mut error_count = 0;
+    for cart_item in cart_items {
+        let decision = ArchiveItemCommand {
+            cart_id: cart_item.cart_id,
+            item_id: cart_item.item_id,
+            price_changed_event_id: triggering_event_id,
+        };
+        match command_bus
+            .dispatch(decision, CommandOrigin::Processor)
+            .await
+        {
+            Ok(_) => {}
+            // E.g. the cart has been submitted since. Retrying would not change that.
+            Err(DecisionError::Domain(error)) => {
+                info!(
+                    "ArchiveProductProcessor: Item {} of cart {} was not archived. {error}",
+                    cart_item.item_id, cart_item.cart_id,
+                );
+            }
+            Err(error) => {
                 error!(
-                    "ArchiveProductProcessor: There were {error_count} errors archiving product {product_id}"
+                    "ArchiveProductProcessor: ArchiveItemCommand failed for cart {} item {} with error: {error:?}",
+                    cart_item.cart_id, cart_item.item_id,
                 );
+                error_count += 1;
             }
         }
-        Err(error) => {
-            error!(
-                "ArchiveProductProcessor: CartsWithProductsReadModel failed for product ({product_id}). Failed with {error:?}"
-            );
-        }
     }
+
+    if error_count > 0 {
+        bail!(
+            "ArchiveProductProcessor: There were {error_count} errors archiving product {product_id}"
+        );
+    }
+    Ok(TaskOutcome::Done)
 }
 
 //-------------------------- Tests -------------------------------
@@ -181,6 +292,32 @@ mod tests {
         }])
     }
 
+    #[test]
+    fn item_should_not_be_archived_if_cart_is_forgotten() {
+        let cart_id = CartId::new();
+        let item_id = ItemId::new();
+
+        TestHarness::given([
+            DomainEvent::CartCreated { cart_id },
+            DomainEvent::CartItemAdded {
+                cart_id,
+                description: Faker.fake(),
+                image: Faker.fake(),
+                price: Faker.fake(),
+                item_id,
+                product_id: ProductId::new(),
+                fingerprint: FingerPrint.fake(),
+            },
+            DomainEvent::CartForgotten { cart_id },
+        ])
+        .when(ArchiveItemCommand {
+            cart_id,
+            item_id,
+            price_changed_event_id: 10,
+        })
+        .then_err(CartError::CartForgotten)
+    }
+
     #[test]
     fn nothing_should_happen_if_archive_has_been_processed_prevously() {
         let cart_id = CartId::new();
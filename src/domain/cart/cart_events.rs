@@ -0,0 +1,54 @@
+//! Cart Events slice. Lists the events of a cart, optionally including archived events.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use uuid::Uuid;
+
+use crate::{
+    domain::{DomainEvent, EventArchive, EventStore},
+    infra::ClientError,
+};
+
+use super::{CartError, CartId};
+
+//------------------------- Web API ----------------------------
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct CartEventsParams {
+    #[serde(default)]
+    pub include_archive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct CartEvent {
+    pub event_id: i64,
+    pub event: DomainEvent,
+}
+
+pub async fn cart_events_endpoint(
+    State(event_store): State<EventStore>,
+    State(event_archive): State<EventArchive>,
+    Path(cart_uuid): Path<Uuid>,
+    Query(params): Query<CartEventsParams>,
+) -> Result<Json<Vec<CartEvent>>, ClientError> {
+    let cart_id: CartId = cart_uuid.try_into()?;
+    let events = event_archive
+        .cart_events(&event_store, &cart_id, params.include_archive)
+        .await?;
+
+    if events.is_empty() {
+        return Err(CartError::CartDoesNotExist(cart_id).into());
+    }
+
+    Ok(Json(
+        events
+            .into_iter()
+            .map(|event| CartEvent {
+                event_id: event.id(),
+                event: event.into_inner(),
+            })
+            .collect(),
+    ))
+}
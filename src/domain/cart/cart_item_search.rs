@@ -0,0 +1,519 @@
+//! Cart Item Search read model. Full-text search over the descriptions of the items in carts,
+//! e.g. for support to find the carts containing a "blue mug".
+//!
+//! An item's cart status is read from the Cart Overview read model, so searches also wait for that
+//! projection to catch up.
+//!
+//! Matches are ranked by Postgres `ts_rank`, best first. A page's `next_cursor`, passed as the
+//! `after` of the next request, holds the rank and ids of the page's last match.
+
+use anyhow::{Context, anyhow};
+use async_trait::async_trait;
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use disintegrate::{EventListener, PersistedEvent, StreamQuery, query};
+use sqlx::{PgConnection, PgPool};
+use tracing::error;
+
+use crate::{
+    domain::{
+        CartStream, MinEventId, ProjectionTransaction, RebuildableProjection, wait_for_projection,
+    },
+    infra::ClientError,
+};
+
+use super::{CartId, CartStatus, ItemId, ProductId, cart_overview};
+
+/// Matches returned per page unless a limit is given.
+const DEFAULT_LIMIT: i64 = 20;
+
+/// Most matches returned per page.
+const MAX_LIMIT: i64 = 100;
+
+//------------------------- Web API ----------------------------
+
+/// e.g. `GET /search/cart-items?q=blue%20mug&status=submitted&limit=10`
+pub async fn cart_item_search_endpoint(
+    State(pool): State<PgPool>,
+    Query(params): Query<CartItemSearchParams>,
+    min_event_id: MinEventId,
+) -> Result<Json<CartItemSearchPage>, ClientError> {
+    if params.q.trim().is_empty() {
+        return Err(ClientError::Payload(
+            "Search query q must not be empty.".to_owned(),
+        ));
+    }
+    let cursor = params
+        .after
+        .as_deref()
+        .map(SearchCursor::decode)
+        .transpose()?;
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
+    wait_for_projection(
+        &pool,
+        cart_overview::PROJECTION_ID,
+        &cart_overview::projection_query(),
+        min_event_id,
+    )
+    .await?;
+    Ok(Json(
+        search_cart_items(&pool, &params, cursor.as_ref()).await?,
+    ))
+}
+
+//----------------------- Read Model API ------------------------
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct CartItemSearchParams {
+    /// Words to search for, in web search syntax, e.g. `blue mug` or `"blue mug" -large`.
+    pub q: String,
+    /// Only match items in carts with this status.
+    pub status: Option<CartStatus>,
+    pub limit: Option<i64>,
+    /// The `next_cursor` of the previous page.
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CartItemMatch {
+    pub cart_id: CartId,
+    pub item_id: ItemId,
+    pub product_id: ProductId,
+    pub description: String,
+    pub status: CartStatus,
+    pub rank: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CartItemSearchPage {
+    pub items: Vec<CartItemMatch>,
+    /// Pass as `after` to read the next page. Missing on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Where the previous page ended: its last match's rank and ids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchCursor {
+    rank: f32,
+    cart_id: CartId,
+    item_id: ItemId,
+}
+
+impl SearchCursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}|{}|{}", self.rank, self.cart_id, self.item_id))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, ClientError> {
+        let invalid = || ClientError::Payload(format!("Invalid cursor {cursor}."));
+        let decoded = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(invalid)?;
+        let mut parts = decoded.splitn(3, '|');
+        let (Some(rank), Some(cart_id), Some(item_id)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        Ok(SearchCursor {
+            rank: rank.parse().map_err(|_| invalid())?,
+            cart_id: cart_id.parse().map_err(|_| invalid())?,
+            item_id: item_id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Reads one page of the items matching the search, best ranked first, continuing after
+/// `cursor` if given.
+pub async fn search_cart_items(
+    pool: &PgPool,
+    params: &CartItemSearchParams,
+    cursor: Option<&SearchCursor>,
+) -> Result<CartItemSearchPage, anyhow::Error> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let rows = sqlx::query!(
+        r#"WITH matches AS (
+               SELECT item.cart_id, item.item_id, item.product_id, item.description, cart.status,
+                      ts_rank(item.search_vector, search) AS rank
+               FROM cart_item_search item
+               JOIN cart_overview cart USING (cart_id),
+                    websearch_to_tsquery('english', $1) search
+               WHERE item.search_vector @@ search
+                 AND ($2::TEXT IS NULL OR cart.status = $2)
+           )
+           SELECT cart_id as "cart_id!: CartId",
+                  item_id as "item_id!: ItemId",
+                  product_id as "product_id!: ProductId",
+                  description as "description!",
+                  status as "status!",
+                  rank as "rank!"
+           FROM matches
+           WHERE ($3::REAL IS NULL OR (rank, cart_id, item_id) < ($3, $4::UUID, $5::UUID))
+           ORDER BY rank DESC, cart_id DESC, item_id DESC
+           LIMIT $6"#,
+        params.q,
+        params.status.map(|status| status.to_string()),
+        cursor.map(|cursor| cursor.rank),
+        cursor.map(|cursor| cursor.cart_id) as Option<CartId>,
+        cursor.map(|cursor| cursor.item_id) as Option<ItemId>,
+        limit + 1,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("Problem in search_cart_items(params: {params:?})."))?;
+
+    let mut items = Vec::with_capacity(rows.len());
+    let mut next_cursor = None;
+    for row in rows.iter().take(limit as usize) {
+        let item = CartItemMatch {
+            cart_id: row.cart_id,
+            item_id: row.item_id,
+            product_id: row.product_id,
+            description: row.description.clone(),
+            status: CartStatus::parse(&row.status).ok_or_else(|| {
+                anyhow!(
+                    "Problem in search_cart_items(). Unknown status {}.",
+                    row.status
+                )
+            })?,
+            rank: row.rank,
+        };
+        if rows.len() as i64 > limit {
+            next_cursor = Some(
+                SearchCursor {
+                    rank: item.rank,
+                    cart_id: item.cart_id,
+                    item_id: item.item_id,
+                }
+                .encode(),
+            );
+        }
+        items.push(item);
+    }
+
+    Ok(CartItemSearchPage { items, next_cursor })
+}
+
+//------------------------- Projection --------------------------
+
+pub(super) const PROJECTION_ID: &str = "cart_item_search";
+/// Listener id of the shadow copy built by a blue/green rebuild.
+const SHADOW_PROJECTION_ID: &str = "cart_item_search_shadow";
+
+fn projection_query() -> StreamQuery<i64, CartStream> {
+    query!(CartStream)
+}
+
+#[derive(Clone)]
+pub(crate) struct CartItemSearchProjection {
+    id: &'static str,
+    pool: PgPool,
+    query: StreamQuery<i64, CartStream>,
+}
+
+impl CartItemSearchProjection {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            id: PROJECTION_ID,
+            pool,
+            query: projection_query(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener<i64, CartStream> for CartItemSearchProjection {
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn query(&self) -> &StreamQuery<i64, CartStream> {
+        &self.query
+    }
+
+    async fn handle(&self, event: PersistedEvent<i64, CartStream>) -> Result<(), Self::Error> {
+        let last_event_id = event.id();
+        let Some(mut tx) =
+            ProjectionTransaction::begin_in_order(&self.pool, self.id, &event, "cart_id").await?
+        else {
+            return Ok(());
+        };
+        let event = event.into_inner();
+        match &event {
+            CartStream::CartItemAdded {
+                cart_id,
+                item_id,
+                product_id,
+                description,
+                ..
+            } => add_item(&mut tx, cart_id, item_id, product_id, description).await,
+            CartStream::CartItemRemoved { cart_id, item_id }
+            | CartStream::ItemArchivedEvent {
+                cart_id, item_id, ..
+            } => remove_item(&mut tx, cart_id, item_id).await,
+            CartStream::CartCleared { cart_id } => remove_items(&mut tx, cart_id).await,
+            CartStream::CartCreated { .. }
+            | CartStream::CartSubmitted { .. }
+            | CartStream::CartForgotten { .. } => Ok(()),
+        }
+        .inspect_err(|e| error!("CartItemSearchProjection: Failed handling event ({last_event_id})\n{event:?}\nfailed with {e}"))?;
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl RebuildableProjection<CartStream> for CartItemSearchProjection {
+    const TABLES: &'static [&'static str] = &["cart_item_search"];
+
+    fn shadow(&self, pool: PgPool) -> Self {
+        Self {
+            id: SHADOW_PROJECTION_ID,
+            pool,
+            ..self.clone()
+        }
+    }
+
+    async fn reset(&self, conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+        sqlx::query!("DELETE FROM cart_item_search")
+            .execute(&mut *conn)
+            .await
+            .context("Problem in reset() deleting cart_item_search.")?;
+        Ok(())
+    }
+}
+
+//--------------------------- SQL -------------------------------
+
+async fn add_item(
+    conn: &mut PgConnection,
+    cart_id: &CartId,
+    item_id: &ItemId,
+    product_id: &ProductId,
+    description: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO cart_item_search (cart_id, item_id, product_id, description)
+           VALUES ($1, $2, $3, $4)
+           ON CONFLICT (cart_id, item_id)
+           DO UPDATE SET
+              product_id = EXCLUDED.product_id,
+              description = EXCLUDED.description"#,
+        cart_id as &CartId,
+        item_id as &ItemId,
+        product_id as &ProductId,
+        description
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in add_item(cart_id: {cart_id}, item_id: {item_id})."))?;
+    Ok(())
+}
+
+async fn remove_item(
+    conn: &mut PgConnection,
+    cart_id: &CartId,
+    item_id: &ItemId,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM cart_item_search
+           WHERE cart_id = $1 and item_id = $2"#,
+        cart_id as &CartId,
+        item_id as &ItemId
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in remove_item(cart_id: {cart_id}, item_id: {item_id})."))?;
+    Ok(())
+}
+
+async fn remove_items(conn: &mut PgConnection, cart_id: &CartId) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM cart_item_search WHERE cart_id = $1",
+        cart_id as &CartId
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in remove_items({cart_id})."))?;
+    Ok(())
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        DomainEvent,
+        cart::CartOverviewProjection,
+        helpers::test_events::{append_events, handle_events},
+    };
+
+    /// Has both this projection and Cart Overview, which the statuses are read from, handle the
+    /// events.
+    async fn handle_all(pool: &PgPool, events: Vec<DomainEvent>) {
+        let events = append_events(pool, events).await;
+        let cart_events = events
+            .iter()
+            .filter(|event| CartStream::try_from(DomainEvent::clone(event)).is_ok())
+            .cloned()
+            .collect();
+        handle_events(&CartItemSearchProjection::new(pool.clone()), cart_events).await;
+        handle_events(&CartOverviewProjection::new(pool.clone()), events).await;
+    }
+
+    fn item_added(cart_id: CartId, item_id: ItemId, description: &str) -> DomainEvent {
+        DomainEvent::CartItemAdded {
+            cart_id,
+            description: description.to_string(),
+            image: "item.jpg".into(),
+            price: 10.into(),
+            item_id,
+            product_id: ProductId::new(),
+            fingerprint: "fingerprint".to_string(),
+        }
+    }
+
+    fn search(q: &str) -> CartItemSearchParams {
+        CartItemSearchParams {
+            q: q.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[sqlx::test]
+    async fn items_are_found_by_description(pool: PgPool) {
+        let (open, submitted, cleared) = (CartId::new(), CartId::new(), CartId::new());
+        let (mug, mugs, removed) = (ItemId::new(), ItemId::new(), ItemId::new());
+        handle_all(
+            &pool,
+            vec![
+                DomainEvent::CartCreated { cart_id: open },
+                item_added(open, mug, "Blue mug"),
+                item_added(open, removed, "Blue mug, chipped"),
+                DomainEvent::CartItemRemoved {
+                    cart_id: open,
+                    item_id: removed,
+                },
+                DomainEvent::CartCreated { cart_id: submitted },
+                item_added(submitted, mugs, "Blue mugs, two blue coffee mugs"),
+                item_added(submitted, ItemId::new(), "Blue socks"),
+                DomainEvent::CartSubmitted {
+                    cart_id: submitted,
+                    ordered_product: vec![],
+                    total_price: 20.into(),
+                },
+                DomainEvent::CartPublicationFailed { cart_id: submitted },
+                DomainEvent::CartRepublishRequested {
+                    cart_id: submitted,
+                    ordered_product: vec![],
+                    total_price: 20.into(),
+                },
+                DomainEvent::CartCreated { cart_id: cleared },
+                item_added(cleared, ItemId::new(), "Blue mug"),
+                DomainEvent::CartCleared { cart_id: cleared },
+            ],
+        )
+        .await;
+
+        let found = search_cart_items(&pool, &search("blue mug"), None)
+            .await
+            .unwrap();
+        let submitted_only = search_cart_items(
+            &pool,
+            &CartItemSearchParams {
+                status: Some(CartStatus::Submitted),
+                ..search("blue mug")
+            },
+            None,
+        )
+        .await
+        .unwrap();
+
+        let matches: Vec<_> = found
+            .items
+            .iter()
+            .map(|item| (item.cart_id, item.item_id, item.status))
+            .collect();
+        assert_eq!(
+            matches,
+            vec![
+                (submitted, mugs, CartStatus::Submitted),
+                (open, mug, CartStatus::Open),
+            ]
+        );
+        assert!(found.items[0].rank > found.items[1].rank);
+        assert_eq!(found.next_cursor, None);
+        assert_eq!(submitted_only.items.len(), 1);
+        assert_eq!(submitted_only.items[0].item_id, mugs);
+    }
+
+    #[sqlx::test]
+    async fn item_added_twice_is_found_once(pool: PgPool) {
+        let (cart_id, item_id) = (CartId::new(), ItemId::new());
+        handle_all(
+            &pool,
+            vec![
+                DomainEvent::CartCreated { cart_id },
+                item_added(cart_id, item_id, "Red mug"),
+                item_added(cart_id, item_id, "Green mug"),
+            ],
+        )
+        .await;
+
+        let red = search_cart_items(&pool, &search("red"), None)
+            .await
+            .unwrap();
+        let mugs = search_cart_items(&pool, &search("mug"), None)
+            .await
+            .unwrap();
+
+        assert!(red.items.is_empty());
+        assert_eq!(mugs.items.len(), 1);
+        assert_eq!(mugs.items[0].item_id, item_id);
+    }
+
+    #[sqlx::test]
+    async fn matches_are_paged_by_cursor(pool: PgPool) {
+        let carts: Vec<CartId> = (0..5).map(|_| CartId::new()).collect();
+        handle_all(
+            &pool,
+            carts
+                .iter()
+                .flat_map(|&cart_id| {
+                    [
+                        DomainEvent::CartCreated { cart_id },
+                        item_added(cart_id, ItemId::new(), "Blue mug"),
+                    ]
+                })
+                .collect(),
+        )
+        .await;
+        let params = CartItemSearchParams {
+            limit: Some(2),
+            ..search("mug")
+        };
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = search_cart_items(&pool, &params, cursor.as_ref())
+                .await
+                .unwrap();
+            seen.extend(page.items.iter().map(|item| item.cart_id));
+            match page.next_cursor {
+                Some(next) => cursor = Some(SearchCursor::decode(&next).unwrap()),
+                None => break,
+            }
+        }
+
+        let mut expected = carts;
+        expected.sort_by(|a, b| b.cmp(a));
+        assert_eq!(seen, expected);
+    }
+}
@@ -4,14 +4,14 @@ use std::path::PathBuf;
 
 use axum::{
     Json,
-    extract::{Path, State},
+    extract::{Path, Query, State},
 };
 use disintegrate::query;
 use rust_decimal::Decimal;
 use uuid::Uuid;
 
 use crate::{
-    domain::{CartStream, EventReadingError, EventStore, read_from_events},
+    domain::{CartStream, EventArchive, EventReadingError, EventStore, read_from_events},
     infra::ClientError,
 };
 
@@ -46,16 +46,30 @@ pub struct CartItem {
     pub fingerprint: String,
 }
 
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct CartItemsParams {
+    /// Also look for the cart among archived events.
+    #[serde(default)]
+    pub include_archive: bool,
+}
+
 pub async fn cart_items_endpoint(
     State(event_store): State<EventStore>,
+    State(event_archive): State<EventArchive>,
     Path(cart_uuid): Path<Uuid>,
+    Query(params): Query<CartItemsParams>,
 ) -> Result<Json<CartItemsReadModel>, ClientError> {
     let cart_id: CartId = cart_uuid.try_into()?;
-    match cart_items_read_model(event_store, &cart_id).await {
-        Ok(Some(read_model)) => Ok(Json(read_model)),
-        Ok(None) => Err(CartError::CartDoesNotExist(cart_id).into()),
-        Err(e) => Err(e.into()),
-    }
+    let read_model = match cart_items_read_model(event_store, &cart_id).await? {
+        Some(read_model) => Some(read_model),
+        None if params.include_archive => {
+            archived_cart_items_read_model(&event_archive, &cart_id).await?
+        }
+        None => None,
+    };
+    read_model
+        .map(Json)
+        .ok_or_else(|| CartError::CartDoesNotExist(cart_id).into())
 }
 
 //----------------------- Implementation --------------------------
@@ -68,7 +82,21 @@ pub async fn cart_items_read_model(
     read_from_events(&event_store, &query, None, apply_event).await
 }
 
-fn apply_event(
+/// Carts whose events have been archived are no longer found in the event store.
+pub async fn archived_cart_items_read_model(
+    event_archive: &EventArchive,
+    cart_id: &CartId,
+) -> Result<Option<CartItemsReadModel>, anyhow::Error> {
+    let read_model = event_archive
+        .archived_cart_events(cart_id)
+        .await?
+        .into_iter()
+        .filter_map(|event| CartStream::try_from(event.into_inner()).ok())
+        .fold(None, apply_event);
+    Ok(read_model)
+}
+
+pub(super) fn apply_event(
     read_model: Option<CartItemsReadModel>,
     event: CartStream,
 ) -> Option<CartItemsReadModel> {
@@ -127,6 +155,8 @@ fn apply_event(
             Some(read_model)
         }
         (Some(read_model), CartStream::CartSubmitted { .. }) => Some(read_model),
+        // Personal data is already redacted as events are read from the event store.
+        (Some(read_model), CartStream::CartForgotten { .. }) => Some(read_model),
         (None, _) => {
             panic!("The first event for the cart was not CartAdded! This should never happen.")
         }
@@ -142,14 +172,13 @@ fn apply_event(
 mod tests {
     use crate::domain::{
         cart::{AddItemCommand, RemoveItemCommand},
-        create_eventstore_and_decider,
+        create_in_memory_eventstore_and_decider,
         helpers::fake::{FingerPrint, Price},
     };
 
     use super::*;
 
     use fake::{Fake, Faker};
-    use sqlx::PgPool;
 
     fn cart_item_from_event(event: &CartStream) -> CartItem {
         if let CartStream::CartItemAdded {
@@ -233,11 +262,9 @@ mod tests {
         assert_eq!(read_model, expected_read_model);
     }
 
-    #[sqlx::test]
-    async fn cart_items_read_model_test(pool: PgPool) {
-        let (event_store, decider) = create_eventstore_and_decider(&pool)
-            .await
-            .expect("EventStore and Decider should be created.");
+    #[tokio::test]
+    async fn cart_items_read_model_test() {
+        let (event_store, decider) = create_in_memory_eventstore_and_decider();
 
         let cart_id = CartId::new();
 
@@ -312,8 +339,6 @@ mod tests {
             .await
             .expect("Cart Items readmodel should have been read.");
 
-        pool.close().await;
-
         assert_eq!(read_model, expected_read_model);
     }
 }
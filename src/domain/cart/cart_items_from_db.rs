@@ -8,10 +8,16 @@ use axum::{
 };
 use disintegrate::{EventListener, PersistedEvent, StreamQuery, query};
 use rust_decimal::Decimal;
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use uuid::Uuid;
 
-use crate::{domain::CartStream, infra::ClientError};
+use crate::{
+    domain::{
+        CartStream, MinEventId, ProjectionTransaction, REDACTED, RebuildableProjection,
+        wait_for_projection,
+    },
+    infra::ClientError,
+};
 
 use super::{CartError, CartId, CartItem, CartItemsReadModel, ItemId, ProductId};
 
@@ -20,8 +26,10 @@ use super::{CartError, CartId, CartItem, CartItemsReadModel, ItemId, ProductId};
 pub async fn cart_items_from_db_endpoint(
     State(pool): State<PgPool>,
     Path(cart_uuid): Path<Uuid>,
+    min_event_id: MinEventId,
 ) -> Result<Json<CartItemsReadModel>, ClientError> {
     let cart_id: CartId = cart_uuid.try_into()?;
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
     match cart_items_from_db_read_model(&pool, &cart_id).await {
         Ok(Some(read_model)) => Ok(Json(read_model)),
         Ok(None) => Err(CartError::CartDoesNotExist(cart_id).into()),
@@ -75,36 +83,19 @@ pub async fn cart_items_from_db_read_model(
     }))
 }
 
-pub async fn cart_items_from_db_read_model_reset(pool: &PgPool) -> Result<(), anyhow::Error> {
-    let mut tx = pool.begin().await?;
-
-    sqlx::query!("DELETE FROM cart;")
-        .execute(&mut *tx)
-        .await
-        .context("Problem in cart_items_from_db_read_model_reset.")?;
-
-    sqlx::query!("DELETE FROM cart_items;")
-        .execute(&mut *tx)
-        .await
-        .context("Problem in cart_items_from_db_read_model_reset.")?;
+//------------------------- Projection --------------------------
 
-    sqlx::query("update event_listener set last_processed_event_id = 0 where id = $1;")
-        .bind(PROJECTION_ID)
-        .execute(&mut *tx)
-        .await
-        .context("Problem in cart_items_from_db_read_model_reset.")?;
+pub(super) const PROJECTION_ID: &str = "cart_items_from_db";
+/// Listener id of the shadow copy built by a blue/green rebuild.
+const SHADOW_PROJECTION_ID: &str = "cart_items_from_db_shadow";
 
-    tx.commit()
-        .await
-        .context("Problem in cart_items_from_db_read_model_reset.")
+fn projection_query() -> StreamQuery<i64, CartStream> {
+    query!(CartStream)
 }
 
-//------------------------- Projection --------------------------
-
-const PROJECTION_ID: &str = "cart_items_from_db";
-
 #[derive(Clone)]
 pub struct CartItemsReadModelProjection {
+    id: &'static str,
     pool: PgPool,
     query: StreamQuery<i64, CartStream>,
 }
@@ -112,8 +103,9 @@ pub struct CartItemsReadModelProjection {
 impl CartItemsReadModelProjection {
     pub fn new(pool: PgPool) -> Self {
         Self {
+            id: PROJECTION_ID,
             pool,
-            query: query!(CartStream),
+            query: projection_query(),
         }
     }
 }
@@ -123,7 +115,7 @@ impl EventListener<i64, CartStream> for CartItemsReadModelProjection {
     type Error = anyhow::Error;
 
     fn id(&self) -> &'static str {
-        PROJECTION_ID
+        self.id
     }
 
     fn query(&self) -> &StreamQuery<i64, CartStream> {
@@ -132,12 +124,14 @@ impl EventListener<i64, CartStream> for CartItemsReadModelProjection {
 
     async fn handle(&self, event: PersistedEvent<i64, CartStream>) -> Result<(), Self::Error> {
         let last_event_id = event.id();
-        let event = event.into_inner();
-        match event {
-            CartStream::CartCleared { cart_id } => {
-                delete_by_cart_id(&self.pool, &cart_id, last_event_id).await
-            }
-            CartStream::CartCreated { cart_id } => add_cart(&self.pool, &cart_id).await,
+        let Some(mut tx) =
+            ProjectionTransaction::begin_in_order(&self.pool, self.id, &event, "cart_id").await?
+        else {
+            return Ok(());
+        };
+        match event.into_inner() {
+            CartStream::CartCleared { cart_id } => delete_by_cart_id(&mut tx, &cart_id).await,
+            CartStream::CartCreated { cart_id } => add_cart(&mut tx, &cart_id).await,
             CartStream::CartItemAdded {
                 cart_id,
                 description,
@@ -148,7 +142,7 @@ impl EventListener<i64, CartStream> for CartItemsReadModelProjection {
                 fingerprint,
             } => {
                 save(
-                    &self.pool,
+                    &mut tx,
                     &cart_id,
                     &description,
                     image.to_string_lossy().as_ref(),
@@ -161,67 +155,101 @@ impl EventListener<i64, CartStream> for CartItemsReadModelProjection {
                 .await
             }
             CartStream::CartItemRemoved { cart_id, item_id } => {
-                delete_by_item_id(&self.pool, &cart_id, &item_id, last_event_id).await
+                delete_by_item_id(&mut tx, &cart_id, &item_id).await
             }
             CartStream::CartSubmitted { .. } => Ok(()),
+            CartStream::CartForgotten { cart_id } => redact_by_cart_id(&mut tx, &cart_id).await,
             CartStream::ItemArchivedEvent {
                 cart_id, item_id, ..
-            } => delete_by_item_id(&self.pool, &cart_id, &item_id, last_event_id).await,
+            } => delete_by_item_id(&mut tx, &cart_id, &item_id).await,
+        }?;
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl RebuildableProjection<CartStream> for CartItemsReadModelProjection {
+    const TABLES: &'static [&'static str] = &["cart", "cart_items"];
+
+    fn shadow(&self, pool: PgPool) -> Self {
+        Self {
+            id: SHADOW_PROJECTION_ID,
+            pool,
+            ..self.clone()
         }
     }
+
+    async fn reset(&self, conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+        sqlx::query!("DELETE FROM cart_items")
+            .execute(&mut *conn)
+            .await
+            .context("Problem in reset() deleting cart_items.")?;
+        sqlx::query!("DELETE FROM cart")
+            .execute(&mut *conn)
+            .await
+            .context("Problem in reset() deleting cart.")?;
+        Ok(())
+    }
 }
 
 //--------------------------- SQL -------------------------------
 
-async fn add_cart(pool: &PgPool, cart_id: &CartId) -> Result<(), anyhow::Error> {
+async fn add_cart(conn: &mut PgConnection, cart_id: &CartId) -> Result<(), anyhow::Error> {
     sqlx::query!("INSERT INTO cart (cart_id) VALUES ($1)", cart_id as &CartId)
-        .execute(pool)
+        .execute(conn)
         .await
         .with_context(|| format!("Problem in add_cart({cart_id})"))?;
     Ok(())
 }
 
-async fn delete_by_cart_id(
-    pool: &PgPool,
-    cart_id: &CartId,
-    last_event_id: i64,
-) -> Result<(), anyhow::Error> {
+async fn delete_by_cart_id(conn: &mut PgConnection, cart_id: &CartId) -> Result<(), anyhow::Error> {
     sqlx::query!(
         r#"DELETE FROM cart_items
-           WHERE cart_id = $1 and last_event_id < $2"#,
+           WHERE cart_id = $1"#,
+        cart_id as &CartId
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in delete_by_cart_id(cart_id: {cart_id})."))?;
+    Ok(())
+}
+
+async fn redact_by_cart_id(conn: &mut PgConnection, cart_id: &CartId) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE cart_items
+           SET fingerprint = $2
+           WHERE cart_id = $1"#,
         cart_id as &CartId,
-        last_event_id
+        REDACTED
     )
-    .execute(pool)
+    .execute(conn)
     .await
-    .with_context(|| {
-        format!("Problem in delete_by_cart_id(cart_id: {cart_id}, last_event_id: {last_event_id}).")
-    })?;
+    .with_context(|| format!("Problem in redact_by_cart_id(cart_id: {cart_id})."))?;
     Ok(())
 }
 
 async fn delete_by_item_id(
-    pool: &PgPool,
+    conn: &mut PgConnection,
     cart_id: &CartId,
     item_id: &ItemId,
-    last_event_id: i64,
 ) -> Result<(), anyhow::Error> {
     sqlx::query!(
         r#"DELETE FROM cart_items
-           WHERE cart_id = $1 and item_id = $2 and last_event_id < $3"#,
+           WHERE cart_id = $1 and item_id = $2"#,
         cart_id as &CartId,
-        item_id as &ItemId,
-        last_event_id
+        item_id as &ItemId
     )
-    .execute(pool)
+    .execute(conn)
     .await
-    .with_context(|| format!("Problem in delete_by_item_id(cart_id: {cart_id}, item_id: {item_id}, last_event_id: {last_event_id})."))?;
+    .with_context(|| {
+        format!("Problem in delete_by_item_id(cart_id: {cart_id}, item_id: {item_id}).")
+    })?;
     Ok(())
 }
 
 #[allow(clippy::too_many_arguments)]
 async fn save(
-    pool: &PgPool,
+    conn: &mut PgConnection,
     cart_id: &CartId,
     description: &str,
     image: &str,
@@ -241,8 +269,7 @@ async fn save(
               price = $4,
               product_id = $6,
               fingerprint = $7,
-              last_event_id = $8
-              WHERE cart_items.last_event_id < $8"#,
+              last_event_id = $8"#,
         cart_id as &CartId,
         description,
         image,
@@ -252,7 +279,7 @@ async fn save(
         fingerprint,
         last_event_id
     )
-    .execute(pool)
+    .execute(conn)
     .await
     .with_context(|| format!("Problem in save(cart_id: {cart_id}, item_id: {item_id}, product_id: {product_id}, last_event_id: {last_event_id})."))?;
     Ok(())
@@ -0,0 +1,645 @@
+//! Cart Overview read model. One row per cart with its status and totals, for listing carts.
+//!
+//! Carts are listed a page at a time. A page's `next_cursor`, passed as the `after` of the next
+//! request, holds the sort value and id of the page's last cart, so pages stay stable while carts
+//! are added or change.
+
+use anyhow::{Context, anyhow};
+use async_trait::async_trait;
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use disintegrate::{EventListener, PersistedEvent, StreamQuery, query};
+use jiff_sqlx::ToSqlx;
+use rust_decimal::Decimal;
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder, Row};
+use tracing::error;
+
+use crate::{
+    domain::{
+        CartStream, DomainEvent, MinEventId, ProjectionTransaction, PublishedStream,
+        RebuildableProjection, RepublishStream, wait_for_projection,
+    },
+    infra::ClientError,
+};
+
+use super::{CartId, ItemId};
+
+/// Carts returned per page unless a limit is given.
+const DEFAULT_LIMIT: i64 = 50;
+
+/// Most carts returned per page.
+const MAX_LIMIT: i64 = 500;
+
+//------------------------- Web API ----------------------------
+
+/// e.g. `GET /carts?status=submitted&created_after=2026-10-01T00:00:00Z&sort=total_price&order=desc`
+pub async fn cart_overview_endpoint(
+    State(pool): State<PgPool>,
+    Query(filter): Query<CartOverviewFilter>,
+    min_event_id: MinEventId,
+) -> Result<Json<CartOverviewPage>, ClientError> {
+    let cursor = filter
+        .after
+        .as_deref()
+        .map(|after| Cursor::decode(after, filter.sort))
+        .transpose()?;
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
+    Ok(Json(
+        find_cart_overviews(&pool, &filter, cursor.as_ref()).await?,
+    ))
+}
+
+//----------------------- Read Model API ------------------------
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, strum_macros::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum CartStatus {
+    Open,
+    /// Submitted, or republished, and awaiting publication.
+    Submitted,
+    Published,
+    PublicationFailed,
+    /// Cleared, and no items added since.
+    ClearedEmpty,
+}
+
+impl CartStatus {
+    pub const ALL: [CartStatus; 5] = [
+        CartStatus::Open,
+        CartStatus::Submitted,
+        CartStatus::Published,
+        CartStatus::PublicationFailed,
+        CartStatus::ClearedEmpty,
+    ];
+
+    pub(super) fn parse(status: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|known| known.to_string() == status)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CartOverview {
+    pub cart_id: CartId,
+    pub status: CartStatus,
+    pub item_count: i32,
+    pub total_price: Decimal,
+    pub created_at: jiff::Timestamp,
+    pub updated_at: jiff::Timestamp,
+    pub last_event_id: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CartOverviewPage {
+    pub carts: Vec<CartOverview>,
+    /// Pass as `after` to read the next page. Missing on the last page.
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, strum_macros::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum CartOverviewSort {
+    CreatedAt,
+    #[default]
+    UpdatedAt,
+    TotalPrice,
+}
+
+impl CartOverviewSort {
+    /// The Postgres type of the sort column, for casting the cursor's sort value back.
+    fn sql_type(self) -> &'static str {
+        match self {
+            CartOverviewSort::CreatedAt | CartOverviewSort::UpdatedAt => "TIMESTAMPTZ",
+            CartOverviewSort::TotalPrice => "NUMERIC",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Filters for `find_cart_overviews`. Carts are returned most recently updated first by default.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct CartOverviewFilter {
+    pub status: Option<CartStatus>,
+    pub created_after: Option<jiff::Timestamp>,
+    pub created_before: Option<jiff::Timestamp>,
+    #[serde(default)]
+    pub sort: CartOverviewSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub limit: Option<i64>,
+    /// The `next_cursor` of the previous page.
+    pub after: Option<String>,
+}
+
+/// Where the previous page ended: its last cart's sort value, as Postgres renders it, and id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    sort_value: String,
+    cart_id: CartId,
+}
+
+impl Cursor {
+    fn encode(&self, sort: CartOverviewSort) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{sort}|{}|{}", self.sort_value, self.cart_id))
+    }
+
+    pub fn decode(cursor: &str, sort: CartOverviewSort) -> Result<Self, ClientError> {
+        let invalid = || ClientError::Payload(format!("Invalid cursor {cursor}."));
+        let decoded = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(invalid)?;
+        let mut parts = decoded.splitn(3, '|');
+        let (Some(cursor_sort), Some(sort_value), Some(cart_id)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        if cursor_sort != sort.to_string() {
+            return Err(ClientError::Payload(format!(
+                "Cursor {cursor} was issued for sort {cursor_sort}, not {sort}."
+            )));
+        }
+        Ok(Cursor {
+            sort_value: sort_value.to_string(),
+            cart_id: cart_id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Reads one page of carts, continuing after `cursor` if given.
+pub async fn find_cart_overviews(
+    pool: &PgPool,
+    filter: &CartOverviewFilter,
+    cursor: Option<&Cursor>,
+) -> Result<CartOverviewPage, anyhow::Error> {
+    let limit = filter.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let column = filter.sort.to_string();
+    let (direction, comparison) = match filter.order {
+        SortOrder::Asc => ("ASC", ">"),
+        SortOrder::Desc => ("DESC", "<"),
+    };
+
+    let mut query: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+        r#"SELECT cart_id, status, item_count, total_price, created_at, updated_at, last_event_id,
+                  {column}::TEXT AS sort_value
+           FROM cart_overview
+           WHERE TRUE"#
+    ));
+    if let Some(status) = filter.status {
+        query.push(" AND status = ").push_bind(status.to_string());
+    }
+    if let Some(created_after) = filter.created_after {
+        query
+            .push(" AND created_at >= ")
+            .push_bind(created_after.to_sqlx());
+    }
+    if let Some(created_before) = filter.created_before {
+        query
+            .push(" AND created_at < ")
+            .push_bind(created_before.to_sqlx());
+    }
+    if let Some(cursor) = cursor {
+        query
+            .push(format!(" AND ({column}, cart_id) {comparison} ("))
+            .push_bind(cursor.sort_value.clone())
+            .push(format!("::{}, ", filter.sort.sql_type()))
+            .push_bind(cursor.cart_id)
+            .push(")");
+    }
+    query
+        .push(format!(
+            " ORDER BY {column} {direction}, cart_id {direction} LIMIT "
+        ))
+        .push_bind(limit + 1);
+
+    let rows = query
+        .build()
+        .fetch_all(pool)
+        .await
+        .with_context(|| format!("Problem in find_cart_overviews(filter: {filter:?})."))?;
+
+    let mut carts = Vec::with_capacity(rows.len());
+    let mut next_cursor = None;
+    for row in rows.iter().take(limit as usize) {
+        let status: String = row.get("status");
+        let cart = CartOverview {
+            cart_id: row.get("cart_id"),
+            status: CartStatus::parse(&status).ok_or_else(|| {
+                anyhow!("Problem in find_cart_overviews(). Unknown status {status}.")
+            })?,
+            item_count: row.get("item_count"),
+            total_price: row.get("total_price"),
+            created_at: row.get::<jiff_sqlx::Timestamp, _>("created_at").to_jiff(),
+            updated_at: row.get::<jiff_sqlx::Timestamp, _>("updated_at").to_jiff(),
+            last_event_id: row.get("last_event_id"),
+        };
+        if rows.len() as i64 > limit {
+            next_cursor = Some(
+                Cursor {
+                    sort_value: row.get("sort_value"),
+                    cart_id: cart.cart_id,
+                }
+                .encode(filter.sort),
+            );
+        }
+        carts.push(cart);
+    }
+
+    Ok(CartOverviewPage { carts, next_cursor })
+}
+
+//------------------------- Projection --------------------------
+
+pub(super) const PROJECTION_ID: &str = "cart_overview";
+/// Listener id of the shadow copy built by a blue/green rebuild.
+const SHADOW_PROJECTION_ID: &str = "cart_overview_shadow";
+
+pub(super) fn projection_query() -> StreamQuery<i64, DomainEvent> {
+    query!(CartStream)
+        .union::<DomainEvent, _>(&query!(PublishedStream))
+        .union(&query!(RepublishStream))
+}
+
+#[derive(Clone)]
+pub(crate) struct CartOverviewProjection {
+    id: &'static str,
+    pool: PgPool,
+    query: StreamQuery<i64, DomainEvent>,
+}
+
+impl CartOverviewProjection {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            id: PROJECTION_ID,
+            pool,
+            query: projection_query(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener<i64, DomainEvent> for CartOverviewProjection {
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn query(&self) -> &StreamQuery<i64, DomainEvent> {
+        &self.query
+    }
+
+    async fn handle(&self, event: PersistedEvent<i64, DomainEvent>) -> Result<(), Self::Error> {
+        let last_event_id = event.id();
+        let Some(mut tx) =
+            ProjectionTransaction::begin_in_order(&self.pool, self.id, &event, "cart_id").await?
+        else {
+            return Ok(());
+        };
+        let event = event.into_inner();
+        let at = tx.appended_at().await?;
+        let change = CartChange { at, last_event_id };
+        match &event {
+            DomainEvent::CartCreated { cart_id } => add_cart(&mut tx, cart_id, &change).await,
+            DomainEvent::CartItemAdded {
+                cart_id,
+                item_id,
+                price,
+                ..
+            } => {
+                add_item(&mut tx, cart_id, item_id, *price).await?;
+                update_cart(&mut tx, cart_id, Some(CartStatus::Open), &change).await
+            }
+            DomainEvent::CartItemRemoved { cart_id, item_id }
+            | DomainEvent::ItemArchivedEvent {
+                cart_id, item_id, ..
+            } => {
+                remove_item(&mut tx, cart_id, item_id).await?;
+                update_cart(&mut tx, cart_id, None, &change).await
+            }
+            DomainEvent::CartCleared { cart_id } => {
+                remove_items(&mut tx, cart_id).await?;
+                update_cart(&mut tx, cart_id, Some(CartStatus::ClearedEmpty), &change).await
+            }
+            DomainEvent::CartSubmitted { cart_id, .. }
+            | DomainEvent::CartRepublishRequested { cart_id, .. } => {
+                update_cart(&mut tx, cart_id, Some(CartStatus::Submitted), &change).await
+            }
+            DomainEvent::CartPublished { cart_id } => {
+                update_cart(&mut tx, cart_id, Some(CartStatus::Published), &change).await
+            }
+            DomainEvent::CartPublicationFailed { cart_id } => {
+                update_cart(&mut tx, cart_id, Some(CartStatus::PublicationFailed), &change).await
+            }
+            DomainEvent::CartForgotten { .. } => Ok(()),
+            unexpected => Err(anyhow!(
+                "CartOverview projection received unsupported event type {unexpected:?} for event {last_event_id}."
+            )),
+        }
+        .inspect_err(|e| error!("CartOverviewProjection: Failed handling event ({last_event_id})\n{event:?}\nfailed with {e}"))?;
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl RebuildableProjection<DomainEvent> for CartOverviewProjection {
+    const TABLES: &'static [&'static str] = &["cart_overview", "cart_overview_items"];
+
+    fn shadow(&self, pool: PgPool) -> Self {
+        Self {
+            id: SHADOW_PROJECTION_ID,
+            pool,
+            ..self.clone()
+        }
+    }
+
+    async fn reset(&self, conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+        sqlx::query!("DELETE FROM cart_overview_items")
+            .execute(&mut *conn)
+            .await
+            .context("Problem in reset() deleting cart_overview_items.")?;
+        sqlx::query!("DELETE FROM cart_overview")
+            .execute(&mut *conn)
+            .await
+            .context("Problem in reset() deleting cart_overview.")?;
+        Ok(())
+    }
+}
+
+/// When, and by which event, a cart changed.
+struct CartChange {
+    at: jiff_sqlx::Timestamp,
+    last_event_id: i64,
+}
+
+//--------------------------- SQL -------------------------------
+
+async fn add_cart(
+    conn: &mut PgConnection,
+    cart_id: &CartId,
+    change: &CartChange,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO cart_overview
+              (cart_id, status, item_count, total_price, created_at, updated_at, last_event_id)
+           VALUES ($1, $2, 0, 0, $3, $3, $4)"#,
+        cart_id as &CartId,
+        CartStatus::Open.to_string(),
+        &change.at as &jiff_sqlx::Timestamp,
+        change.last_event_id
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in add_cart({cart_id})."))?;
+    Ok(())
+}
+
+async fn add_item(
+    conn: &mut PgConnection,
+    cart_id: &CartId,
+    item_id: &ItemId,
+    price: Decimal,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO cart_overview_items (cart_id, item_id, price)
+           VALUES ($1, $2, $3)
+           ON CONFLICT (cart_id, item_id)
+           DO UPDATE SET price = EXCLUDED.price"#,
+        cart_id as &CartId,
+        item_id as &ItemId,
+        price
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in add_item(cart_id: {cart_id}, item_id: {item_id})."))?;
+    Ok(())
+}
+
+async fn remove_item(
+    conn: &mut PgConnection,
+    cart_id: &CartId,
+    item_id: &ItemId,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM cart_overview_items
+           WHERE cart_id = $1 and item_id = $2"#,
+        cart_id as &CartId,
+        item_id as &ItemId
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in remove_item(cart_id: {cart_id}, item_id: {item_id})."))?;
+    Ok(())
+}
+
+async fn remove_items(conn: &mut PgConnection, cart_id: &CartId) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM cart_overview_items WHERE cart_id = $1",
+        cart_id as &CartId
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in remove_items({cart_id})."))?;
+    Ok(())
+}
+
+/// Recounts the cart's items and total, and sets its status if given.
+async fn update_cart(
+    conn: &mut PgConnection,
+    cart_id: &CartId,
+    status: Option<CartStatus>,
+    change: &CartChange,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE cart_overview SET
+              status = COALESCE($2, status),
+              item_count = (SELECT count(*)::INT FROM cart_overview_items WHERE cart_id = $1),
+              total_price = (SELECT COALESCE(sum(price), 0) FROM cart_overview_items WHERE cart_id = $1),
+              updated_at = $3,
+              last_event_id = $4
+           WHERE cart_id = $1"#,
+        cart_id as &CartId,
+        status.map(|status| status.to_string()),
+        &change.at as &jiff_sqlx::Timestamp,
+        change.last_event_id
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in update_cart(cart_id: {cart_id}, status: {status:?})."))?;
+    Ok(())
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{cart::ProductId, helpers::test_events::append_and_handle};
+
+    async fn handle_all(pool: &PgPool, events: Vec<DomainEvent>) {
+        let projection = CartOverviewProjection::new(pool.clone());
+        append_and_handle(pool, &projection, events).await;
+    }
+
+    fn item_added(cart_id: CartId, item_id: ItemId, price: i64) -> DomainEvent {
+        DomainEvent::CartItemAdded {
+            cart_id,
+            description: "Socks".to_string(),
+            image: "socks.jpg".into(),
+            price: price.into(),
+            item_id,
+            product_id: ProductId::new(),
+            fingerprint: "fingerprint".to_string(),
+        }
+    }
+
+    #[sqlx::test]
+    async fn carts_are_tracked_through_their_lifecycle(pool: PgPool) {
+        let (submitted, republished) = (CartId::new(), CartId::new());
+        let (cleared, open) = (CartId::new(), CartId::new());
+        let removed = ItemId::new();
+        handle_all(
+            &pool,
+            vec![
+                DomainEvent::CartCreated { cart_id: submitted },
+                item_added(submitted, ItemId::new(), 10),
+                item_added(submitted, removed, 5),
+                DomainEvent::CartItemRemoved {
+                    cart_id: submitted,
+                    item_id: removed,
+                },
+                DomainEvent::CartSubmitted {
+                    cart_id: submitted,
+                    ordered_product: vec![],
+                    total_price: 10.into(),
+                },
+                DomainEvent::CartPublicationFailed { cart_id: submitted },
+                DomainEvent::CartCreated {
+                    cart_id: republished,
+                },
+                DomainEvent::CartSubmitted {
+                    cart_id: republished,
+                    ordered_product: vec![],
+                    total_price: 0.into(),
+                },
+                DomainEvent::CartPublicationFailed {
+                    cart_id: republished,
+                },
+                DomainEvent::CartRepublishRequested {
+                    cart_id: republished,
+                    ordered_product: vec![],
+                    total_price: 0.into(),
+                },
+                DomainEvent::CartCreated { cart_id: cleared },
+                item_added(cleared, ItemId::new(), 3),
+                DomainEvent::CartCleared { cart_id: cleared },
+                DomainEvent::CartCreated { cart_id: open },
+                item_added(open, ItemId::new(), 7),
+            ],
+        )
+        .await;
+
+        let page = find_cart_overviews(&pool, &CartOverviewFilter::default(), None)
+            .await
+            .unwrap();
+
+        let summary: Vec<_> = page
+            .carts
+            .iter()
+            .map(|cart| (cart.cart_id, cart.status, cart.item_count, cart.total_price))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (open, CartStatus::Open, 1, 7.into()),
+                (cleared, CartStatus::ClearedEmpty, 0, 0.into()),
+                (republished, CartStatus::Submitted, 0, 0.into()),
+                (submitted, CartStatus::PublicationFailed, 1, 10.into()),
+            ]
+        );
+        assert_eq!(page.carts[3].last_event_id, 6);
+        assert!(page.carts[3].created_at < page.carts[3].updated_at);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[sqlx::test]
+    async fn item_added_twice_is_counted_once(pool: PgPool) {
+        let (cart_id, item_id) = (CartId::new(), ItemId::new());
+        handle_all(
+            &pool,
+            vec![
+                DomainEvent::CartCreated { cart_id },
+                item_added(cart_id, item_id, 4),
+                item_added(cart_id, item_id, 6),
+            ],
+        )
+        .await;
+
+        let page = find_cart_overviews(&pool, &CartOverviewFilter::default(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(page.carts.len(), 1);
+        assert_eq!(page.carts[0].item_count, 1);
+        assert_eq!(page.carts[0].total_price, 6.into());
+        assert_eq!(page.carts[0].last_event_id, 3);
+    }
+
+    #[sqlx::test]
+    async fn carts_are_paged_by_cursor(pool: PgPool) {
+        let carts: Vec<CartId> = (0..5).map(|_| CartId::new()).collect();
+        handle_all(
+            &pool,
+            carts
+                .iter()
+                .enumerate()
+                .flat_map(|(i, &cart_id)| {
+                    [
+                        DomainEvent::CartCreated { cart_id },
+                        item_added(cart_id, ItemId::new(), 10 - i as i64),
+                    ]
+                })
+                .collect(),
+        )
+        .await;
+        let filter = CartOverviewFilter {
+            sort: CartOverviewSort::TotalPrice,
+            order: SortOrder::Asc,
+            limit: Some(2),
+            ..Default::default()
+        };
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = find_cart_overviews(&pool, &filter, cursor.as_ref())
+                .await
+                .unwrap();
+            seen.extend(page.carts.iter().map(|cart| cart.cart_id));
+            match page.next_cursor {
+                Some(next) => cursor = Some(Cursor::decode(&next, filter.sort).unwrap()),
+                None => break,
+            }
+        }
+
+        assert_eq!(seen, carts.into_iter().rev().collect::<Vec<_>>());
+        assert!(Cursor::decode("nonsense", filter.sort).is_err());
+    }
+}
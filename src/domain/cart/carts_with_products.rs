@@ -7,24 +7,29 @@ use axum::{
     extract::{Path, State},
 };
 use disintegrate::{EventListener, PersistedEvent, StreamQuery, query};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use tracing::error;
 use uuid::Uuid;
 
 use crate::{
-    domain::{CartStream, DecisionMaker, DomainEvent, PricingStream},
+    domain::{
+        CartStream, MinEventId, ProjectionTransaction, RebuildableProjection, has_caught_up,
+        wait_for_projection,
+    },
     infra::ClientError,
 };
 
-use super::{CartId, ItemId, ProductId, archive_item::archive_product_processor};
+use super::{CartId, ItemId, ProductId};
 
 //------------------------- Web API ----------------------------
 
 pub async fn carts_with_products_endpoint(
     State(pool): State<PgPool>,
     Path(product_uuid): Path<Uuid>,
+    min_event_id: MinEventId,
 ) -> Result<Json<Vec<CartsWithProductsReadModel>>, ClientError> {
     let product_id: ProductId = product_uuid.try_into()?;
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
     match find_by_product_id(&pool, &product_id).await {
         Ok(read_model) => Ok(Json(read_model)),
         Err(e) => Err(e.into()),
@@ -59,111 +64,160 @@ pub async fn find_by_product_id(
     .with_context(|| format!("Problem in find_by_product_id({product_id})"))
 }
 
+/// The items with the product that were added before the given event and are still in their
+/// carts, or `None` if the projection has not caught up with the event yet. Items added after the
+/// event are left out even once the projection has handled them.
+pub(super) async fn find_added_before(
+    pool: &PgPool,
+    product_id: &ProductId,
+    event_id: i64,
+) -> Result<Option<Vec<CartsWithProductsReadModel>>, anyhow::Error> {
+    if !has_caught_up(pool, PROJECTION_ID, &projection_query(), event_id).await? {
+        return Ok(None);
+    }
+    sqlx::query_as!(
+        CartsWithProductsReadModel,
+        r#"SELECT
+           cart_id as "cart_id: _",
+           item_id as "item_id: _",
+           product_id as "product_id: _"
+           from carts_with_products
+           where product_id = $1 and last_event_id < $2;"#,
+        product_id as &ProductId,
+        event_id
+    )
+    .fetch_all(pool)
+    .await
+    .map(Some)
+    .with_context(|| format!("Problem in find_added_before({product_id}, {event_id})"))
+}
+
 //------------------------- Projection --------------------------
 
+pub(super) const PROJECTION_ID: &str = "carts_with_products";
+/// Listener id of the shadow copy built by a blue/green rebuild.
+const SHADOW_PROJECTION_ID: &str = "carts_with_products_shadow";
+
+fn projection_query() -> StreamQuery<i64, CartStream> {
+    query!(CartStream)
+}
+
 #[derive(Clone)]
 pub(crate) struct CartsWithProductsReadModelProjection {
+    id: &'static str,
     pool: PgPool,
-    decider: DecisionMaker,
-    query: StreamQuery<i64, DomainEvent>,
+    query: StreamQuery<i64, CartStream>,
 }
 
 impl CartsWithProductsReadModelProjection {
-    pub fn new(pool: PgPool, decider: DecisionMaker) -> Self {
+    pub fn new(pool: PgPool) -> Self {
         Self {
+            id: PROJECTION_ID,
             pool,
-            decider,
-            query: query!(CartStream).union(&query!(PricingStream)),
+            query: projection_query(),
         }
     }
 }
 
 #[async_trait]
-impl EventListener<i64, DomainEvent> for CartsWithProductsReadModelProjection {
+impl EventListener<i64, CartStream> for CartsWithProductsReadModelProjection {
     type Error = anyhow::Error;
 
     fn id(&self) -> &'static str {
-        "carts_with_products"
+        self.id
     }
 
-    fn query(&self) -> &StreamQuery<i64, DomainEvent> {
+    fn query(&self) -> &StreamQuery<i64, CartStream> {
         &self.query
     }
 
-    async fn handle(&self, event: PersistedEvent<i64, DomainEvent>) -> Result<(), Self::Error> {
+    async fn handle(&self, event: PersistedEvent<i64, CartStream>) -> Result<(), Self::Error> {
         let last_event_id = event.id();
+        let Some(mut tx) =
+            ProjectionTransaction::begin_in_order(&self.pool, self.id, &event, "cart_id").await?
+        else {
+            return Ok(());
+        };
         let event = event.into_inner();
-        match event {
-            DomainEvent::CartCreated { .. } => Ok(()),
-            DomainEvent::CartItemAdded {
+        match &event {
+            CartStream::CartCreated { .. } => Ok(()),
+            CartStream::CartForgotten { .. } => Ok(()),
+            CartStream::CartItemAdded {
                 cart_id,
                 item_id,
                 product_id,
                 ..
-            } => save(&self.pool, &cart_id, &item_id, &product_id, last_event_id).await,
-            DomainEvent::CartItemRemoved { cart_id, item_id } =>
-                delete_by_item_id(&self.pool, &cart_id, &item_id, last_event_id).await,
-            DomainEvent::CartCleared { cart_id } =>
-                delete_by_cart_id(&self.pool, &cart_id, last_event_id).await,
-            DomainEvent::ItemArchivedEvent {
+            } => save(&mut tx, cart_id, item_id, product_id, last_event_id).await,
+            CartStream::CartItemRemoved { cart_id, item_id } =>
+                delete_by_item_id(&mut tx, cart_id, item_id).await,
+            CartStream::CartCleared { cart_id } => delete_by_cart_id(&mut tx, cart_id).await,
+            CartStream::ItemArchivedEvent {
                 cart_id, item_id, ..
-            } => delete_by_item_id(&self.pool, &cart_id, &item_id, last_event_id).await,
-            DomainEvent::PriceChanged { product_id, .. } => {
-                archive_product_processor(&self.pool, &self.decider, product_id, last_event_id).await;
-                Ok(())
-            }
-            DomainEvent::CartSubmitted { cart_id, .. } =>
-                delete_by_cart_id(&self.pool, &cart_id, last_event_id).await,
-            unexpected => {
-                panic!("CartsWithProducts projection received unsupported event type {unexpected:?} for event {last_event_id}.")
-            }
+            } => delete_by_item_id(&mut tx, cart_id, item_id).await,
+            CartStream::CartSubmitted { cart_id, .. } => delete_by_cart_id(&mut tx, cart_id).await,
         }
-        .inspect_err(|e| error!("CartsWithProductsReadModelProjection: Failed handling event ({last_event_id})\n{event:?}\nfailed with {e}"))
+        .inspect_err(|e| error!("CartsWithProductsReadModelProjection: Failed handling event ({last_event_id})\n{event:?}\nfailed with {e}"))?;
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl RebuildableProjection<CartStream> for CartsWithProductsReadModelProjection {
+    const TABLES: &'static [&'static str] = &["carts_with_products"];
+
+    fn shadow(&self, pool: PgPool) -> Self {
+        Self {
+            id: SHADOW_PROJECTION_ID,
+            pool,
+            ..self.clone()
+        }
+    }
+
+    async fn reset(&self, conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+        sqlx::query!("DELETE FROM carts_with_products")
+            .execute(conn)
+            .await
+            .context("Problem in reset() deleting carts_with_products.")?;
+        Ok(())
     }
 }
 
 //--------------------------- SQL -------------------------------
 
-async fn delete_by_cart_id(
-    pool: &PgPool,
-    cart_id: &CartId,
-    last_event_id: i64,
-) -> Result<(), anyhow::Error> {
+async fn delete_by_cart_id(conn: &mut PgConnection, cart_id: &CartId) -> Result<(), anyhow::Error> {
     sqlx::query!(
         r#"DELETE FROM carts_with_products
-           WHERE cart_id = $1 and last_event_id < $2"#,
-        cart_id as &CartId,
-        last_event_id
+           WHERE cart_id = $1"#,
+        cart_id as &CartId
     )
-    .execute(pool)
+    .execute(conn)
     .await
-    .with_context(|| {
-        format!("Problem in delete_by_cart_id(cart_id: {cart_id}, last_event_id: {last_event_id}).")
-    })?;
+    .with_context(|| format!("Problem in delete_by_cart_id(cart_id: {cart_id})."))?;
     Ok(())
 }
 
 async fn delete_by_item_id(
-    pool: &PgPool,
+    conn: &mut PgConnection,
     cart_id: &CartId,
     item_id: &ItemId,
-    last_event_id: i64,
 ) -> Result<(), anyhow::Error> {
     sqlx::query!(
         r#"DELETE FROM carts_with_products
-           WHERE cart_id = $1 and item_id = $2 and last_event_id < $3"#,
+           WHERE cart_id = $1 and item_id = $2"#,
         cart_id as &CartId,
-        item_id as &ItemId,
-        last_event_id
+        item_id as &ItemId
     )
-    .execute(pool)
+    .execute(conn)
     .await
-    .with_context(|| format!("Problem in delete_by_item_id(cart_id: {cart_id}, item_id: {item_id}, last_event_id: {last_event_id})."))?;
+    .with_context(|| {
+        format!("Problem in delete_by_item_id(cart_id: {cart_id}, item_id: {item_id}).")
+    })?;
     Ok(())
 }
 
+/// Records the item with the event that added it, which `find_added_before` relies on.
 async fn save(
-    pool: &PgPool,
+    conn: &mut PgConnection,
     cart_id: &CartId,
     item_id: &ItemId,
     product_id: &ProductId,
@@ -181,7 +235,7 @@ async fn save(
         product_id as &ProductId,
         last_event_id
     )
-    .execute(pool)
+    .execute(conn)
     .await
     .with_context(|| format!("Problem in save(cart_id: {cart_id}, item_id: {item_id}, product_id: {product_id}, last_event_id: {last_event_id})."))?;
     Ok(())
@@ -192,8 +246,10 @@ async fn save(
 #[cfg(test)]
 mod tests {
     use crate::domain::{
+        DomainEvent,
         cart::{AddItemCommand, RemoveItemCommand},
         create_eventstore_and_decider,
+        helpers::test_events::{append_and_handle, append_events, set_checkpoint},
     };
 
     use super::*;
@@ -204,7 +260,7 @@ mod tests {
         let (_event_store, decider) = create_eventstore_and_decider(&pool)
             .await
             .expect("Eventstore and DecisionMaker should be created.");
-        let projection = CartsWithProductsReadModelProjection::new(pool.clone(), decider.clone());
+        let projection = CartsWithProductsReadModelProjection::new(pool.clone());
 
         let cart_id = CartId::new();
         let product_id = ProductId::new();
@@ -257,6 +313,7 @@ mod tests {
 
         // Process the events through the projection.
         for event in persisted_events {
+            let event = PersistedEvent::new(event.id(), event.into_inner().try_into().unwrap());
             projection
                 .handle(event)
                 .await
@@ -286,4 +343,90 @@ mod tests {
         assert!(read_model.contains(&expected_read_model[0]));
         assert!(read_model.contains(&expected_read_model[1]));
     }
+
+    #[sqlx::test]
+    async fn only_items_added_before_the_event_are_found(pool: PgPool) {
+        let product_id = ProductId::new();
+        let before = (CartId::new(), ItemId::new());
+        let after = (CartId::new(), ItemId::new());
+        let item_added = |(cart_id, item_id): (CartId, ItemId)| DomainEvent::CartItemAdded {
+            cart_id,
+            description: "Bread".to_string(),
+            image: "bread.jpg".into(),
+            price: 2.into(),
+            item_id,
+            product_id,
+            fingerprint: "fingerprint".to_string(),
+        };
+        let events = append_events(
+            &pool,
+            [
+                item_added(before),
+                DomainEvent::PriceChanged {
+                    product_id,
+                    old_price: 2.into(),
+                    new_price: 3.into(),
+                },
+                item_added(after),
+            ],
+        )
+        .await;
+        let price_changed = events[1].id();
+        set_checkpoint(&pool, PROJECTION_ID, events[0].id() - 1).await;
+        assert_eq!(
+            find_added_before(&pool, &product_id, price_changed)
+                .await
+                .unwrap(),
+            None,
+            "The projection has not handled the item added before the price change."
+        );
+        set_checkpoint(&pool, PROJECTION_ID, events[2].id()).await;
+        let mut conn = pool.acquire().await.unwrap();
+        save(&mut conn, &before.0, &before.1, &product_id, events[0].id())
+            .await
+            .unwrap();
+        save(&mut conn, &after.0, &after.1, &product_id, events[2].id())
+            .await
+            .unwrap();
+        drop(conn);
+
+        let found = find_added_before(&pool, &product_id, price_changed)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            found,
+            Some(vec![CartsWithProductsReadModel {
+                cart_id: before.0,
+                item_id: before.1,
+                product_id,
+            }])
+        );
+    }
+
+    #[sqlx::test]
+    async fn item_added_twice_is_held_once(pool: PgPool) {
+        let projection = CartsWithProductsReadModelProjection::new(pool.clone());
+        let (cart_id, item_id, product_id) = (CartId::new(), ItemId::new(), ProductId::new());
+        let item_added = DomainEvent::CartItemAdded {
+            cart_id,
+            description: "Bread".to_string(),
+            image: "bread.jpg".into(),
+            price: 2.into(),
+            item_id,
+            product_id,
+            fingerprint: "fingerprint".to_string(),
+        };
+
+        append_and_handle(&pool, &projection, [item_added.clone(), item_added]).await;
+
+        assert_eq!(
+            find_by_product_id(&pool, &product_id).await.unwrap(),
+            vec![CartsWithProductsReadModel {
+                cart_id,
+                item_id,
+                product_id,
+            }]
+        );
+    }
 }
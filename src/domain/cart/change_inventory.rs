@@ -4,7 +4,7 @@ use tracing::error;
 use uuid::Uuid;
 
 use crate::{
-    domain::{DecisionMaker, DomainEvent, helpers::Stateless},
+    domain::{Command, CommandBus, CommandOrigin, DomainEvent, helpers::Stateless},
     subsystems::KafkaMessageHandler,
 };
 
@@ -14,12 +14,12 @@ use super::{CartError, ProductId};
 
 #[derive(Clone)]
 pub struct InventoryChangedTranslator {
-    decider: DecisionMaker,
+    command_bus: CommandBus,
 }
 
 impl InventoryChangedTranslator {
-    pub fn new(decider: DecisionMaker) -> Self {
-        Self { decider }
+    pub fn new(command_bus: CommandBus) -> Self {
+        Self { command_bus }
     }
 }
 
@@ -36,7 +36,11 @@ impl KafkaMessageHandler for InventoryChangedTranslator {
                     product_id,
                     inventory: message.inventory,
                 };
-                if let Err(error) = self.decider.make(decision).await {
+                if let Err(error) = self
+                    .command_bus
+                    .dispatch(decision, CommandOrigin::Kafka)
+                    .await
+                {
                     error!(
                         "InventoryChangedTranslator: ChangeInventoryCommand failed with {error}"
                     );
@@ -53,12 +57,18 @@ impl KafkaMessageHandler for InventoryChangedTranslator {
 
 // --------------------------- Command ----------------------------
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ChangeInventoryCommand {
     pub product_id: ProductId,
     pub inventory: i32,
 }
 
+impl Command for ChangeInventoryCommand {
+    fn origins(&self) -> &'static [CommandOrigin] {
+        &[CommandOrigin::Kafka]
+    }
+}
+
 impl Decision for ChangeInventoryCommand {
     type Event = DomainEvent;
     type StateQuery = Stateless;
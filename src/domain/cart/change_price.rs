@@ -3,6 +3,7 @@ use async_trait::async_trait;
 use axum::{
     Json,
     extract::{Path, State},
+    response::{IntoResponse, Response},
 };
 use disintegrate::Decision;
 use rust_decimal::Decimal;
@@ -10,7 +11,10 @@ use tracing::error;
 use uuid::Uuid;
 
 use crate::{
-    domain::{DecisionMaker, DomainEvent, helpers::Stateless},
+    domain::{
+        Command, CommandBus, CommandOrigin, DomainEvent, DryRunParam, ExpectedEventId,
+        helpers::Stateless,
+    },
     infra::ClientError,
     subsystems::KafkaMessageHandler,
 };
@@ -27,10 +31,11 @@ pub struct ChangePricePayload {
 }
 
 pub async fn change_price_endpoint(
-    State(decider): State<DecisionMaker>,
+    State(command_bus): State<CommandBus>,
     Path(product_id): Path<Uuid>,
+    dry_run: DryRunParam,
     Json(payload): Json<ChangePricePayload>,
-) -> Result<Json<(Uuid, i64)>, ClientError> {
+) -> Result<Response, ClientError> {
     if product_id != payload.product_id {
         return Err(ClientError::Payload(
             "Path PrpductId does not match payload ProductId.".to_owned(),
@@ -38,7 +43,13 @@ pub async fn change_price_endpoint(
     }
 
     let decision: ChangePriceCommand = payload.try_into()?;
-    let events = decider.make(decision).await?;
+    if dry_run.0 {
+        return Ok(
+            Json(command_bus.dry_run(decision, ExpectedEventId(None)).await?).into_response(),
+        );
+    }
+
+    let events = command_bus.dispatch(decision, CommandOrigin::Http).await?;
 
     let last_event_id = events
         .into_iter()
@@ -46,19 +57,25 @@ pub async fn change_price_endpoint(
         .map(|e| e.id())
         .context("No event returned for AddItemCommand!")?;
 
-    Ok(Json((product_id, last_event_id)))
+    Ok(Json((product_id, last_event_id)).into_response())
 }
 
 //------------------------- Command ----------------------------
 
 /// The command used for processing ExternalPriceChanged events/messages.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ChangePriceCommand {
     pub product_id: ProductId,
     pub old_price: Decimal,
     pub new_price: Decimal,
 }
 
+impl Command for ChangePriceCommand {
+    fn origins(&self) -> &'static [CommandOrigin] {
+        &[CommandOrigin::Http, CommandOrigin::Kafka]
+    }
+}
+
 impl Decision for ChangePriceCommand {
     type Event = DomainEvent;
     type StateQuery = Stateless;
@@ -103,12 +120,12 @@ pub struct PriceChangedMessage {
 /// The handler that processes ExternalPriceChanged events/messages.
 #[derive(Clone)]
 pub struct PriceChangeTranslator {
-    decider: DecisionMaker,
+    command_bus: CommandBus,
 }
 
 impl PriceChangeTranslator {
-    pub fn new(decider: DecisionMaker) -> Self {
-        Self { decider }
+    pub fn new(command_bus: CommandBus) -> Self {
+        Self { command_bus }
     }
 }
 
@@ -126,7 +143,11 @@ impl KafkaMessageHandler for PriceChangeTranslator {
                     old_price: message.old_price,
                     new_price: message.new_price,
                 };
-                if let Err(error) = self.decider.make(decision).await {
+                if let Err(error) = self
+                    .command_bus
+                    .dispatch(decision, CommandOrigin::Kafka)
+                    .await
+                {
                     error!("PriceChangeTranslator: ChangePriceCommand failed with {error}");
                 }
             }
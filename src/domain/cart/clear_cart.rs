@@ -4,12 +4,15 @@ use anyhow::Context;
 use axum::{
     Json,
     extract::{Path, State},
+    response::{IntoResponse, Response},
 };
 use disintegrate::{Decision, StateMutate, StateQuery};
 use uuid::Uuid;
 
 use crate::{
-    domain::{CartStream, DecisionMaker, DomainEvent},
+    domain::{
+        CartStream, Command, CommandBus, CommandOrigin, DomainEvent, DryRunParam, ExpectedEventId,
+    },
     infra::ClientError,
 };
 
@@ -18,12 +21,20 @@ use super::{CartError, CartId};
 //------------------------- Web API ----------------------------
 
 pub async fn clear_cart_endpoint(
-    State(decider): State<DecisionMaker>,
+    State(command_bus): State<CommandBus>,
     Path(cart_uuid): Path<Uuid>,
-) -> Result<Json<(Uuid, i64)>, ClientError> {
+    dry_run: DryRunParam,
+    expected_event_id: ExpectedEventId,
+) -> Result<Response, ClientError> {
     let cart_id = cart_uuid.try_into()?;
     let decision = ClearCartCommand { cart_id };
-    let events = decider.make(decision).await?;
+    if dry_run.0 {
+        return Ok(Json(command_bus.dry_run(decision, expected_event_id).await?).into_response());
+    }
+
+    let events = command_bus
+        .dispatch_expecting(decision, CommandOrigin::Http, expected_event_id)
+        .await?;
 
     let last_event_id = events
         .into_iter()
@@ -31,16 +42,22 @@ pub async fn clear_cart_endpoint(
         .map(|e| e.id())
         .context("No event returned for ClearCartCommand!")?;
 
-    Ok(Json((cart_uuid, last_event_id)))
+    Ok(Json((cart_uuid, last_event_id)).into_response())
 }
 
 //------------------------- Command ----------------------------
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ClearCartCommand {
     pub cart_id: CartId,
 }
 
+impl Command for ClearCartCommand {
+    fn cart_id(&self) -> Option<CartId> {
+        Some(self.cart_id)
+    }
+}
+
 impl Decision for ClearCartCommand {
     type Event = DomainEvent;
     type StateQuery = ClearCartState;
@@ -51,10 +68,15 @@ impl Decision for ClearCartCommand {
             cart_id: self.cart_id,
             cart_exists: false,
             submitted: false,
+            forgotten: false,
         }
     }
 
     fn process(&self, state: &Self::StateQuery) -> Result<Vec<Self::Event>, Self::Error> {
+        if state.forgotten {
+            return Err(CartError::CartForgotten);
+        }
+
         if state.submitted {
             return Err(CartError::CartCannotBeAltered);
         }
@@ -78,6 +100,7 @@ pub struct ClearCartState {
     cart_id: CartId,
     cart_exists: bool,
     submitted: bool,
+    forgotten: bool,
 }
 
 impl StateMutate for ClearCartState {
@@ -89,6 +112,7 @@ impl StateMutate for ClearCartState {
             CartStream::CartItemRemoved { .. } => {}
             CartStream::CartSubmitted { .. } => self.submitted = true,
             CartStream::ItemArchivedEvent { .. } => {}
+            CartStream::CartForgotten { .. } => self.forgotten = true,
         }
     }
 }
@@ -133,4 +157,15 @@ mod tests {
             .when(ClearCartCommand { cart_id })
             .then_err(CartError::CartDoesNotExist(cart_id));
     }
+
+    #[test]
+    fn should_error_if_cart_is_forgotten() {
+        let cart_id = CartId::new();
+        TestHarness::given([
+            DomainEvent::CartCreated { cart_id },
+            DomainEvent::CartForgotten { cart_id },
+        ])
+        .when(ClearCartCommand { cart_id })
+        .then_err(CartError::CartForgotten);
+    }
 }
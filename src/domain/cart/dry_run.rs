@@ -0,0 +1,158 @@
+//! Dry Run slice. Makes any command against the current state without appending its events, to
+//! explain a decision or preview its outcome, e.g. the `total_price` of a submitted cart.
+
+use anyhow::{Context, anyhow, bail};
+use disintegrate::{Decision, LoadState};
+use disintegrate_postgres::PgEventId;
+
+use crate::domain::{
+    Command, CommandBus, DomainEvent, ExpectedEventId,
+    helpers::{
+        decision_maker::StateStore, device_fingerprint_calculator::calculate_device_fingerprint,
+    },
+};
+
+use super::{
+    AddItemCommand, CartError, ChangeInventoryCommand, ChangePriceCommand, ForgetCartCommand,
+    RemoveItemCommand, RepublishCartCommand, SubmitCartCommand, archive_item::ArchiveItemCommand,
+    clear_cart::ClearCartCommand,
+};
+
+/// The commands that can be dry run by name.
+pub const DRY_RUN_COMMANDS: [&str; 9] = [
+    "AddItemCommand",
+    "RemoveItemCommand",
+    "ClearCartCommand",
+    "SubmitCartCommand",
+    "ForgetCartCommand",
+    "RepublishCartCommand",
+    "ArchiveItemCommand",
+    "ChangePriceCommand",
+    "ChangeInventoryCommand",
+];
+
+/// Dry runs the named command, e.g. `SubmitCartCommand`, with its fields given as JSON.
+/// Returns the `DryRun` as JSON.
+pub async fn dry_run_command(
+    command_bus: &CommandBus,
+    name: &str,
+    payload: &str,
+) -> Result<serde_json::Value, anyhow::Error> {
+    match name {
+        "AddItemCommand" => {
+            let mut command: AddItemCommand = parse(name, payload)?;
+            command.fingerprint = calculate_device_fingerprint();
+            dry_run(command_bus, command).await
+        }
+        "RemoveItemCommand" => {
+            dry_run::<RemoveItemCommand, _>(command_bus, parse(name, payload)?).await
+        }
+        "ClearCartCommand" => {
+            dry_run::<ClearCartCommand, _>(command_bus, parse(name, payload)?).await
+        }
+        "SubmitCartCommand" => {
+            dry_run::<SubmitCartCommand, _>(command_bus, parse(name, payload)?).await
+        }
+        "ForgetCartCommand" => {
+            dry_run::<ForgetCartCommand, _>(command_bus, parse(name, payload)?).await
+        }
+        "RepublishCartCommand" => {
+            dry_run::<RepublishCartCommand, _>(command_bus, parse(name, payload)?).await
+        }
+        "ArchiveItemCommand" => {
+            dry_run::<ArchiveItemCommand, _>(command_bus, parse(name, payload)?).await
+        }
+        "ChangePriceCommand" => {
+            dry_run::<ChangePriceCommand, _>(command_bus, parse(name, payload)?).await
+        }
+        "ChangeInventoryCommand" => {
+            dry_run::<ChangeInventoryCommand, _>(command_bus, parse(name, payload)?).await
+        }
+        _ => bail!("Unknown command {name}. Expected one of {DRY_RUN_COMMANDS:?}."),
+    }
+}
+
+fn parse<D: serde::de::DeserializeOwned>(name: &str, payload: &str) -> Result<D, anyhow::Error> {
+    serde_json::from_str(payload).with_context(|| format!("Invalid payload for {name}."))
+}
+
+async fn dry_run<D, S>(
+    command_bus: &CommandBus,
+    command: D,
+) -> Result<serde_json::Value, anyhow::Error>
+where
+    D: Command + Decision<StateQuery = S, Event = DomainEvent, Error = CartError>,
+    S: Clone + serde::Serialize,
+    StateStore: LoadState<PgEventId, S, DomainEvent>,
+{
+    let dry_run = command_bus
+        .dry_run(command, ExpectedEventId(None))
+        .await
+        .map_err(|e| anyhow!("Problem in dry_run(). {e}"))?;
+    serde_json::to_value(dry_run).context("Problem in dry_run() serialising the outcome.")
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use fake::{Fake, Faker};
+
+    use super::*;
+    use crate::domain::{cart::CartId, create_in_memory_eventstore_and_decider};
+
+    #[tokio::test]
+    async fn submission_is_previewed_by_name() {
+        let (_event_store, decider) = create_in_memory_eventstore_and_decider();
+        let command_bus = CommandBus::new(decider);
+        let cart_id = CartId::new();
+        let add_item = AddItemCommand {
+            cart_id,
+            ..Faker.fake()
+        };
+        command_bus.decider().make(add_item.clone()).await.unwrap();
+
+        let dry_run = dry_run_command(
+            &command_bus,
+            "SubmitCartCommand",
+            &format!(r#"{{"cart_id": "{cart_id}"}}"#),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            dry_run["events"][0]["CartSubmitted"]["total_price"],
+            serde_json::to_value(add_item.price).unwrap()
+        );
+        assert_eq!(dry_run["state"]["item_count"], 1);
+        assert!(dry_run["rejection"].is_null());
+    }
+
+    #[tokio::test]
+    async fn personal_data_is_not_previewed() {
+        let (_event_store, decider) = create_in_memory_eventstore_and_decider();
+        let add_item: AddItemCommand = Faker.fake();
+
+        let dry_run = dry_run_command(
+            &CommandBus::new(decider),
+            "AddItemCommand",
+            &serde_json::to_string(&add_item).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            dry_run["events"][1]["CartItemAdded"]["fingerprint"],
+            crate::domain::REDACTED
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_command_is_an_error() {
+        let (_event_store, decider) = create_in_memory_eventstore_and_decider();
+
+        let result = dry_run_command(&CommandBus::new(decider), "FlyCartCommand", "{}").await;
+
+        assert!(result.is_err());
+    }
+}
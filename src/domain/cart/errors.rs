@@ -1,4 +1,5 @@
 use super::CartId;
+use crate::domain::CommandOrigin;
 
 #[derive(Debug, PartialEq, thiserror::Error)]
 pub enum CartError {
@@ -16,4 +17,22 @@ pub enum CartError {
     CannotSubmitCartTwice,
     #[error("Cart has been submitted. Cannot be altered.")]
     CartCannotBeAltered,
+    #[error("Cart with ID {0} has not been submitted.")]
+    CartNotSubmitted(CartId),
+    #[error("Cannot republish cart. Only a cart whose publication failed can be republished.")]
+    CannotRepublishCart,
+    #[error("Cart has been forgotten.")]
+    CartForgotten,
+    #[error("Cart with ID {0} has been archived. Cannot be altered.")]
+    CartArchived(CartId),
+    #[error("{command} cannot be dispatched from {origin}.")]
+    CommandNotAllowed {
+        command: &'static str,
+        origin: CommandOrigin,
+    },
+    #[error("Cart has changed since event {expected_event_id}. Its last event is {last_event_id}.")]
+    CartChanged {
+        expected_event_id: i64,
+        last_event_id: i64,
+    },
 }
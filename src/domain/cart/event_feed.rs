@@ -0,0 +1,723 @@
+//! Event Feed slice. A pull-based HTTP feed of events, in event id order, for consumers that do
+//! not use Kafka.
+//!
+//! Events are mapped to public contracts, `FeedEventData`, rather than exposing `DomainEvent`, so
+//! internal refactors do not break consumers. Every public type is versioned, e.g.
+//! `cart.item_added.v1`. Personal data and internal bookkeeping are left out and a changed
+//! contract is published under a new version. Internal events such as `EmptyEvent` are skipped.
+//!
+//! A consumer pages through the feed by passing the `next_cursor` of one page as the `after` of
+//! the next. The cursor moves past skipped and filtered out events too, so it always advances.
+//! With `wait_ms` an empty page is held open until events arrive or the wait is over.
+//! Archived events are only in the feed when asked for with `include_archive`.
+
+use std::{collections::HashSet, time::Duration};
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use disintegrate::{EventStore as _, StreamQuery, query};
+use futures::{StreamExt, TryStreamExt, future};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        CartStream, DomainEvent, EventArchive, EventStore, InventoryStream, PricingStream,
+        events::PublishedStream, helpers::projections::in_event_id_order,
+    },
+    infra::ClientError,
+};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// Most events read for one page, so a filter that matches few events cannot scan the whole
+/// event store in one request.
+const MAX_SCAN: usize = 10_000;
+
+const MAX_WAIT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+//-------------------------- Contract ----------------------------
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    strum_macros::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum FeedStream {
+    Cart,
+    Inventory,
+    Pricing,
+    Publication,
+}
+
+impl FeedStream {
+    const ALL: [FeedStream; 4] = [
+        FeedStream::Cart,
+        FeedStream::Inventory,
+        FeedStream::Pricing,
+        FeedStream::Publication,
+    ];
+
+    fn query(self) -> StreamQuery<i64, DomainEvent> {
+        match self {
+            FeedStream::Cart => query!(CartStream).cast(),
+            FeedStream::Inventory => query!(InventoryStream).cast(),
+            FeedStream::Pricing => query!(PricingStream).cast(),
+            FeedStream::Publication => query!(PublishedStream).cast(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct FeedEvent {
+    pub event_id: i64,
+    pub stream: FeedStream,
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    pub data: FeedEventData,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct FeedOrderedProduct {
+    pub product_id: Uuid,
+    pub price: Decimal,
+}
+
+/// The public contract of each event type. Fields may be added to a version but are never
+/// renamed, retyped or removed.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(untagged)]
+pub enum FeedEventData {
+    CartCreatedV1 {
+        cart_id: Uuid,
+    },
+    CartItemAddedV1 {
+        cart_id: Uuid,
+        item_id: Uuid,
+        product_id: Uuid,
+        description: String,
+        image: String,
+        price: Decimal,
+    },
+    CartItemRemovedV1 {
+        cart_id: Uuid,
+        item_id: Uuid,
+    },
+    CartItemArchivedV1 {
+        cart_id: Uuid,
+        item_id: Uuid,
+    },
+    CartClearedV1 {
+        cart_id: Uuid,
+    },
+    CartSubmittedV1 {
+        cart_id: Uuid,
+        ordered_products: Vec<FeedOrderedProduct>,
+        total_price: Decimal,
+    },
+    CartForgottenV1 {
+        cart_id: Uuid,
+    },
+    CartPublishedV1 {
+        cart_id: Uuid,
+    },
+    CartPublicationFailedV1 {
+        cart_id: Uuid,
+    },
+    InventoryChangedV1 {
+        product_id: Uuid,
+        inventory: i32,
+    },
+    PriceChangedV1 {
+        product_id: Uuid,
+        old_price: Decimal,
+        new_price: Decimal,
+    },
+}
+
+/// Names the public type of each `FeedEventData` variant. The match in `event_type` is
+/// exhaustive, so `FEED_EVENT_TYPES` lists every type a feed event can have.
+macro_rules! feed_event_types {
+    ($($variant:ident => $event_type:literal),* $(,)?) => {
+        /// The public event types, as they appear in `FeedEvent::event_type`.
+        pub const FEED_EVENT_TYPES: [&str; [$($event_type),*].len()] = [$($event_type),*];
+
+        impl FeedEventData {
+            pub fn event_type(&self) -> &'static str {
+                match self {
+                    $(FeedEventData::$variant { .. } => $event_type,)*
+                }
+            }
+        }
+    };
+}
+
+feed_event_types! {
+    CartCreatedV1 => "cart.created.v1",
+    CartItemAddedV1 => "cart.item_added.v1",
+    CartItemRemovedV1 => "cart.item_removed.v1",
+    CartItemArchivedV1 => "cart.item_archived.v1",
+    CartClearedV1 => "cart.cleared.v1",
+    CartSubmittedV1 => "cart.submitted.v1",
+    CartForgottenV1 => "cart.forgotten.v1",
+    CartPublishedV1 => "cart.published.v1",
+    CartPublicationFailedV1 => "cart.publication_failed.v1",
+    InventoryChangedV1 => "inventory.changed.v1",
+    PriceChangedV1 => "price.changed.v1",
+}
+
+impl FeedEventData {
+    pub fn stream(&self) -> FeedStream {
+        match self {
+            FeedEventData::InventoryChangedV1 { .. } => FeedStream::Inventory,
+            FeedEventData::PriceChangedV1 { .. } => FeedStream::Pricing,
+            FeedEventData::CartPublishedV1 { .. }
+            | FeedEventData::CartPublicationFailedV1 { .. } => FeedStream::Publication,
+            _ => FeedStream::Cart,
+        }
+    }
+
+    /// Maps an event to its public contract. Returns `None` for internal events.
+    pub fn from_domain(event: DomainEvent) -> Option<Self> {
+        let data = match event {
+            DomainEvent::CartCreated { cart_id } => FeedEventData::CartCreatedV1 {
+                cart_id: cart_id.into(),
+            },
+            DomainEvent::CartItemAdded {
+                cart_id,
+                description,
+                image,
+                price,
+                item_id,
+                product_id,
+                fingerprint: _,
+            } => FeedEventData::CartItemAddedV1 {
+                cart_id: cart_id.into(),
+                item_id: item_id.into(),
+                product_id: product_id.into(),
+                description,
+                image: image.to_string_lossy().into_owned(),
+                price,
+            },
+            DomainEvent::CartItemRemoved { cart_id, item_id } => FeedEventData::CartItemRemovedV1 {
+                cart_id: cart_id.into(),
+                item_id: item_id.into(),
+            },
+            DomainEvent::ItemArchivedEvent {
+                cart_id, item_id, ..
+            } => FeedEventData::CartItemArchivedV1 {
+                cart_id: cart_id.into(),
+                item_id: item_id.into(),
+            },
+            DomainEvent::CartCleared { cart_id } => FeedEventData::CartClearedV1 {
+                cart_id: cart_id.into(),
+            },
+            DomainEvent::CartSubmitted {
+                cart_id,
+                ordered_product,
+                total_price,
+            } => FeedEventData::CartSubmittedV1 {
+                cart_id: cart_id.into(),
+                ordered_products: ordered_product
+                    .into_iter()
+                    .map(|op| FeedOrderedProduct {
+                        product_id: op.product_id.into(),
+                        price: op.price,
+                    })
+                    .collect(),
+                total_price,
+            },
+            DomainEvent::CartForgotten { cart_id } => FeedEventData::CartForgottenV1 {
+                cart_id: cart_id.into(),
+            },
+            DomainEvent::CartPublished { cart_id } => FeedEventData::CartPublishedV1 {
+                cart_id: cart_id.into(),
+            },
+            DomainEvent::CartPublicationFailed { cart_id } => {
+                FeedEventData::CartPublicationFailedV1 {
+                    cart_id: cart_id.into(),
+                }
+            }
+            DomainEvent::InventoryChanged {
+                product_id,
+                inventory,
+            } => FeedEventData::InventoryChangedV1 {
+                product_id: product_id.into(),
+                inventory,
+            },
+            DomainEvent::PriceChanged {
+                product_id,
+                old_price,
+                new_price,
+            } => FeedEventData::PriceChangedV1 {
+                product_id: product_id.into(),
+                old_price,
+                new_price,
+            },
+            DomainEvent::EmptyEvent
+            | DomainEvent::LowStockDetected { .. }
+            | DomainEvent::CartRepublishRequested { .. } => return None,
+        };
+        Some(data)
+    }
+}
+
+//------------------------- Read Model ---------------------------
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct FeedPage {
+    pub events: Vec<FeedEvent>,
+    /// Pass as `after` to read the next page.
+    pub next_cursor: i64,
+}
+
+/// Which events to read, parsed from `FeedParams`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedFilter {
+    pub after: i64,
+    pub limit: usize,
+    pub streams: Vec<FeedStream>,
+    /// Public event types. Empty means every type.
+    pub event_types: HashSet<&'static str>,
+    /// Also read the events of archived carts.
+    pub include_archive: bool,
+}
+
+impl Default for FeedFilter {
+    fn default() -> Self {
+        Self {
+            after: 0,
+            limit: DEFAULT_LIMIT,
+            streams: FeedStream::ALL.to_vec(),
+            event_types: HashSet::new(),
+            include_archive: false,
+        }
+    }
+}
+
+/// Reads one page of the feed.
+pub async fn read_feed(
+    event_store: &EventStore,
+    event_archive: &EventArchive,
+    filter: &FeedFilter,
+) -> Result<FeedPage, anyhow::Error> {
+    let query = filter
+        .streams
+        .iter()
+        .map(|stream| stream.query())
+        .reduce(|query, other| query.union(&other))
+        .unwrap_or_else(|| FeedStream::Cart.query())
+        .change_origin(filter.after);
+
+    let live = event_store
+        .stream(&query)
+        .map(|event| event.map_err(anyhow::Error::new))
+        .boxed();
+    let selected = if filter.include_archive {
+        let archived = event_archive
+            .archived_events_after(filter.after)
+            .try_filter(|event| future::ready(query.matches(event)))
+            .boxed();
+        in_event_id_order(archived, live)
+    } else {
+        live
+    };
+    let mut stream = selected.take(MAX_SCAN);
+    let mut events = Vec::new();
+    let mut next_cursor = filter.after;
+    while let Some(event) = stream.next().await {
+        let event =
+            event.with_context(|| format!("Problem in read_feed(after: {}).", filter.after))?;
+        next_cursor = event.id();
+        let event_id = event.id();
+        let Some(data) = FeedEventData::from_domain(event.into_inner()) else {
+            continue;
+        };
+        if !filter.event_types.is_empty() && !filter.event_types.contains(data.event_type()) {
+            continue;
+        }
+        events.push(FeedEvent {
+            event_id,
+            stream: data.stream(),
+            event_type: data.event_type(),
+            data,
+        });
+        if events.len() >= filter.limit {
+            break;
+        }
+    }
+
+    Ok(FeedPage {
+        events,
+        next_cursor,
+    })
+}
+
+/// Reads one page of the feed, waiting up to `wait` for events if there are none yet.
+pub async fn wait_for_feed(
+    event_store: &EventStore,
+    event_archive: &EventArchive,
+    filter: &FeedFilter,
+    wait: Duration,
+) -> Result<FeedPage, anyhow::Error> {
+    let deadline = tokio::time::Instant::now() + wait.min(MAX_WAIT);
+    let mut filter = filter.clone();
+    loop {
+        let page = read_feed(event_store, event_archive, &filter).await?;
+        if !page.events.is_empty() || tokio::time::Instant::now() >= deadline {
+            return Ok(page);
+        }
+        filter.after = page.next_cursor;
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+//------------------------- Web API ----------------------------
+
+/// e.g. `GET /feed?after=42&stream=cart&type=cart.submitted.v1&wait_ms=10000&include_archive=true`
+/// `stream` and `type` take comma separated lists.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct FeedParams {
+    pub after: Option<i64>,
+    pub limit: Option<usize>,
+    pub stream: Option<String>,
+    #[serde(rename = "type")]
+    pub event_type: Option<String>,
+    pub wait_ms: Option<u64>,
+    #[serde(default)]
+    pub include_archive: bool,
+}
+
+impl TryFrom<FeedParams> for FeedFilter {
+    type Error = ClientError;
+
+    fn try_from(params: FeedParams) -> Result<Self, Self::Error> {
+        let streams = match params.stream.as_deref() {
+            Some(streams) => split(streams)
+                .map(|stream| {
+                    FeedStream::ALL
+                        .into_iter()
+                        .find(|known| known.to_string() == stream)
+                        .ok_or_else(|| {
+                            ClientError::Payload(format!(
+                                "Unknown stream {stream}. Expected one of {:?}.",
+                                FeedStream::ALL.map(|s| s.to_string())
+                            ))
+                        })
+                })
+                .collect::<Result<_, _>>()?,
+            None => FeedStream::ALL.to_vec(),
+        };
+        let event_types = match params.event_type.as_deref() {
+            Some(event_types) => split(event_types)
+                .map(|event_type| {
+                    FEED_EVENT_TYPES
+                        .into_iter()
+                        .find(|known| *known == event_type)
+                        .ok_or_else(|| {
+                            ClientError::Payload(format!(
+                                "Unknown event type {event_type}. Expected one of {FEED_EVENT_TYPES:?}."
+                            ))
+                        })
+                })
+                .collect::<Result<_, _>>()?,
+            None => HashSet::new(),
+        };
+
+        Ok(FeedFilter {
+            after: params.after.unwrap_or(0),
+            limit: params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+            streams,
+            event_types,
+            include_archive: params.include_archive,
+        })
+    }
+}
+
+fn split(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+pub async fn event_feed_endpoint(
+    State(event_store): State<EventStore>,
+    State(event_archive): State<EventArchive>,
+    Query(params): Query<FeedParams>,
+) -> Result<Json<FeedPage>, ClientError> {
+    let wait = Duration::from_millis(params.wait_ms.unwrap_or(0));
+    let filter: FeedFilter = params.try_into()?;
+    Ok(Json(
+        wait_for_feed(&event_store, &event_archive, &filter, wait).await?,
+    ))
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use disintegrate::EventStore as _;
+
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::domain::{
+        EventCodec, EventSerde, KeyStore, ListenerMonitor,
+        cart::{CartId, ItemId, ProductId},
+        create_eventstore, create_in_memory_eventstore_and_decider,
+        helpers::device_fingerprint_calculator::default_fingerprint,
+    };
+
+    fn no_archive() -> EventArchive {
+        EventArchive::in_memory(EventSerde::new(
+            KeyStore::in_memory(),
+            EventCodec::default(),
+        ))
+    }
+
+    async fn event_store_with_events() -> (EventStore, CartId, ProductId) {
+        let (event_store, _decider) = create_in_memory_eventstore_and_decider();
+        let cart_id = CartId::new();
+        let product_id = ProductId::new();
+        event_store
+            .append_without_validation(vec![
+                DomainEvent::CartCreated { cart_id },
+                DomainEvent::EmptyEvent,
+                DomainEvent::CartItemAdded {
+                    cart_id,
+                    description: "Socks".to_owned(),
+                    image: "socks.png".into(),
+                    price: Decimal::new(250, 2),
+                    item_id: ItemId::new(),
+                    product_id,
+                    fingerprint: default_fingerprint(),
+                },
+                DomainEvent::InventoryChanged {
+                    product_id,
+                    inventory: 7,
+                },
+                DomainEvent::CartCleared { cart_id },
+            ])
+            .await
+            .unwrap();
+        (event_store, cart_id, product_id)
+    }
+
+    #[tokio::test]
+    async fn feed_is_paged_by_cursor() {
+        let (event_store, _, _) = event_store_with_events().await;
+        let filter = FeedFilter {
+            limit: 2,
+            ..Default::default()
+        };
+
+        let first = read_feed(&event_store, &no_archive(), &filter)
+            .await
+            .unwrap();
+        let second = read_feed(
+            &event_store,
+            &no_archive(),
+            &FeedFilter {
+                after: first.next_cursor,
+                ..filter.clone()
+            },
+        )
+        .await
+        .unwrap();
+        let last = read_feed(
+            &event_store,
+            &no_archive(),
+            &FeedFilter {
+                after: second.next_cursor,
+                ..filter
+            },
+        )
+        .await
+        .unwrap();
+
+        let ids = |page: &FeedPage| page.events.iter().map(|e| e.event_id).collect::<Vec<_>>();
+        // Event 2 is internal and is not in the feed.
+        assert_eq!(ids(&first), vec![1, 3]);
+        assert_eq!(ids(&second), vec![4, 5]);
+        assert!(last.events.is_empty());
+        assert_eq!(last.next_cursor, 5);
+    }
+
+    #[tokio::test]
+    async fn feed_is_filtered_by_stream_and_type() {
+        let (event_store, _, product_id) = event_store_with_events().await;
+
+        let inventory = read_feed(
+            &event_store,
+            &no_archive(),
+            &FeedFilter {
+                streams: vec![FeedStream::Inventory],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let cleared = read_feed(
+            &event_store,
+            &no_archive(),
+            &FeedFilter {
+                event_types: HashSet::from(["cart.cleared.v1"]),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            inventory.events,
+            vec![FeedEvent {
+                event_id: 4,
+                stream: FeedStream::Inventory,
+                event_type: "inventory.changed.v1",
+                data: FeedEventData::InventoryChangedV1 {
+                    product_id: product_id.into(),
+                    inventory: 7,
+                },
+            }]
+        );
+        assert_eq!(cleared.events.len(), 1);
+        assert_eq!(cleared.events[0].event_id, 5);
+        assert_eq!(cleared.next_cursor, 5);
+    }
+
+    #[tokio::test]
+    async fn public_contract_leaves_out_personal_data() {
+        let (event_store, cart_id, _) = event_store_with_events().await;
+
+        let page = read_feed(&event_store, &no_archive(), &FeedFilter::default())
+            .await
+            .unwrap();
+        let added = serde_json::to_value(&page.events[1]).unwrap();
+
+        assert_eq!(added["type"], "cart.item_added.v1");
+        assert_eq!(added["stream"], "cart");
+        assert_eq!(added["data"]["cart_id"], cart_id.to_string());
+        assert_eq!(added["data"]["price"], "2.50");
+        assert!(added["data"].get("fingerprint").is_none());
+    }
+
+    #[tokio::test]
+    async fn long_poll_returns_events_appended_while_waiting() {
+        let (event_store, cart_id, _) = event_store_with_events().await;
+        let appender = event_store.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            appender
+                .append_without_validation(vec![DomainEvent::CartForgotten { cart_id }])
+                .await
+                .unwrap();
+        });
+
+        let page = wait_for_feed(
+            &event_store,
+            &no_archive(),
+            &FeedFilter {
+                after: 5,
+                ..Default::default()
+            },
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(page.events.len(), 1);
+        assert_eq!(page.events[0].event_type, "cart.forgotten.v1");
+    }
+
+    #[sqlx::test]
+    async fn archived_events_are_only_read_when_asked_for(pool: PgPool) {
+        let serde = EventSerde::new(KeyStore::load(&pool).await.unwrap(), EventCodec::Json);
+        let event_store = create_eventstore(&pool, serde.clone()).await.unwrap();
+        let archive = EventArchive::new(pool.clone(), serde);
+        let cart_id = CartId::new();
+        for event in [
+            DomainEvent::CartCreated { cart_id },
+            DomainEvent::InventoryChanged {
+                product_id: ProductId::new(),
+                inventory: 7,
+            },
+            DomainEvent::CartPublished { cart_id },
+        ] {
+            event_store
+                .append_without_validation(vec![event])
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE event SET inserted_at = now() - INTERVAL '31 days' WHERE cart_id = $1")
+            .bind(cart_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        archive
+            .archive_published_carts(30, &ListenerMonitor::in_memory())
+            .await
+            .unwrap();
+
+        let live = read_feed(&event_store, &archive, &FeedFilter::default())
+            .await
+            .unwrap();
+        let all = read_feed(
+            &event_store,
+            &archive,
+            &FeedFilter {
+                include_archive: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let types = |page: &FeedPage| page.events.iter().map(|e| e.event_type).collect::<Vec<_>>();
+        assert_eq!(types(&live), vec!["inventory.changed.v1"]);
+        assert_eq!(
+            types(&all),
+            vec![
+                "cart.created.v1",
+                "inventory.changed.v1",
+                "cart.published.v1"
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_filters_are_rejected() {
+        let unknown_stream = FeedFilter::try_from(FeedParams {
+            stream: Some("cart,orders".to_owned()),
+            ..Default::default()
+        });
+        let unknown_type = FeedFilter::try_from(FeedParams {
+            event_type: Some("cart.created.v9".to_owned()),
+            ..Default::default()
+        });
+        let known = FeedFilter::try_from(FeedParams {
+            stream: Some("cart, pricing".to_owned()),
+            event_type: Some("cart.created.v1".to_owned()),
+            limit: Some(5000),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert!(matches!(unknown_stream, Err(ClientError::Payload(_))));
+        assert!(matches!(unknown_type, Err(ClientError::Payload(_))));
+        assert_eq!(known.streams, vec![FeedStream::Cart, FeedStream::Pricing]);
+        assert_eq!(known.limit, MAX_LIMIT);
+    }
+}
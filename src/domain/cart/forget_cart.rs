@@ -0,0 +1,228 @@
+//! Forget Cart slice. Crypto-shreds the personal data held in a cart's events.
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::{
+    Json,
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+};
+use disintegrate::{
+    Decision, EventListener, PersistedEvent, StateMutate, StateQuery, StreamQuery, query,
+};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        CartStream, Command, CommandBus, CommandOrigin, DomainEvent, DryRunParam, ExpectedEventId,
+        ForgottenStream, KeyStore,
+    },
+    infra::ClientError,
+};
+
+use super::{CartError, CartId};
+
+//------------------------- Web API ----------------------------
+
+pub async fn forget_cart_endpoint(
+    State(command_bus): State<CommandBus>,
+    Path(cart_uuid): Path<Uuid>,
+    dry_run: DryRunParam,
+) -> Result<Response, ClientError> {
+    let cart_id = cart_uuid.try_into()?;
+    let decision = ForgetCartCommand { cart_id };
+    if dry_run.0 {
+        return Ok(
+            Json(command_bus.dry_run(decision, ExpectedEventId(None)).await?).into_response(),
+        );
+    }
+
+    let events = command_bus.dispatch(decision, CommandOrigin::Http).await?;
+
+    let last_event_id = events
+        .into_iter()
+        .last()
+        .map(|e| e.id())
+        .context("No event returned for ForgetCartCommand!")?;
+
+    Ok(Json((cart_uuid, last_event_id)).into_response())
+}
+
+//------------------------- Command ----------------------------
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ForgetCartCommand {
+    pub cart_id: CartId,
+}
+
+impl Command for ForgetCartCommand {
+    fn cart_id(&self) -> Option<CartId> {
+        Some(self.cart_id)
+    }
+
+    /// An archived cart can still be forgotten, so its personal data can be crypto-shredded.
+    fn decide_archived(
+        &self,
+        events: Vec<DomainEvent>,
+    ) -> Option<Result<Vec<DomainEvent>, CartError>> {
+        let mut state = self.state_query();
+        for event in events {
+            if let Ok(event) = CartStream::try_from(event) {
+                state.mutate(event);
+            }
+        }
+        Some(self.process(&state))
+    }
+}
+
+impl Decision for ForgetCartCommand {
+    type Event = DomainEvent;
+    type StateQuery = ForgetCartState;
+    type Error = CartError;
+
+    fn state_query(&self) -> Self::StateQuery {
+        ForgetCartState {
+            cart_id: self.cart_id,
+            cart_exists: false,
+            forgotten: false,
+        }
+    }
+
+    fn process(&self, state: &Self::StateQuery) -> Result<Vec<Self::Event>, Self::Error> {
+        if !state.cart_exists {
+            return Err(CartError::CartDoesNotExist(self.cart_id));
+        }
+
+        if state.forgotten {
+            return Err(CartError::CartForgotten);
+        }
+
+        Ok(vec![DomainEvent::CartForgotten {
+            cart_id: self.cart_id,
+        }])
+    }
+}
+
+//---------------------- Command State --------------------------
+
+#[derive(Clone, Debug, PartialEq, Eq, StateQuery, serde::Serialize, serde::Deserialize)]
+#[state_query(CartStream)]
+pub struct ForgetCartState {
+    #[id]
+    cart_id: CartId,
+    cart_exists: bool,
+    forgotten: bool,
+}
+
+impl StateMutate for ForgetCartState {
+    fn mutate(&mut self, event: Self::Event) {
+        match event {
+            CartStream::CartCreated { .. } => self.cart_exists = true,
+            CartStream::CartForgotten { .. } => self.forgotten = true,
+            CartStream::CartCleared { .. } => {}
+            CartStream::CartItemAdded { .. } => {}
+            CartStream::CartItemRemoved { .. } => {}
+            CartStream::CartSubmitted { .. } => {}
+            CartStream::ItemArchivedEvent { .. } => {}
+        }
+    }
+}
+
+//---------------------- Event Handler --------------------------
+
+/// Deletes the encryption key of a cart once it has been forgotten.
+/// Being an event listener the deletion is retried until it succeeds.
+pub struct CartForgottenEventHandler {
+    query: StreamQuery<i64, ForgottenStream>,
+    key_store: KeyStore,
+}
+
+impl CartForgottenEventHandler {
+    pub fn new(key_store: KeyStore) -> Self {
+        Self {
+            key_store,
+            query: query!(ForgottenStream),
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener<i64, ForgottenStream> for CartForgottenEventHandler {
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        "cart_forgotten"
+    }
+
+    fn query(&self) -> &StreamQuery<i64, ForgottenStream> {
+        &self.query
+    }
+
+    async fn handle(&self, event: PersistedEvent<i64, ForgottenStream>) -> Result<(), Self::Error> {
+        let event_id = event.id();
+        match event.into_inner() {
+            ForgottenStream::CartForgotten { cart_id } => {
+                self.key_store
+                    .forget(&cart_id)
+                    .await
+                    .inspect_err(|e| error!("CartForgottenEventHandler: Failed to forget key for event {event_id} due to {e}."))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{
+        cart::{ItemId, ProductId},
+        helpers::fake::FingerPrint,
+    };
+
+    use super::*;
+    use disintegrate::TestHarness;
+    use fake::{Fake, Faker};
+
+    #[test]
+    fn cart_should_be_forgotten_if_cart_exists() {
+        let cart_id = CartId::new();
+
+        TestHarness::given([
+            DomainEvent::CartCreated { cart_id },
+            DomainEvent::CartItemAdded {
+                cart_id,
+                description: Faker.fake(),
+                image: Faker.fake(),
+                price: Faker.fake(),
+                item_id: ItemId::new(),
+                product_id: ProductId::new(),
+                fingerprint: FingerPrint.fake(),
+            },
+        ])
+        .when(ForgetCartCommand { cart_id })
+        .then([DomainEvent::CartForgotten { cart_id }])
+    }
+
+    #[test]
+    fn should_error_if_cart_already_forgotten() {
+        let cart_id = CartId::new();
+        TestHarness::given([
+            DomainEvent::CartCreated { cart_id },
+            DomainEvent::CartForgotten { cart_id },
+        ])
+        .when(ForgetCartCommand { cart_id })
+        .then_err(CartError::CartForgotten);
+    }
+
+    #[test]
+    fn should_error_if_cart_does_not_exist() {
+        let cart_id = CartId::new();
+        TestHarness::given([])
+            .when(ForgetCartCommand { cart_id })
+            .then_err(CartError::CartDoesNotExist(cart_id));
+    }
+}
@@ -6,11 +6,17 @@ use axum::{
     extract::{Path, State},
 };
 use disintegrate::{EventListener, PersistedEvent, StreamQuery, query};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use tracing::error;
 use uuid::Uuid;
 
-use crate::{domain::InventoryStream, infra::ClientError};
+use crate::{
+    domain::{
+        InventoryStream, MinEventId, ProjectionTransaction, RebuildableProjection,
+        wait_for_projection,
+    },
+    infra::{ClientError, Settings},
+};
 
 use super::ProductId;
 
@@ -19,14 +25,29 @@ use super::ProductId;
 pub async fn inventories_endpoint(
     State(pool): State<PgPool>,
     Path(product_uuid): Path<Uuid>,
+    min_event_id: MinEventId,
 ) -> Result<Json<Option<InventoriesReadModel>>, ClientError> {
     let product_id: ProductId = product_uuid.try_into()?;
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
     match find_by_id(&pool, &product_id).await {
         Ok(read_model) => Ok(Json(read_model)),
         Err(e) => Err(e.into()),
     }
 }
 
+/// e.g. `GET /inventories/lowstock`
+pub async fn low_stock_endpoint(
+    State(pool): State<PgPool>,
+    State(settings): State<Settings>,
+    min_event_id: MinEventId,
+) -> Result<Json<Vec<InventoriesReadModel>>, ClientError> {
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
+    match find_below(&pool, settings.inventory.low_stock_threshold).await {
+        Ok(read_model) => Ok(Json(read_model)),
+        Err(e) => Err(e.into()),
+    }
+}
+
 //----------------------- Read Model API ------------------------
 
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
@@ -51,18 +72,47 @@ pub async fn find_by_id(
     .with_context(|| format!("Problem in find_by_product_id({product_id})"))
 }
 
+/// The products whose inventory is below the threshold, lowest first.
+pub async fn find_below(
+    pool: &PgPool,
+    threshold: i32,
+) -> Result<Vec<InventoriesReadModel>, anyhow::Error> {
+    sqlx::query_as!(
+        InventoriesReadModel,
+        r#"SELECT product_id as "product_id: _", inventory
+           from inventories
+           where inventory < $1
+           order by inventory, product_id;"#,
+        threshold
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("Problem in find_below({threshold})"))
+}
+
 //------------------------- Projection --------------------------
 
+pub(super) const PROJECTION_ID: &str = "inventories";
+/// Listener id of the shadow copy built by a blue/green rebuild.
+const SHADOW_PROJECTION_ID: &str = "inventories_shadow";
+
+fn projection_query() -> StreamQuery<i64, InventoryStream> {
+    query!(InventoryStream)
+}
+
+#[derive(Clone)]
 pub(crate) struct InventoriesReadModelProjection {
     query: StreamQuery<i64, InventoryStream>,
+    id: &'static str,
     pool: PgPool,
 }
 
 impl InventoriesReadModelProjection {
     pub fn new(pool: PgPool) -> Self {
         Self {
+            id: PROJECTION_ID,
             pool,
-            query: query!(InventoryStream),
+            query: projection_query(),
         }
     }
 }
@@ -72,7 +122,7 @@ impl EventListener<i64, InventoryStream> for InventoriesReadModelProjection {
     type Error = anyhow::Error;
 
     fn id(&self) -> &'static str {
-        "inventories"
+        self.id
     }
 
     fn query(&self) -> &StreamQuery<i64, InventoryStream> {
@@ -81,6 +131,12 @@ impl EventListener<i64, InventoryStream> for InventoriesReadModelProjection {
 
     async fn handle(&self, event: PersistedEvent<i64, InventoryStream>) -> Result<(), Self::Error> {
         let last_event_id = event.id();
+        let Some(mut tx) =
+            ProjectionTransaction::begin_in_order(&self.pool, self.id, &event, "product_id")
+                .await?
+        else {
+            return Ok(());
+        };
         match event.into_inner() {
             InventoryStream::InventoryChanged {
                 product_id,
@@ -92,18 +148,38 @@ impl EventListener<i64, InventoryStream> for InventoriesReadModelProjection {
                      ON CONFLICT(product_id)
                      DO UPDATE SET
                        inventory = $2,
-                       last_event_id = $3
-                       WHERE inventories.last_event_id < $3;"#,
+                       last_event_id = $3;"#,
                     product_id as ProductId,
                     inventory,
                     last_event_id
                 )
-                .execute(&self.pool)
+                .execute(&mut *tx)
                 .await
                 .inspect_err(|e| error!("InventoriesReadModelProjection: Failed to handle InventoryChanged event {last_event_id} due to {e}"))?;
             }
         }
 
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl RebuildableProjection<InventoryStream> for InventoriesReadModelProjection {
+    const TABLES: &'static [&'static str] = &["inventories"];
+
+    fn shadow(&self, pool: PgPool) -> Self {
+        Self {
+            id: SHADOW_PROJECTION_ID,
+            pool,
+            ..self.clone()
+        }
+    }
+
+    async fn reset(&self, conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+        sqlx::query!("DELETE FROM inventories")
+            .execute(conn)
+            .await
+            .context("Problem in reset() deleting inventories.")?;
         Ok(())
     }
 }
@@ -113,7 +189,10 @@ impl EventListener<i64, InventoryStream> for InventoriesReadModelProjection {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::{cart::ChangeInventoryCommand, create_eventstore_and_decider};
+    use crate::domain::{
+        EventArchive, EventCodec, EventSerde, KeyStore, cart::ChangeInventoryCommand,
+        create_eventstore_and_decider, rebuild_projection,
+    };
     use fake::Fake;
 
     #[sqlx::test]
@@ -169,4 +248,41 @@ mod tests {
 
         assert_eq!(expected_inventory, found_inventory);
     }
+
+    #[sqlx::test]
+    async fn rebuilt_inventories_are_replayed_from_events(pool: PgPool) {
+        let (event_store, decider) = create_eventstore_and_decider(&pool)
+            .await
+            .expect("EventStore and Decider should be created.");
+        let projection = InventoriesReadModelProjection::new(pool.clone());
+        let key_store = KeyStore::load(&pool).await.unwrap();
+        let archive = EventArchive::new(pool.clone(), EventSerde::new(key_store, EventCodec::Json));
+        let product_id = ProductId::new();
+        decider
+            .make(ChangeInventoryCommand {
+                product_id,
+                inventory: 5,
+            })
+            .await
+            .expect("Command should be successful.");
+        sqlx::query!("UPDATE inventories SET inventory = 0")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let replayed = rebuild_projection(&pool, &event_store, &archive, &projection)
+            .await
+            .expect("Projection should be rebuilt.");
+
+        assert_eq!(replayed, 1);
+        let found_inventory = find_by_id(&pool, &product_id).await.unwrap().unwrap();
+        assert_eq!(found_inventory.inventory, 5);
+        let checkpoint: i64 = sqlx::query_scalar(
+            "SELECT last_processed_event_id FROM event_listener WHERE id = 'inventories'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(checkpoint > 0);
+    }
 }
@@ -0,0 +1,239 @@
+//! Inventory History read model. Every inventory level a product has had, alongside the latest
+//! one kept by the Inventories read model.
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use disintegrate::{EventListener, PersistedEvent, StreamQuery, query};
+use sqlx::{PgConnection, PgPool};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        InventoryStream, MinEventId, ProjectionTransaction, RebuildableProjection,
+        wait_for_projection,
+    },
+    infra::ClientError,
+};
+
+use super::ProductId;
+
+//------------------------- Web API ----------------------------
+
+/// e.g. `GET /inventories/{product_id}/history`
+pub async fn inventory_history_endpoint(
+    State(pool): State<PgPool>,
+    Path(product_uuid): Path<Uuid>,
+    min_event_id: MinEventId,
+) -> Result<Json<Vec<InventoryChange>>, ClientError> {
+    let product_id: ProductId = product_uuid.try_into()?;
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
+    match find_by_product_id(&pool, &product_id).await {
+        Ok(read_model) => Ok(Json(read_model)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+//----------------------- Read Model API ------------------------
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct InventoryChange {
+    pub product_id: ProductId,
+    pub event_id: i64,
+    pub inventory: i32,
+    pub changed_at: jiff::Timestamp,
+}
+
+/// The product's inventory changes, oldest first.
+pub async fn find_by_product_id(
+    pool: &PgPool,
+    product_id: &ProductId,
+) -> Result<Vec<InventoryChange>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT
+           product_id as "product_id: ProductId",
+           event_id,
+           inventory,
+           changed_at as "changed_at: jiff_sqlx::Timestamp"
+           from inventory_history
+           where product_id = $1
+           order by event_id;"#,
+        product_id as &ProductId
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("Problem in find_by_product_id({product_id})"))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| InventoryChange {
+            product_id: row.product_id,
+            event_id: row.event_id,
+            inventory: row.inventory,
+            changed_at: row.changed_at.to_jiff(),
+        })
+        .collect())
+}
+
+//------------------------- Projection --------------------------
+
+pub(super) const PROJECTION_ID: &str = "inventory_history";
+/// Listener id of the shadow copy built by a blue/green rebuild.
+const SHADOW_PROJECTION_ID: &str = "inventory_history_shadow";
+
+fn projection_query() -> StreamQuery<i64, InventoryStream> {
+    query!(InventoryStream)
+}
+
+#[derive(Clone)]
+pub(crate) struct InventoryHistoryProjection {
+    id: &'static str,
+    pool: PgPool,
+    query: StreamQuery<i64, InventoryStream>,
+}
+
+impl InventoryHistoryProjection {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            id: PROJECTION_ID,
+            pool,
+            query: projection_query(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener<i64, InventoryStream> for InventoryHistoryProjection {
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn query(&self) -> &StreamQuery<i64, InventoryStream> {
+        &self.query
+    }
+
+    async fn handle(&self, event: PersistedEvent<i64, InventoryStream>) -> Result<(), Self::Error> {
+        let last_event_id = event.id();
+        let Some(mut tx) = ProjectionTransaction::begin(&self.pool, self.id, last_event_id).await?
+        else {
+            return Ok(());
+        };
+        match event.into_inner() {
+            InventoryStream::InventoryChanged {
+                product_id,
+                inventory,
+            } => {
+                let changed_at = tx.appended_at().await?;
+                save(&mut tx, &product_id, last_event_id, inventory, &changed_at)
+                    .await
+                    .inspect_err(|e| error!("InventoryHistoryProjection: Failed to handle InventoryChanged event {last_event_id} due to {e}"))?;
+            }
+        }
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl RebuildableProjection<InventoryStream> for InventoryHistoryProjection {
+    const TABLES: &'static [&'static str] = &["inventory_history"];
+
+    fn shadow(&self, pool: PgPool) -> Self {
+        Self {
+            id: SHADOW_PROJECTION_ID,
+            pool,
+            ..self.clone()
+        }
+    }
+
+    async fn reset(&self, conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+        sqlx::query!("DELETE FROM inventory_history")
+            .execute(conn)
+            .await
+            .context("Problem in reset() deleting inventory_history.")?;
+        Ok(())
+    }
+}
+
+//--------------------------- SQL -------------------------------
+
+async fn save(
+    conn: &mut PgConnection,
+    product_id: &ProductId,
+    event_id: i64,
+    inventory: i32,
+    changed_at: &jiff_sqlx::Timestamp,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO inventory_history (product_id, event_id, inventory, changed_at)
+           VALUES ($1, $2, $3, $4)"#,
+        product_id as &ProductId,
+        event_id,
+        inventory,
+        changed_at as &jiff_sqlx::Timestamp
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in save(product_id: {product_id}, event_id: {event_id})."))?;
+    Ok(())
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        DomainEvent,
+        helpers::test_events::{append_events, handle_events, set_appended_at},
+    };
+
+    #[sqlx::test]
+    async fn inventory_changes_are_listed_in_order(pool: PgPool) {
+        let projection = InventoryHistoryProjection::new(pool.clone());
+        let (product_id, other_product_id) = (ProductId::new(), ProductId::new());
+        let changed = |product_id, inventory| DomainEvent::InventoryChanged {
+            product_id,
+            inventory,
+        };
+        let events = [
+            ("2026-10-01T09:00:00Z", changed(product_id, 10)),
+            ("2026-10-01T10:00:00Z", changed(other_product_id, 4)),
+            ("2026-10-02T09:00:00Z", changed(product_id, 7)),
+            ("2026-10-03T09:00:00Z", changed(product_id, 0)),
+        ];
+        let (appended_at, events): (Vec<_>, Vec<_>) = events.into_iter().unzip();
+        let events = append_events(&pool, events).await;
+        let event_ids: Vec<_> = events.iter().map(|event| event.id()).collect();
+        for (event, appended_at) in events.iter().zip(&appended_at) {
+            set_appended_at(&pool, event.id(), appended_at.parse().unwrap()).await;
+        }
+        handle_events(&projection, events).await;
+
+        let history = find_by_product_id(&pool, &product_id).await.unwrap();
+
+        let series: Vec<_> = history
+            .iter()
+            .map(|change| {
+                (
+                    change.event_id,
+                    change.inventory,
+                    change.changed_at.to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            series,
+            vec![
+                (event_ids[0], 10, appended_at[0].to_owned()),
+                (event_ids[2], 7, appended_at[2].to_owned()),
+                (event_ids[3], 0, appended_at[3].to_owned()),
+            ]
+        );
+    }
+}
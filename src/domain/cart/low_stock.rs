@@ -0,0 +1,328 @@
+//! Low Stock slice. Detects a product's inventory falling below the configured threshold,
+//! records it as a LowStockDetected event and notifies the `low-stock` Kafka topic.
+//!
+//! The `low_stock_detector` listener dispatches a DetectLowStockCommand for every InventoryChanged
+//! event. The command only emits LowStockDetected when the inventory has crossed the threshold,
+//! i.e. no LowStockDetected has been recorded since the inventory was last at or above it, so
+//! handling an event again emits nothing. The `low_stock_detected` listener then queues a
+//! notification task per LowStockDetected event.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use disintegrate::{
+    Decision, EventListener, PersistedEvent, StateMutate, StateQuery, StreamQuery, query,
+};
+use tracing::error;
+
+use crate::{
+    domain::{
+        Command, CommandBus, CommandOrigin, DomainEvent, InventoryStream, LowStockStream,
+        StockLevelStream,
+        helpers::{PublishError, publish},
+    },
+    infra::KafkaSettings,
+    subsystems::work_queue::{TaskArgs, TaskDomainArgs, TaskLimit, TaskTrigger, WorkQueue},
+};
+
+use super::{CartError, ProductId};
+
+//------------------------- Command ----------------------------
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DetectLowStockCommand {
+    pub product_id: ProductId,
+    pub threshold: i32,
+}
+
+impl Command for DetectLowStockCommand {
+    fn origins(&self) -> &'static [CommandOrigin] {
+        &[CommandOrigin::Processor]
+    }
+}
+
+impl Decision for DetectLowStockCommand {
+    type Event = DomainEvent;
+    type StateQuery = LowStockState;
+    type Error = CartError;
+
+    fn state_query(&self) -> Self::StateQuery {
+        LowStockState {
+            product_id: self.product_id,
+            inventory: None,
+            reported_below: None,
+        }
+    }
+
+    fn process(&self, state: &Self::StateQuery) -> Result<Vec<Self::Event>, Self::Error> {
+        match state.inventory {
+            Some(inventory) if inventory < self.threshold && state.reported_below.is_none() => {
+                Ok(vec![DomainEvent::LowStockDetected {
+                    product_id: self.product_id,
+                    inventory,
+                    threshold: self.threshold,
+                }])
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+//---------------------- Command State --------------------------
+
+#[derive(Clone, Debug, PartialEq, Eq, StateQuery, serde::Serialize, serde::Deserialize)]
+#[state_query(StockLevelStream)]
+pub struct LowStockState {
+    #[id]
+    product_id: ProductId,
+    inventory: Option<i32>,
+    /// The threshold of the LowStockDetected event recorded since the inventory was last at or
+    /// above it.
+    reported_below: Option<i32>,
+}
+
+impl StateMutate for LowStockState {
+    fn mutate(&mut self, event: Self::Event) {
+        match event {
+            StockLevelStream::InventoryChanged { inventory, .. } => {
+                self.inventory = Some(inventory);
+                if self
+                    .reported_below
+                    .is_some_and(|threshold| inventory >= threshold)
+                {
+                    self.reported_below = None;
+                }
+            }
+            StockLevelStream::LowStockDetected { threshold, .. } => {
+                self.reported_below = Some(threshold);
+            }
+        }
+    }
+}
+
+//------------ Event Handler for triggering Command -------------
+
+pub struct LowStockDetector {
+    query: StreamQuery<i64, InventoryStream>,
+    command_bus: CommandBus,
+    threshold: i32,
+}
+
+impl LowStockDetector {
+    pub fn new(command_bus: CommandBus, threshold: i32) -> Self {
+        Self {
+            query: query!(InventoryStream),
+            command_bus,
+            threshold,
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener<i64, InventoryStream> for LowStockDetector {
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        "low_stock_detector"
+    }
+
+    fn query(&self) -> &StreamQuery<i64, InventoryStream> {
+        &self.query
+    }
+
+    async fn handle(&self, event: PersistedEvent<i64, InventoryStream>) -> Result<(), Self::Error> {
+        let event_id = event.id();
+        match event.into_inner() {
+            InventoryStream::InventoryChanged { product_id, .. } => {
+                let decision = DetectLowStockCommand {
+                    product_id,
+                    threshold: self.threshold,
+                };
+                self.command_bus
+                    .dispatch(decision, CommandOrigin::Processor)
+                    .await
+                    .inspect_err(|e| error!("LowStockDetector: DetectLowStockCommand failed for event {event_id} with {e}"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//------------ Event Handler for triggering Processor -----------
+
+/// Queues a notification for every LowStockDetected event. The work queue only holds one task per
+/// event, so handling an event again queues nothing.
+pub struct LowStockDetectedEventHandler {
+    query: StreamQuery<i64, LowStockStream>,
+    queue: WorkQueue,
+}
+
+impl LowStockDetectedEventHandler {
+    pub fn new(queue: WorkQueue) -> Self {
+        Self {
+            queue,
+            query: query!(LowStockStream),
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener<i64, LowStockStream> for LowStockDetectedEventHandler {
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        "low_stock_detected"
+    }
+
+    fn query(&self) -> &StreamQuery<i64, LowStockStream> {
+        &self.query
+    }
+
+    async fn handle(&self, event: PersistedEvent<i64, LowStockStream>) -> Result<(), Self::Error> {
+        let event_id = event.id();
+        match event.into_inner() {
+            LowStockStream::LowStockDetected {
+                product_id,
+                inventory,
+                threshold,
+            } => {
+                let task_args = TaskArgs {
+                    trigger: TaskTrigger::Event(event_id),
+                    limits: TaskLimit::TimeoutAfter(Duration::from_secs(3600)),
+                    domain_args: TaskDomainArgs::NotifyLowStock(LowStockNotification {
+                        low_stock_event_id: event_id,
+                        product_id,
+                        inventory,
+                        threshold,
+                    }),
+                };
+
+                self.queue
+                    .push(task_args)
+                    .await
+                    .inspect_err(|e| error!("LowStockDetectedEventHandler: Failed to queue NotifyLowStock task for event {event_id} due to {e}."))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------- Processor -----------------------------
+
+/// The message published to the `low-stock` topic. Consumers deduplicate by `low_stock_event_id`.
+#[derive(Debug, Clone, Hash, serde::Serialize, serde::Deserialize)]
+pub struct LowStockNotification {
+    pub low_stock_event_id: i64,
+    pub product_id: ProductId,
+    pub inventory: i32,
+    pub threshold: i32,
+}
+
+pub async fn notify_low_stock_processor(
+    settings: &KafkaSettings,
+    notification: LowStockNotification,
+) -> Result<(), PublishError> {
+    publish(settings, "low-stock", &notification).await
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disintegrate::TestHarness;
+
+    #[test]
+    fn low_stock_should_be_detected_when_inventory_falls_below_threshold() {
+        let product_id = ProductId::new();
+
+        TestHarness::given([
+            DomainEvent::InventoryChanged {
+                product_id,
+                inventory: 12,
+            },
+            DomainEvent::InventoryChanged {
+                product_id,
+                inventory: 4,
+            },
+        ])
+        .when(DetectLowStockCommand {
+            product_id,
+            threshold: 10,
+        })
+        .then([DomainEvent::LowStockDetected {
+            product_id,
+            inventory: 4,
+            threshold: 10,
+        }]);
+    }
+
+    #[test]
+    fn low_stock_should_only_be_detected_once_until_restocked() {
+        let product_id = ProductId::new();
+        let low_stock = DomainEvent::LowStockDetected {
+            product_id,
+            inventory: 4,
+            threshold: 10,
+        };
+
+        TestHarness::given([
+            DomainEvent::InventoryChanged {
+                product_id,
+                inventory: 4,
+            },
+            low_stock.clone(),
+            DomainEvent::InventoryChanged {
+                product_id,
+                inventory: 2,
+            },
+        ])
+        .when(DetectLowStockCommand {
+            product_id,
+            threshold: 10,
+        })
+        .then([]);
+
+        TestHarness::given([
+            DomainEvent::InventoryChanged {
+                product_id,
+                inventory: 4,
+            },
+            low_stock,
+            DomainEvent::InventoryChanged {
+                product_id,
+                inventory: 20,
+            },
+            DomainEvent::InventoryChanged {
+                product_id,
+                inventory: 3,
+            },
+        ])
+        .when(DetectLowStockCommand {
+            product_id,
+            threshold: 10,
+        })
+        .then([DomainEvent::LowStockDetected {
+            product_id,
+            inventory: 3,
+            threshold: 10,
+        }]);
+    }
+
+    #[test]
+    fn no_low_stock_should_be_detected_at_or_above_threshold() {
+        let product_id = ProductId::new();
+
+        TestHarness::given([DomainEvent::InventoryChanged {
+            product_id,
+            inventory: 10,
+        }])
+        .when(DetectLowStockCommand {
+            product_id,
+            threshold: 10,
+        })
+        .then([]);
+    }
+}
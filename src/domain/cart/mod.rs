@@ -1,24 +1,53 @@
 mod add_item;
 mod archive_item;
+mod cart_events;
+mod cart_item_search;
 mod cart_items;
 mod cart_items_from_db;
+mod cart_overview;
 mod carts_with_products;
 mod change_inventory;
 mod change_price;
 mod clear_cart;
+mod dry_run;
 mod errors;
+mod event_feed;
+mod forget_cart;
 mod ids;
 mod inventories;
+mod inventory_history;
+mod low_stock;
+mod price_history;
+mod publication_status;
 mod publish_cart;
+mod rebuild_projections;
 mod remove_item;
+mod republish_cart;
+mod sales_analytics;
 mod submit_cart;
+mod verify;
 
 pub use add_item::{AddItemCommand, AddItemPayload, add_item_endpoint};
-pub use archive_item::archive_product_processor;
-pub use cart_items::{CartItem, CartItemsReadModel, cart_items_endpoint, cart_items_read_model};
+pub use archive_item::{
+    ArchiveProductProcessorArgs, PriceChangedEventHandler, archive_product_processor,
+};
+pub use cart_events::{CartEvent, CartEventsParams, cart_events_endpoint};
+pub(crate) use cart_item_search::CartItemSearchProjection;
+pub use cart_item_search::{
+    CartItemMatch, CartItemSearchPage, CartItemSearchParams, SearchCursor,
+    cart_item_search_endpoint, search_cart_items,
+};
+pub use cart_items::{
+    CartItem, CartItemsParams, CartItemsReadModel, archived_cart_items_read_model,
+    cart_items_endpoint, cart_items_read_model,
+};
 pub use cart_items_from_db::{
     CartItemsReadModelProjection, cart_items_from_db_endpoint, cart_items_from_db_read_model,
-    cart_items_from_db_read_model_reset,
+};
+pub(crate) use cart_overview::CartOverviewProjection;
+pub use cart_overview::{
+    CartOverview, CartOverviewFilter, CartOverviewPage, CartOverviewSort, CartStatus, Cursor,
+    SortOrder, cart_overview_endpoint, find_cart_overviews,
 };
 pub(crate) use carts_with_products::CartsWithProductsReadModelProjection;
 pub use carts_with_products::{CartsWithProductsReadModel, carts_with_products_endpoint};
@@ -31,13 +60,42 @@ pub use change_price::{
     change_price_endpoint,
 };
 pub use clear_cart::clear_cart_endpoint;
+pub use dry_run::{DRY_RUN_COMMANDS, dry_run_command};
 pub use errors::CartError;
+pub use event_feed::{
+    FEED_EVENT_TYPES, FeedEvent, FeedEventData, FeedFilter, FeedPage, FeedParams, FeedStream,
+    event_feed_endpoint, read_feed,
+};
+pub use forget_cart::{CartForgottenEventHandler, ForgetCartCommand, forget_cart_endpoint};
 pub use ids::*;
 pub(crate) use inventories::InventoriesReadModelProjection;
-pub use inventories::{InventoriesReadModel, inventories_endpoint};
+pub use inventories::{InventoriesReadModel, inventories_endpoint, low_stock_endpoint};
+pub(crate) use inventory_history::InventoryHistoryProjection;
+pub use inventory_history::{InventoryChange, inventory_history_endpoint};
+pub use low_stock::{
+    DetectLowStockCommand, LowStockDetectedEventHandler, LowStockDetector, LowStockNotification,
+    notify_low_stock_processor,
+};
+pub(crate) use price_history::PriceHistoryProjection;
+pub use price_history::{PriceChange, price_history_endpoint};
+pub(crate) use publication_status::PublicationStatusProjection;
+pub use publication_status::{CartPublication, PublicationStatus, publication_status_endpoint};
 pub use publish_cart::{
     CartSubmittedEventHandler, ExternalPublishCart, OrderedProduct, PublishCartProcessorArgs,
     publish_cart_processor,
 };
+pub use rebuild_projections::{
+    REBUILDABLE_PROJECTIONS, RebuildResult, rebuild_projection_by_id, rebuild_projection_endpoint,
+};
 pub use remove_item::{RemoveItemCommand, remove_item_endpoint};
+pub use republish_cart::{
+    CartRepublishRequestedEventHandler, RepublishCartCommand, republish_cart_endpoint,
+};
+pub(crate) use sales_analytics::SalesAnalyticsProjection;
+pub use sales_analytics::{
+    DailySales, DateRange, ProductSales, RankProductsBy, SalesSummary, TopProductsParams,
+    daily_sales, daily_sales_endpoint, sales_summary, sales_summary_endpoint, top_products,
+    top_products_endpoint,
+};
 pub use submit_cart::{SubmitCartCommand, submit_cart_endpoint};
+pub use verify::{CartViolation, verify_carts};
@@ -0,0 +1,244 @@
+//! Price History read model. Every price change of a product, whether it came through Kafka or
+//! the `changeprice` endpoint, so that merchandisers can audit them.
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use disintegrate::{EventListener, PersistedEvent, StreamQuery, query};
+use rust_decimal::Decimal;
+use sqlx::{PgConnection, PgPool};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        MinEventId, PricingStream, ProjectionTransaction, RebuildableProjection,
+        wait_for_projection,
+    },
+    infra::ClientError,
+};
+
+use super::ProductId;
+
+//------------------------- Web API ----------------------------
+
+/// e.g. `GET /products/{product_id}/prices`
+pub async fn price_history_endpoint(
+    State(pool): State<PgPool>,
+    Path(product_uuid): Path<Uuid>,
+    min_event_id: MinEventId,
+) -> Result<Json<Vec<PriceChange>>, ClientError> {
+    let product_id: ProductId = product_uuid.try_into()?;
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
+    match find_by_product_id(&pool, &product_id).await {
+        Ok(read_model) => Ok(Json(read_model)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+//----------------------- Read Model API ------------------------
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PriceChange {
+    pub product_id: ProductId,
+    pub event_id: i64,
+    pub old_price: Decimal,
+    pub new_price: Decimal,
+    pub changed_at: jiff::Timestamp,
+}
+
+/// The product's price changes, oldest first.
+pub async fn find_by_product_id(
+    pool: &PgPool,
+    product_id: &ProductId,
+) -> Result<Vec<PriceChange>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT
+           product_id as "product_id: ProductId",
+           event_id,
+           old_price,
+           new_price,
+           changed_at as "changed_at: jiff_sqlx::Timestamp"
+           from price_history
+           where product_id = $1
+           order by event_id;"#,
+        product_id as &ProductId
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("Problem in find_by_product_id({product_id})"))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| PriceChange {
+            product_id: row.product_id,
+            event_id: row.event_id,
+            old_price: row.old_price,
+            new_price: row.new_price,
+            changed_at: row.changed_at.to_jiff(),
+        })
+        .collect())
+}
+
+//------------------------- Projection --------------------------
+
+pub(super) const PROJECTION_ID: &str = "price_history";
+/// Listener id of the shadow copy built by a blue/green rebuild.
+const SHADOW_PROJECTION_ID: &str = "price_history_shadow";
+
+fn projection_query() -> StreamQuery<i64, PricingStream> {
+    query!(PricingStream)
+}
+
+#[derive(Clone)]
+pub(crate) struct PriceHistoryProjection {
+    id: &'static str,
+    pool: PgPool,
+    query: StreamQuery<i64, PricingStream>,
+}
+
+impl PriceHistoryProjection {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            id: PROJECTION_ID,
+            pool,
+            query: projection_query(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener<i64, PricingStream> for PriceHistoryProjection {
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn query(&self) -> &StreamQuery<i64, PricingStream> {
+        &self.query
+    }
+
+    async fn handle(&self, event: PersistedEvent<i64, PricingStream>) -> Result<(), Self::Error> {
+        let last_event_id = event.id();
+        let Some(mut tx) = ProjectionTransaction::begin(&self.pool, self.id, last_event_id).await?
+        else {
+            return Ok(());
+        };
+        match event.into_inner() {
+            PricingStream::PriceChanged {
+                product_id,
+                old_price,
+                new_price,
+            } => {
+                let changed_at = tx.appended_at().await?;
+                save(
+                    &mut tx,
+                    &product_id,
+                    last_event_id,
+                    old_price,
+                    new_price,
+                    &changed_at,
+                )
+                .await
+                .inspect_err(|e| error!("PriceHistoryProjection: Failed to handle PriceChanged event {last_event_id} due to {e}"))?;
+            }
+        }
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl RebuildableProjection<PricingStream> for PriceHistoryProjection {
+    const TABLES: &'static [&'static str] = &["price_history"];
+
+    fn shadow(&self, pool: PgPool) -> Self {
+        Self {
+            id: SHADOW_PROJECTION_ID,
+            pool,
+            ..self.clone()
+        }
+    }
+
+    async fn reset(&self, conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+        sqlx::query!("DELETE FROM price_history")
+            .execute(conn)
+            .await
+            .context("Problem in reset() deleting price_history.")?;
+        Ok(())
+    }
+}
+
+//--------------------------- SQL -------------------------------
+
+async fn save(
+    conn: &mut PgConnection,
+    product_id: &ProductId,
+    event_id: i64,
+    old_price: Decimal,
+    new_price: Decimal,
+    changed_at: &jiff_sqlx::Timestamp,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO price_history (product_id, event_id, old_price, new_price, changed_at)
+           VALUES ($1, $2, $3, $4, $5)"#,
+        product_id as &ProductId,
+        event_id,
+        old_price,
+        new_price,
+        changed_at as &jiff_sqlx::Timestamp
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in save(product_id: {product_id}, event_id: {event_id})."))?;
+    Ok(())
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{DomainEvent, helpers::test_events::append_and_handle};
+
+    #[sqlx::test]
+    async fn price_changes_are_listed_in_order(pool: PgPool) {
+        let projection = PriceHistoryProjection::new(pool.clone());
+        let (product_id, other_product_id) = (ProductId::new(), ProductId::new());
+        let changes = [
+            (product_id, 10, 12),
+            (other_product_id, 4, 5),
+            (product_id, 12, 9),
+        ];
+        let event_ids = append_and_handle(
+            &pool,
+            &projection,
+            changes.map(
+                |(product_id, old_price, new_price)| DomainEvent::PriceChanged {
+                    product_id,
+                    old_price: Decimal::from(old_price),
+                    new_price: Decimal::from(new_price),
+                },
+            ),
+        )
+        .await;
+
+        let history = find_by_product_id(&pool, &product_id).await.unwrap();
+
+        let series: Vec<_> = history
+            .iter()
+            .map(|change| (change.event_id, change.old_price, change.new_price))
+            .collect();
+        assert_eq!(
+            series,
+            vec![
+                (event_ids[0], Decimal::from(10), Decimal::from(12)),
+                (event_ids[2], Decimal::from(12), Decimal::from(9)),
+            ]
+        );
+        assert!(history[0].changed_at < history[1].changed_at);
+    }
+}
@@ -0,0 +1,325 @@
+//! Publication Status read model. Whether each submitted cart has been published to the
+//! `published-carts` topic, and how many times publication has been attempted.
+
+use anyhow::{Context, anyhow};
+use async_trait::async_trait;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use disintegrate::{EventListener, PersistedEvent, StreamQuery, query};
+use sqlx::{PgConnection, PgPool};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        MinEventId, ProjectionTransaction, PublicationStream, RebuildableProjection,
+        wait_for_projection,
+    },
+    infra::ClientError,
+};
+
+use super::CartId;
+
+//------------------------- Web API ----------------------------
+
+/// e.g. `GET /carts/{cart_id}/publication`
+pub async fn publication_status_endpoint(
+    State(pool): State<PgPool>,
+    Path(cart_uuid): Path<Uuid>,
+    min_event_id: MinEventId,
+) -> Result<Json<Option<CartPublication>>, ClientError> {
+    let cart_id: CartId = cart_uuid.try_into()?;
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
+    match find_by_cart_id(&pool, &cart_id).await {
+        Ok(read_model) => Ok(Json(read_model)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+//----------------------- Read Model API ------------------------
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, strum_macros::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum PublicationStatus {
+    /// Submitted or republished, and not yet published or failed.
+    Pending,
+    Published,
+    /// Can be republished.
+    Failed,
+}
+
+impl PublicationStatus {
+    pub const ALL: [PublicationStatus; 3] = [
+        PublicationStatus::Pending,
+        PublicationStatus::Published,
+        PublicationStatus::Failed,
+    ];
+
+    fn parse(status: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|known| known.to_string() == status)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CartPublication {
+    pub cart_id: CartId,
+    pub status: PublicationStatus,
+    /// The submission plus each republish.
+    pub attempts: i32,
+    pub submitted_at: jiff::Timestamp,
+    pub updated_at: jiff::Timestamp,
+    pub last_event_id: i64,
+}
+
+pub async fn find_by_cart_id(
+    pool: &PgPool,
+    cart_id: &CartId,
+) -> Result<Option<CartPublication>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT
+           cart_id as "cart_id: CartId",
+           status,
+           attempts,
+           submitted_at as "submitted_at: jiff_sqlx::Timestamp",
+           updated_at as "updated_at: jiff_sqlx::Timestamp",
+           last_event_id
+           from cart_publication
+           where cart_id = $1;"#,
+        cart_id as &CartId
+    )
+    .fetch_optional(pool)
+    .await
+    .with_context(|| format!("Problem in find_by_cart_id({cart_id})"))?;
+
+    row.map(|row| {
+        Ok(CartPublication {
+            cart_id: row.cart_id,
+            status: PublicationStatus::parse(&row.status).ok_or_else(|| {
+                anyhow!(
+                    "Problem in find_by_cart_id({cart_id}). Unknown status {}.",
+                    row.status
+                )
+            })?,
+            attempts: row.attempts,
+            submitted_at: row.submitted_at.to_jiff(),
+            updated_at: row.updated_at.to_jiff(),
+            last_event_id: row.last_event_id,
+        })
+    })
+    .transpose()
+}
+
+//------------------------- Projection --------------------------
+
+pub(super) const PROJECTION_ID: &str = "publication_status";
+/// Listener id of the shadow copy built by a blue/green rebuild.
+const SHADOW_PROJECTION_ID: &str = "publication_status_shadow";
+
+fn projection_query() -> StreamQuery<i64, PublicationStream> {
+    query!(PublicationStream)
+}
+
+#[derive(Clone)]
+pub(crate) struct PublicationStatusProjection {
+    id: &'static str,
+    pool: PgPool,
+    query: StreamQuery<i64, PublicationStream>,
+}
+
+impl PublicationStatusProjection {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            id: PROJECTION_ID,
+            pool,
+            query: projection_query(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener<i64, PublicationStream> for PublicationStatusProjection {
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn query(&self) -> &StreamQuery<i64, PublicationStream> {
+        &self.query
+    }
+
+    async fn handle(
+        &self,
+        event: PersistedEvent<i64, PublicationStream>,
+    ) -> Result<(), Self::Error> {
+        let last_event_id = event.id();
+        let Some(mut tx) =
+            ProjectionTransaction::begin_in_order(&self.pool, self.id, &event, "cart_id").await?
+        else {
+            return Ok(());
+        };
+        let event = event.into_inner();
+        let at = tx.appended_at().await?;
+        let change = PublicationChange { at, last_event_id };
+        match &event {
+            PublicationStream::CartSubmitted { cart_id, .. } => {
+                add_cart(&mut tx, cart_id, &change).await
+            }
+            PublicationStream::CartPublished { cart_id } => {
+                update_status(&mut tx, cart_id, PublicationStatus::Published, 0, &change).await
+            }
+            PublicationStream::CartPublicationFailed { cart_id } => {
+                update_status(&mut tx, cart_id, PublicationStatus::Failed, 0, &change).await
+            }
+            PublicationStream::CartRepublishRequested { cart_id, .. } => {
+                update_status(&mut tx, cart_id, PublicationStatus::Pending, 1, &change).await
+            }
+        }
+        .inspect_err(|e| error!("PublicationStatusProjection: Failed handling event ({last_event_id})\n{event:?}\nfailed with {e}"))?;
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl RebuildableProjection<PublicationStream> for PublicationStatusProjection {
+    const TABLES: &'static [&'static str] = &["cart_publication"];
+
+    fn shadow(&self, pool: PgPool) -> Self {
+        Self {
+            id: SHADOW_PROJECTION_ID,
+            pool,
+            ..self.clone()
+        }
+    }
+
+    async fn reset(&self, conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+        sqlx::query!("DELETE FROM cart_publication")
+            .execute(conn)
+            .await
+            .context("Problem in reset() deleting cart_publication.")?;
+        Ok(())
+    }
+}
+
+/// When, and by which event, a cart's publication changed.
+struct PublicationChange {
+    at: jiff_sqlx::Timestamp,
+    last_event_id: i64,
+}
+
+//--------------------------- SQL -------------------------------
+
+async fn add_cart(
+    conn: &mut PgConnection,
+    cart_id: &CartId,
+    change: &PublicationChange,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO cart_publication
+              (cart_id, status, attempts, submitted_at, updated_at, last_event_id)
+           VALUES ($1, $2, 1, $3, $3, $4)"#,
+        cart_id as &CartId,
+        PublicationStatus::Pending.to_string(),
+        &change.at as &jiff_sqlx::Timestamp,
+        change.last_event_id
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in add_cart({cart_id})."))?;
+    Ok(())
+}
+
+/// Sets the cart's status, counting `new_attempts` more attempts.
+async fn update_status(
+    conn: &mut PgConnection,
+    cart_id: &CartId,
+    status: PublicationStatus,
+    new_attempts: i32,
+    change: &PublicationChange,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE cart_publication SET
+              status = $2,
+              attempts = attempts + $3,
+              updated_at = $4,
+              last_event_id = $5
+           WHERE cart_id = $1"#,
+        cart_id as &CartId,
+        status.to_string(),
+        new_attempts,
+        &change.at as &jiff_sqlx::Timestamp,
+        change.last_event_id
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in update_status(cart_id: {cart_id}, status: {status})."))?;
+    Ok(())
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        DomainEvent,
+        helpers::test_events::{append_events, handle_events},
+    };
+    use rust_decimal::Decimal;
+
+    #[sqlx::test]
+    async fn publication_is_tracked_through_republish(pool: PgPool) {
+        let projection = PublicationStatusProjection::new(pool.clone());
+        let (republished, unsubmitted) = (CartId::new(), CartId::new());
+        let events = append_events(
+            &pool,
+            [
+                DomainEvent::CartSubmitted {
+                    cart_id: republished,
+                    ordered_product: Vec::new(),
+                    total_price: Decimal::ZERO,
+                },
+                DomainEvent::CartPublicationFailed {
+                    cart_id: republished,
+                },
+                DomainEvent::CartRepublishRequested {
+                    cart_id: republished,
+                    ordered_product: Vec::new(),
+                    total_price: Decimal::ZERO,
+                },
+            ],
+        )
+        .await;
+        let last_event_id = events.last().unwrap().id();
+        let mut statuses = Vec::new();
+        for event in events {
+            handle_events(&projection, vec![event]).await;
+            let publication = find_by_cart_id(&pool, &republished)
+                .await
+                .unwrap()
+                .expect("Submitted cart should have a publication status.");
+            statuses.push((publication.status, publication.attempts));
+        }
+
+        let publication = find_by_cart_id(&pool, &republished).await.unwrap().unwrap();
+
+        assert_eq!(
+            statuses,
+            vec![
+                (PublicationStatus::Pending, 1),
+                (PublicationStatus::Failed, 1),
+                (PublicationStatus::Pending, 2),
+            ]
+        );
+        assert_eq!(publication.last_event_id, last_event_id);
+        assert!(publication.submitted_at < publication.updated_at);
+        assert_eq!(find_by_cart_id(&pool, &unsubmitted).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Rebuild Projections slice. Replays the events into a fresh copy of a projected read model and
+//! swaps it in once it has caught up, e.g. after a bug in the projection has been fixed.
+
+use anyhow::bail;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use sqlx::PgPool;
+
+use crate::{
+    domain::{EventArchive, EventStore, rebuild_projection},
+    infra::ClientError,
+};
+
+use super::{
+    CartItemSearchProjection, CartItemsReadModelProjection, CartOverviewProjection,
+    CartsWithProductsReadModelProjection, InventoriesReadModelProjection,
+    InventoryHistoryProjection, PriceHistoryProjection, PublicationStatusProjection,
+    SalesAnalyticsProjection, cart_item_search, cart_items_from_db, cart_overview,
+    carts_with_products, inventories, inventory_history, price_history, publication_status,
+    sales_analytics,
+};
+
+/// The ids of the projections that can be rebuilt.
+pub const REBUILDABLE_PROJECTIONS: [&str; 9] = [
+    cart_item_search::PROJECTION_ID,
+    cart_items_from_db::PROJECTION_ID,
+    cart_overview::PROJECTION_ID,
+    carts_with_products::PROJECTION_ID,
+    inventories::PROJECTION_ID,
+    inventory_history::PROJECTION_ID,
+    price_history::PROJECTION_ID,
+    publication_status::PROJECTION_ID,
+    sales_analytics::PROJECTION_ID,
+];
+
+//------------------------- Web API ----------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RebuildResult {
+    pub projection_id: String,
+    pub events_replayed: u64,
+}
+
+/// e.g. `POST /admin/projections/inventories/rebuild`
+pub async fn rebuild_projection_endpoint(
+    State(pool): State<PgPool>,
+    State(event_store): State<EventStore>,
+    State(event_archive): State<EventArchive>,
+    Path(projection_id): Path<String>,
+) -> Result<Json<RebuildResult>, ClientError> {
+    if !REBUILDABLE_PROJECTIONS.contains(&projection_id.as_str()) {
+        return Err(ClientError::Payload(format!(
+            "Unknown projection {projection_id}. Expected one of {REBUILDABLE_PROJECTIONS:?}."
+        )));
+    }
+    let events_replayed =
+        rebuild_projection_by_id(&pool, &event_store, &event_archive, &projection_id).await?;
+    Ok(Json(RebuildResult {
+        projection_id,
+        events_replayed,
+    }))
+}
+
+//----------------------- Implementation --------------------------
+
+/// Rebuilds the projection with the given id. Returns the number of events replayed.
+pub async fn rebuild_projection_by_id(
+    pool: &PgPool,
+    event_store: &EventStore,
+    event_archive: &EventArchive,
+    projection_id: &str,
+) -> Result<u64, anyhow::Error> {
+    match projection_id {
+        cart_item_search::PROJECTION_ID => {
+            let projection = CartItemSearchProjection::new(pool.clone());
+            rebuild_projection(pool, event_store, event_archive, &projection).await
+        }
+        cart_items_from_db::PROJECTION_ID => {
+            let projection = CartItemsReadModelProjection::new(pool.clone());
+            rebuild_projection(pool, event_store, event_archive, &projection).await
+        }
+        cart_overview::PROJECTION_ID => {
+            let projection = CartOverviewProjection::new(pool.clone());
+            rebuild_projection(pool, event_store, event_archive, &projection).await
+        }
+        carts_with_products::PROJECTION_ID => {
+            let projection = CartsWithProductsReadModelProjection::new(pool.clone());
+            rebuild_projection(pool, event_store, event_archive, &projection).await
+        }
+        inventories::PROJECTION_ID => {
+            let projection = InventoriesReadModelProjection::new(pool.clone());
+            rebuild_projection(pool, event_store, event_archive, &projection).await
+        }
+        inventory_history::PROJECTION_ID => {
+            let projection = InventoryHistoryProjection::new(pool.clone());
+            rebuild_projection(pool, event_store, event_archive, &projection).await
+        }
+        price_history::PROJECTION_ID => {
+            let projection = PriceHistoryProjection::new(pool.clone());
+            rebuild_projection(pool, event_store, event_archive, &projection).await
+        }
+        publication_status::PROJECTION_ID => {
+            let projection = PublicationStatusProjection::new(pool.clone());
+            rebuild_projection(pool, event_store, event_archive, &projection).await
+        }
+        sales_analytics::PROJECTION_ID => {
+            let projection = SalesAnalyticsProjection::new(pool.clone());
+            rebuild_projection(pool, event_store, event_archive, &projection).await
+        }
+        _ => bail!(
+            "Unknown projection {projection_id}. Expected one of {REBUILDABLE_PROJECTIONS:?}."
+        ),
+    }
+}
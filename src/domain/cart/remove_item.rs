@@ -2,12 +2,15 @@ use anyhow::Context;
 use axum::{
     Json,
     extract::{Path, State},
+    response::{IntoResponse, Response},
 };
 use disintegrate::{Decision, StateMutate, StateQuery};
 use uuid::Uuid;
 
 use crate::{
-    domain::{CartStream, DecisionMaker, DomainEvent},
+    domain::{
+        CartStream, Command, CommandBus, CommandOrigin, DomainEvent, DryRunParam, ExpectedEventId,
+    },
     infra::ClientError,
 };
 
@@ -22,10 +25,12 @@ pub struct RemoveItemPayload {
 }
 
 pub async fn remove_item_endpoint(
-    State(decider): State<DecisionMaker>,
+    State(command_bus): State<CommandBus>,
     Path(cart_uuid): Path<Uuid>,
+    dry_run: DryRunParam,
+    expected_event_id: ExpectedEventId,
     Json(payload): Json<RemoveItemPayload>,
-) -> Result<Json<(Uuid, i64)>, ClientError> {
+) -> Result<Response, ClientError> {
     if cart_uuid != payload.cart_id {
         return Err(ClientError::Payload(
             "Path CartId does not match payload CartId.".to_owned(),
@@ -33,7 +38,13 @@ pub async fn remove_item_endpoint(
     }
 
     let decision: RemoveItemCommand = payload.try_into()?;
-    let events = decider.make(decision).await?;
+    if dry_run.0 {
+        return Ok(Json(command_bus.dry_run(decision, expected_event_id).await?).into_response());
+    }
+
+    let events = command_bus
+        .dispatch_expecting(decision, CommandOrigin::Http, expected_event_id)
+        .await?;
 
     let last_event_id = events
         .into_iter()
@@ -41,12 +52,12 @@ pub async fn remove_item_endpoint(
         .map(|e| e.id())
         .context("No event returned for RemoveItemCommand!")?;
 
-    Ok(Json((cart_uuid, last_event_id)))
+    Ok(Json((cart_uuid, last_event_id)).into_response())
 }
 
 //------------------------- Command ----------------------------
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct RemoveItemCommand {
     pub cart_id: CartId,
     pub item_id: ItemId,
@@ -62,6 +73,12 @@ impl TryFrom<RemoveItemPayload> for RemoveItemCommand {
     }
 }
 
+impl Command for RemoveItemCommand {
+    fn cart_id(&self) -> Option<CartId> {
+        Some(self.cart_id)
+    }
+}
+
 impl Decision for RemoveItemCommand {
     type Event = DomainEvent;
     type StateQuery = RemoveItemState;
@@ -74,10 +91,15 @@ impl Decision for RemoveItemCommand {
             cart_exists: false,
             item_exists: false,
             submitted: false,
+            forgotten: false,
         }
     }
 
     fn process(&self, state: &Self::StateQuery) -> Result<Vec<Self::Event>, Self::Error> {
+        if state.forgotten {
+            return Err(CartError::CartForgotten);
+        }
+
         if !state.cart_exists {
             return Err(CartError::CartDoesNotExist(self.cart_id));
         }
@@ -108,6 +130,7 @@ pub struct RemoveItemState {
     cart_exists: bool,
     item_exists: bool,
     submitted: bool,
+    forgotten: bool,
 }
 
 impl StateMutate for RemoveItemState {
@@ -135,6 +158,7 @@ impl StateMutate for RemoveItemState {
                 }
             }
             CartStream::CartSubmitted { .. } => self.submitted = true,
+            CartStream::CartForgotten { .. } => self.forgotten = true,
         }
     }
 }
@@ -203,4 +227,26 @@ mod tests {
             })
             .then_err(CartError::CartDoesNotExist(cart_id));
     }
+
+    #[test]
+    fn item_should_not_be_removed_if_cart_is_forgotten() {
+        let cart_id = CartId::new();
+        let item_id = ItemId::new();
+
+        TestHarness::given([
+            DomainEvent::CartCreated { cart_id },
+            DomainEvent::CartItemAdded {
+                cart_id,
+                description: Faker.fake(),
+                image: Faker.fake(),
+                price: Faker.fake(),
+                item_id,
+                product_id: ProductId::new(),
+                fingerprint: default_fingerprint(),
+            },
+            DomainEvent::CartForgotten { cart_id },
+        ])
+        .when(RemoveItemCommand { cart_id, item_id })
+        .then_err(CartError::CartForgotten);
+    }
 }
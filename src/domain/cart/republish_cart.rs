@@ -0,0 +1,337 @@
+//! Republish Cart slice. Publishes a submitted cart again after its publication failed.
+//!
+//! The RepublishCartCommand records a CartRepublishRequested event, and the
+//! `cart_republish_requested` listener queues a fresh PublishCart task for it. The task is
+//! triggered by the CartRepublishRequested event rather than the CartSubmitted event, so it does
+//! not clash with the cart's original PublishCart task in the work queue.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::{
+    Json,
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+};
+use disintegrate::{
+    Decision, EventListener, PersistedEvent, StateMutate, StateQuery, StreamQuery, query,
+};
+use rust_decimal::Decimal;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        Command, CommandBus, CommandOrigin, DomainEvent, DryRunParam, ExpectedEventId,
+        RepublishCartStream, RepublishStream, events::OrderedProduct,
+    },
+    infra::ClientError,
+    subsystems::work_queue::{TaskArgs, TaskDomainArgs, TaskLimit, TaskTrigger, WorkQueue},
+};
+
+use super::{CartError, CartId, ExternalPublishCart, PublishCartProcessorArgs};
+
+//------------------------- Web API ----------------------------
+
+/// e.g. `POST /republishcart/{cart_id}`
+pub async fn republish_cart_endpoint(
+    State(command_bus): State<CommandBus>,
+    Path(cart_uuid): Path<Uuid>,
+    dry_run: DryRunParam,
+) -> Result<Response, ClientError> {
+    let cart_id = cart_uuid.try_into()?;
+    let decision = RepublishCartCommand { cart_id };
+    if dry_run.0 {
+        return Ok(
+            Json(command_bus.dry_run(decision, ExpectedEventId(None)).await?).into_response(),
+        );
+    }
+
+    let events = command_bus.dispatch(decision, CommandOrigin::Http).await?;
+
+    let last_event_id = events
+        .into_iter()
+        .last()
+        .map(|e| e.id())
+        .context("No event returned for RepublishCartCommand!")?;
+
+    Ok(Json((cart_uuid, last_event_id)).into_response())
+}
+
+//------------------------- Command ----------------------------
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RepublishCartCommand {
+    pub cart_id: CartId,
+}
+
+impl Command for RepublishCartCommand {
+    fn cart_id(&self) -> Option<CartId> {
+        Some(self.cart_id)
+    }
+}
+
+impl Decision for RepublishCartCommand {
+    type Event = DomainEvent;
+    type StateQuery = RepublishCartState;
+    type Error = CartError;
+
+    fn state_query(&self) -> Self::StateQuery {
+        RepublishCartState {
+            cart_id: self.cart_id,
+            submitted: false,
+            forgotten: false,
+            publication_failed: false,
+            ordered_product: Vec::new(),
+            total_price: Decimal::ZERO,
+        }
+    }
+
+    fn process(&self, state: &Self::StateQuery) -> Result<Vec<Self::Event>, Self::Error> {
+        if state.forgotten {
+            return Err(CartError::CartForgotten);
+        }
+
+        if !state.submitted {
+            return Err(CartError::CartNotSubmitted(self.cart_id));
+        }
+        if !state.publication_failed {
+            return Err(CartError::CannotRepublishCart);
+        }
+
+        Ok(vec![DomainEvent::CartRepublishRequested {
+            cart_id: self.cart_id,
+            ordered_product: state.ordered_product.clone(),
+            total_price: state.total_price,
+        }])
+    }
+}
+
+//---------------------- Command State --------------------------
+
+#[derive(Clone, Debug, PartialEq, Eq, StateQuery, serde::Serialize, serde::Deserialize)]
+#[state_query(RepublishCartStream)]
+pub struct RepublishCartState {
+    #[id]
+    cart_id: CartId,
+    submitted: bool,
+    forgotten: bool,
+    /// The last publication failed and no republish has been requested since.
+    publication_failed: bool,
+    ordered_product: Vec<OrderedProduct>,
+    total_price: Decimal,
+}
+
+impl StateMutate for RepublishCartState {
+    fn mutate(&mut self, event: Self::Event) {
+        match event {
+            RepublishCartStream::CartSubmitted {
+                ordered_product,
+                total_price,
+                ..
+            } => {
+                self.submitted = true;
+                self.ordered_product = ordered_product;
+                self.total_price = total_price;
+            }
+            RepublishCartStream::CartPublicationFailed { .. } => self.publication_failed = true,
+            RepublishCartStream::CartForgotten { .. } => self.forgotten = true,
+            RepublishCartStream::CartPublished { .. }
+            | RepublishCartStream::CartRepublishRequested { .. } => self.publication_failed = false,
+        }
+    }
+}
+
+//------------ Event Handler for triggering Processor -----------
+
+pub struct CartRepublishRequestedEventHandler {
+    query: StreamQuery<i64, RepublishStream>,
+    queue: WorkQueue,
+}
+
+impl CartRepublishRequestedEventHandler {
+    pub fn new(queue: WorkQueue) -> Self {
+        Self {
+            queue,
+            query: query!(RepublishStream),
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener<i64, RepublishStream> for CartRepublishRequestedEventHandler {
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        "cart_republish_requested"
+    }
+
+    fn query(&self) -> &StreamQuery<i64, RepublishStream> {
+        &self.query
+    }
+
+    async fn handle(&self, event: PersistedEvent<i64, RepublishStream>) -> Result<(), Self::Error> {
+        let event_id = event.id();
+        match event.into_inner() {
+            RepublishStream::CartRepublishRequested {
+                cart_id,
+                ordered_product,
+                total_price,
+            } => {
+                let task_args = TaskArgs {
+                    trigger: TaskTrigger::Event(event_id),
+                    limits: TaskLimit::TimeoutAfter(Duration::from_secs(3600)),
+                    domain_args: TaskDomainArgs::PublishCart(PublishCartProcessorArgs {
+                        triggering_event_id: event_id,
+                        message: ExternalPublishCart {
+                            cart_id,
+                            ordered_product: ordered_product
+                                .into_iter()
+                                .map(|op| op.into())
+                                .collect(),
+                            total_price,
+                        },
+                    }),
+                };
+
+                self.queue
+                    .push(task_args)
+                    .await
+                    .inspect_err(|e| error!("CartRepublishRequestedEventHandler: Failed to queue PublishCart task for event {event_id} due to {e}."))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{cart::ProductId, helpers::fake::Price};
+    use disintegrate::TestHarness;
+    use fake::Fake;
+    use sqlx::PgPool;
+
+    fn submitted(cart_id: CartId) -> (DomainEvent, Vec<OrderedProduct>, Decimal) {
+        let price = Price.fake();
+        let ordered_product = vec![OrderedProduct {
+            product_id: ProductId::new(),
+            price,
+        }];
+        let event = DomainEvent::CartSubmitted {
+            cart_id,
+            ordered_product: ordered_product.clone(),
+            total_price: price,
+        };
+        (event, ordered_product, price)
+    }
+
+    #[test]
+    fn should_not_republish_if_cart_not_submitted() {
+        let cart_id = CartId::new();
+
+        TestHarness::given([])
+            .when(RepublishCartCommand { cart_id })
+            .then_err(CartError::CartNotSubmitted(cart_id))
+    }
+
+    #[test]
+    fn should_not_republish_unless_publication_failed() {
+        let cart_id = CartId::new();
+        let (submitted, ordered_product, total_price) = submitted(cart_id);
+
+        TestHarness::given([submitted.clone()])
+            .when(RepublishCartCommand { cart_id })
+            .then_err(CartError::CannotRepublishCart);
+
+        TestHarness::given([
+            submitted.clone(),
+            DomainEvent::CartPublicationFailed { cart_id },
+            DomainEvent::CartRepublishRequested {
+                cart_id,
+                ordered_product,
+                total_price,
+            },
+        ])
+        .when(RepublishCartCommand { cart_id })
+        .then_err(CartError::CannotRepublishCart);
+
+        TestHarness::given([submitted, DomainEvent::CartPublished { cart_id }])
+            .when(RepublishCartCommand { cart_id })
+            .then_err(CartError::CannotRepublishCart);
+    }
+
+    #[test]
+    fn should_not_republish_if_cart_is_forgotten() {
+        let cart_id = CartId::new();
+        let (submitted, _, _) = submitted(cart_id);
+
+        TestHarness::given([
+            submitted,
+            DomainEvent::CartPublicationFailed { cart_id },
+            DomainEvent::CartForgotten { cart_id },
+        ])
+        .when(RepublishCartCommand { cart_id })
+        .then_err(CartError::CartForgotten);
+    }
+
+    #[test]
+    fn cart_should_be_republished_after_publication_failed() {
+        let cart_id = CartId::new();
+        let (submitted, ordered_product, total_price) = submitted(cart_id);
+
+        TestHarness::given([submitted, DomainEvent::CartPublicationFailed { cart_id }])
+            .when(RepublishCartCommand { cart_id })
+            .then([DomainEvent::CartRepublishRequested {
+                cart_id,
+                ordered_product,
+                total_price,
+            }]);
+    }
+
+    #[sqlx::test]
+    async fn republish_is_queued_alongside_the_original_publication(pool: PgPool) {
+        let queue = WorkQueue::new(pool.clone());
+        let cart_id = CartId::new();
+        let message = ExternalPublishCart {
+            cart_id,
+            ordered_product: Vec::new(),
+            total_price: Decimal::ZERO,
+        };
+        queue
+            .push(TaskArgs {
+                trigger: TaskTrigger::Event(1),
+                limits: TaskLimit::TimeoutAfter(Duration::from_secs(3600)),
+                domain_args: TaskDomainArgs::PublishCart(PublishCartProcessorArgs {
+                    triggering_event_id: 1,
+                    message,
+                }),
+            })
+            .await
+            .unwrap();
+
+        CartRepublishRequestedEventHandler::new(queue)
+            .handle(PersistedEvent::new(
+                3,
+                RepublishStream::CartRepublishRequested {
+                    cart_id,
+                    ordered_product: Vec::new(),
+                    total_price: Decimal::ZERO,
+                },
+            ))
+            .await
+            .expect("Republish should be queued.");
+
+        let triggering_events: Vec<Option<i64>> = sqlx::query_scalar(
+            "SELECT triggering_event FROM queue WHERE task_type = 'PublishCart' ORDER BY triggering_event",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(triggering_events, vec![Some(1), Some(3)]);
+    }
+}
@@ -0,0 +1,471 @@
+//! Sales Analytics read model. Revenue and orders per day, top products and average cart value,
+//! from the submitted carts. Every figure is aggregated when queried, for an inclusive range of
+//! UTC days, so the projection only records each order and can be rebuilt from history.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, anyhow};
+use async_trait::async_trait;
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use disintegrate::{EventListener, PersistedEvent, StreamQuery, query};
+use jiff_sqlx::ToSqlx;
+use rust_decimal::Decimal;
+use sqlx::{PgConnection, PgPool};
+use tracing::error;
+
+use crate::{
+    domain::{
+        DomainEvent, MinEventId, ProjectionTransaction, PublishedStream, RebuildableProjection,
+        events::{OrderedProduct, SubmittedStream},
+        wait_for_projection,
+    },
+    infra::ClientError,
+};
+
+use super::{CartId, ProductId};
+
+/// Products returned by `top_products` unless a limit is given.
+const DEFAULT_LIMIT: i64 = 10;
+
+/// Most products returned by `top_products`.
+const MAX_LIMIT: i64 = 100;
+
+//------------------------- Web API ----------------------------
+
+/// e.g. `GET /analytics/sales/daily?from=2026-10-01&to=2026-10-31`
+pub async fn daily_sales_endpoint(
+    State(pool): State<PgPool>,
+    Query(range): Query<DateRange>,
+    min_event_id: MinEventId,
+) -> Result<Json<Vec<DailySales>>, ClientError> {
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
+    Ok(Json(daily_sales(&pool, &range).await?))
+}
+
+/// e.g. `GET /analytics/sales/products?from=2026-10-01&by=revenue&limit=5`
+pub async fn top_products_endpoint(
+    State(pool): State<PgPool>,
+    Query(params): Query<TopProductsParams>,
+    min_event_id: MinEventId,
+) -> Result<Json<Vec<ProductSales>>, ClientError> {
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
+    Ok(Json(top_products(&pool, &params).await?))
+}
+
+/// e.g. `GET /analytics/sales/summary?from=2026-10-01&to=2026-10-31`
+pub async fn sales_summary_endpoint(
+    State(pool): State<PgPool>,
+    Query(range): Query<DateRange>,
+    min_event_id: MinEventId,
+) -> Result<Json<SalesSummary>, ClientError> {
+    wait_for_projection(&pool, PROJECTION_ID, &projection_query(), min_event_id).await?;
+    Ok(Json(sales_summary(&pool, &range).await?))
+}
+
+//----------------------- Read Model API ------------------------
+
+/// Inclusive range of UTC days. Either end may be left open.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct DateRange {
+    pub from: Option<jiff::civil::Date>,
+    pub to: Option<jiff::civil::Date>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, strum_macros::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum RankProductsBy {
+    #[default]
+    Quantity,
+    Revenue,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct TopProductsParams {
+    #[serde(flatten)]
+    pub range: DateRange,
+    #[serde(default)]
+    pub by: RankProductsBy,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DailySales {
+    pub date: jiff::civil::Date,
+    pub orders: i64,
+    /// Orders that have also been published.
+    pub published_orders: i64,
+    pub revenue: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ProductSales {
+    pub product_id: ProductId,
+    pub quantity: i64,
+    pub revenue: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SalesSummary {
+    pub orders: i64,
+    pub revenue: Decimal,
+    /// Missing when there were no orders.
+    pub average_cart_value: Option<Decimal>,
+}
+
+/// Orders and revenue for every day in the range that had orders, oldest first.
+pub async fn daily_sales(
+    pool: &PgPool,
+    range: &DateRange,
+) -> Result<Vec<DailySales>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT order_date as "order_date: jiff_sqlx::Date",
+                  count(*) as "orders!",
+                  count(*) FILTER (WHERE published) as "published_orders!",
+                  sum(total_price) as "revenue!"
+           FROM sales_orders
+           WHERE ($1::DATE IS NULL OR order_date >= $1)
+             AND ($2::DATE IS NULL OR order_date <= $2)
+           GROUP BY order_date
+           ORDER BY order_date"#,
+        range.from.map(|from| from.to_sqlx()) as Option<jiff_sqlx::Date>,
+        range.to.map(|to| to.to_sqlx()) as Option<jiff_sqlx::Date>,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("Problem in daily_sales(range: {range:?})."))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| DailySales {
+            date: row.order_date.to_jiff(),
+            orders: row.orders,
+            published_orders: row.published_orders,
+            revenue: row.revenue,
+        })
+        .collect())
+}
+
+/// The best selling products in the range, by quantity or revenue.
+pub async fn top_products(
+    pool: &PgPool,
+    params: &TopProductsParams,
+) -> Result<Vec<ProductSales>, anyhow::Error> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    sqlx::query_as!(
+        ProductSales,
+        r#"SELECT product.product_id as "product_id: ProductId",
+                  sum(product.quantity) as "quantity!",
+                  sum(product.revenue) as "revenue!"
+           FROM sales_order_products product
+           JOIN sales_orders sales_order USING (cart_id)
+           WHERE ($1::DATE IS NULL OR sales_order.order_date >= $1)
+             AND ($2::DATE IS NULL OR sales_order.order_date <= $2)
+           GROUP BY product.product_id
+           ORDER BY CASE WHEN $3 = 'revenue' THEN sum(product.revenue) END DESC NULLS LAST,
+                    sum(product.quantity) DESC,
+                    sum(product.revenue) DESC,
+                    product.product_id
+           LIMIT $4"#,
+        params.range.from.map(|from| from.to_sqlx()) as Option<jiff_sqlx::Date>,
+        params.range.to.map(|to| to.to_sqlx()) as Option<jiff_sqlx::Date>,
+        params.by.to_string(),
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("Problem in top_products(params: {params:?})."))
+}
+
+pub async fn sales_summary(
+    pool: &PgPool,
+    range: &DateRange,
+) -> Result<SalesSummary, anyhow::Error> {
+    sqlx::query_as!(
+        SalesSummary,
+        r#"SELECT count(*) as "orders!",
+                  COALESCE(sum(total_price), 0) as "revenue!",
+                  round(avg(total_price), 2) as average_cart_value
+           FROM sales_orders
+           WHERE ($1::DATE IS NULL OR order_date >= $1)
+             AND ($2::DATE IS NULL OR order_date <= $2)"#,
+        range.from.map(|from| from.to_sqlx()) as Option<jiff_sqlx::Date>,
+        range.to.map(|to| to.to_sqlx()) as Option<jiff_sqlx::Date>,
+    )
+    .fetch_one(pool)
+    .await
+    .with_context(|| format!("Problem in sales_summary(range: {range:?})."))
+}
+
+//------------------------- Projection --------------------------
+
+pub(super) const PROJECTION_ID: &str = "sales_analytics";
+/// Listener id of the shadow copy built by a blue/green rebuild.
+const SHADOW_PROJECTION_ID: &str = "sales_analytics_shadow";
+
+fn projection_query() -> StreamQuery<i64, DomainEvent> {
+    query!(SubmittedStream).union(&query!(PublishedStream))
+}
+
+#[derive(Clone)]
+pub(crate) struct SalesAnalyticsProjection {
+    id: &'static str,
+    pool: PgPool,
+    query: StreamQuery<i64, DomainEvent>,
+}
+
+impl SalesAnalyticsProjection {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            id: PROJECTION_ID,
+            pool,
+            query: projection_query(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener<i64, DomainEvent> for SalesAnalyticsProjection {
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn query(&self) -> &StreamQuery<i64, DomainEvent> {
+        &self.query
+    }
+
+    async fn handle(&self, event: PersistedEvent<i64, DomainEvent>) -> Result<(), Self::Error> {
+        let last_event_id = event.id();
+        let Some(mut tx) =
+            ProjectionTransaction::begin_in_order(&self.pool, self.id, &event, "cart_id").await?
+        else {
+            return Ok(());
+        };
+        let event = event.into_inner();
+        match &event {
+            DomainEvent::CartSubmitted {
+                cart_id,
+                ordered_product,
+                total_price,
+            } => {
+                let submitted_at = tx.appended_at().await?;
+                save_order(&mut tx, cart_id, *total_price, &submitted_at).await?;
+                save_order_products(&mut tx, cart_id, ordered_product).await
+            }
+            DomainEvent::CartPublished { cart_id } => mark_published(&mut tx, cart_id).await,
+            DomainEvent::CartPublicationFailed { .. } => Ok(()),
+            unexpected => Err(anyhow!(
+                "SalesAnalytics projection received unsupported event type {unexpected:?} for event {last_event_id}."
+            )),
+        }
+        .inspect_err(|e| error!("SalesAnalyticsProjection: Failed handling event ({last_event_id})\n{event:?}\nfailed with {e}"))?;
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl RebuildableProjection<DomainEvent> for SalesAnalyticsProjection {
+    const TABLES: &'static [&'static str] = &["sales_orders", "sales_order_products"];
+
+    fn shadow(&self, pool: PgPool) -> Self {
+        Self {
+            id: SHADOW_PROJECTION_ID,
+            pool,
+            ..self.clone()
+        }
+    }
+
+    async fn reset(&self, conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+        sqlx::query!("DELETE FROM sales_order_products")
+            .execute(&mut *conn)
+            .await
+            .context("Problem in reset() deleting sales_order_products.")?;
+        sqlx::query!("DELETE FROM sales_orders")
+            .execute(&mut *conn)
+            .await
+            .context("Problem in reset() deleting sales_orders.")?;
+        Ok(())
+    }
+}
+
+//--------------------------- SQL -------------------------------
+
+async fn save_order(
+    conn: &mut PgConnection,
+    cart_id: &CartId,
+    total_price: Decimal,
+    submitted_at: &jiff_sqlx::Timestamp,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO sales_orders (cart_id, order_date, total_price, published, submitted_at)
+           VALUES ($1, ($3 AT TIME ZONE 'UTC')::DATE, $2, false, $3)"#,
+        cart_id as &CartId,
+        total_price,
+        submitted_at as &jiff_sqlx::Timestamp
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in save_order({cart_id})."))?;
+    Ok(())
+}
+
+/// Records the quantity and revenue of each product, one unit per ordered product.
+async fn save_order_products(
+    conn: &mut PgConnection,
+    cart_id: &CartId,
+    ordered_products: &[OrderedProduct],
+) -> Result<(), anyhow::Error> {
+    let mut products: BTreeMap<ProductId, (i32, Decimal)> = BTreeMap::new();
+    for ordered_product in ordered_products {
+        let (quantity, revenue) = products.entry(ordered_product.product_id).or_default();
+        *quantity += 1;
+        *revenue += ordered_product.price;
+    }
+    for (product_id, (quantity, revenue)) in products {
+        sqlx::query!(
+            r#"INSERT INTO sales_order_products (cart_id, product_id, quantity, revenue)
+               VALUES ($1, $2, $3, $4)"#,
+            cart_id as &CartId,
+            product_id as ProductId,
+            quantity,
+            revenue
+        )
+        .execute(&mut *conn)
+        .await
+        .with_context(|| {
+            format!("Problem in save_order_products(cart_id: {cart_id}, product_id: {product_id}).")
+        })?;
+    }
+    Ok(())
+}
+
+async fn mark_published(conn: &mut PgConnection, cart_id: &CartId) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE sales_orders SET published = true WHERE cart_id = $1",
+        cart_id as &CartId
+    )
+    .execute(conn)
+    .await
+    .with_context(|| format!("Problem in mark_published({cart_id})."))?;
+    Ok(())
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::helpers::test_events::{append_events, handle_events, set_appended_at};
+
+    fn submitted(cart_id: CartId, products: &[(ProductId, i64)]) -> DomainEvent {
+        let ordered_product: Vec<OrderedProduct> = products
+            .iter()
+            .map(|&(product_id, price)| OrderedProduct {
+                product_id,
+                price: price.into(),
+            })
+            .collect();
+        DomainEvent::CartSubmitted {
+            cart_id,
+            total_price: ordered_product.iter().map(|op| op.price).sum(),
+            ordered_product,
+        }
+    }
+
+    #[sqlx::test]
+    async fn sales_are_aggregated_per_day_and_product(pool: PgPool) {
+        let projection = SalesAnalyticsProjection::new(pool.clone());
+        let (socks, shoes) = (ProductId::new(), ProductId::new());
+        let (first, second, third) = (CartId::new(), CartId::new(), CartId::new());
+        let events = [
+            (
+                "2026-10-01T09:00:00Z",
+                submitted(first, &[(socks, 5), (socks, 5), (shoes, 50)]),
+            ),
+            (
+                "2026-10-01T10:00:00Z",
+                DomainEvent::CartPublished { cart_id: first },
+            ),
+            ("2026-10-01T11:00:00Z", submitted(second, &[(socks, 5)])),
+            ("2026-10-02T09:00:00Z", submitted(third, &[(shoes, 40)])),
+        ];
+        let (appended_at, events): (Vec<_>, Vec<_>) = events.into_iter().unzip();
+        let events = append_events(&pool, events).await;
+        for (event, appended_at) in events.iter().zip(appended_at) {
+            set_appended_at(&pool, event.id(), appended_at.parse().unwrap()).await;
+        }
+        handle_events(&projection, events).await;
+        let first_day = DateRange {
+            from: Some(jiff::civil::date(2026, 10, 1)),
+            to: Some(jiff::civil::date(2026, 10, 1)),
+        };
+
+        let daily = daily_sales(&pool, &DateRange::default()).await.unwrap();
+        let by_revenue = top_products(
+            &pool,
+            &TopProductsParams {
+                by: RankProductsBy::Revenue,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let by_quantity = top_products(
+            &pool,
+            &TopProductsParams {
+                range: first_day.clone(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let summary = sales_summary(&pool, &first_day).await.unwrap();
+
+        assert_eq!(
+            daily,
+            vec![
+                DailySales {
+                    date: jiff::civil::date(2026, 10, 1),
+                    orders: 2,
+                    published_orders: 1,
+                    revenue: 65.into(),
+                },
+                DailySales {
+                    date: jiff::civil::date(2026, 10, 2),
+                    orders: 1,
+                    published_orders: 0,
+                    revenue: 40.into(),
+                },
+            ]
+        );
+        assert_eq!(
+            by_revenue,
+            vec![
+                ProductSales {
+                    product_id: shoes,
+                    quantity: 2,
+                    revenue: 90.into(),
+                },
+                ProductSales {
+                    product_id: socks,
+                    quantity: 3,
+                    revenue: 15.into(),
+                },
+            ]
+        );
+        assert_eq!(by_quantity[0].product_id, socks);
+        assert_eq!(
+            summary,
+            SalesSummary {
+                orders: 2,
+                revenue: 65.into(),
+                average_cart_value: Some(Decimal::new(3250, 2)),
+            }
+        );
+    }
+}
@@ -3,13 +3,16 @@
 use anyhow::Context;
 use axum::Json;
 use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Response};
 use disintegrate::{Decision, StateMutate, StateQuery};
 use rust_decimal::Decimal;
 use std::collections::HashMap;
 use uuid::Uuid;
 
 use crate::domain::events::OrderedProduct;
-use crate::domain::{CartStream, DecisionMaker, DomainEvent};
+use crate::domain::{
+    CartStream, Command, CommandBus, CommandOrigin, DomainEvent, DryRunParam, ExpectedEventId,
+};
 use crate::infra::ClientError;
 
 use super::{CartError, CartId, ItemId, ProductId};
@@ -22,10 +25,12 @@ pub struct SubmitCartPayload {
 }
 
 pub async fn submit_cart_endpoint(
-    State(decider): State<DecisionMaker>,
+    State(command_bus): State<CommandBus>,
     Path(cart_id): Path<Uuid>,
+    dry_run: DryRunParam,
+    expected_event_id: ExpectedEventId,
     Json(payload): Json<SubmitCartPayload>,
-) -> Result<Json<(Uuid, i64)>, ClientError> {
+) -> Result<Response, ClientError> {
     if cart_id != payload.cart_id {
         return Err(ClientError::Payload(
             "Path CartId does not match payload CartId.".to_owned(),
@@ -33,7 +38,13 @@ pub async fn submit_cart_endpoint(
     }
 
     let decision: SubmitCartCommand = payload.try_into()?;
-    let events = decider.make(decision).await?;
+    if dry_run.0 {
+        return Ok(Json(command_bus.dry_run(decision, expected_event_id).await?).into_response());
+    }
+
+    let events = command_bus
+        .dispatch_expecting(decision, CommandOrigin::Http, expected_event_id)
+        .await?;
 
     let last_event_id = events
         .into_iter()
@@ -41,12 +52,12 @@ pub async fn submit_cart_endpoint(
         .map(|e| e.id())
         .context("No event returned for SubmitCartCommand!")?;
 
-    Ok(Json((cart_id, last_event_id)))
+    Ok(Json((cart_id, last_event_id)).into_response())
 }
 
 //------------------------- Command ----------------------------
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SubmitCartCommand {
     pub cart_id: CartId,
 }
@@ -60,6 +71,12 @@ impl TryFrom<SubmitCartPayload> for SubmitCartCommand {
     }
 }
 
+impl Command for SubmitCartCommand {
+    fn cart_id(&self) -> Option<CartId> {
+        Some(self.cart_id)
+    }
+}
+
 impl Decision for SubmitCartCommand {
     type Event = DomainEvent;
     type StateQuery = SubmitCartState;
@@ -71,12 +88,17 @@ impl Decision for SubmitCartCommand {
             cart_exists: false,
             item_count: 0,
             submitted: false,
+            forgotten: false,
             cart_items: HashMap::new(),
             product_price: HashMap::new(),
         }
     }
 
     fn process(&self, state: &Self::StateQuery) -> Result<Vec<Self::Event>, Self::Error> {
+        if state.forgotten {
+            return Err(CartError::CartForgotten);
+        }
+
         if !state.cart_exists {
             return Err(CartError::CartDoesNotExist(self.cart_id));
         }
@@ -120,10 +142,17 @@ pub struct SubmitCartState {
     cart_exists: bool,
     item_count: u8,
     submitted: bool,
+    forgotten: bool,
     cart_items: HashMap<ItemId, ProductId>,
     product_price: HashMap<ProductId, Decimal>,
 }
 
+impl SubmitCartState {
+    pub(super) fn item_count(&self) -> u8 {
+        self.item_count
+    }
+}
+
 impl StateMutate for SubmitCartState {
     fn mutate(&mut self, event: Self::Event) {
         match event {
@@ -160,6 +189,9 @@ impl StateMutate for SubmitCartState {
             CartStream::CartSubmitted { .. } => {
                 self.submitted = true;
             }
+            CartStream::CartForgotten { .. } => {
+                self.forgotten = true;
+            }
         }
     }
 }
@@ -242,4 +274,26 @@ mod tests {
         .when(SubmitCartCommand { cart_id })
         .then_err(CartError::CannotSubmitCartTwice);
     }
+
+    #[test]
+    fn should_not_submit_if_cart_is_forgotten() {
+        let cart_id = CartId::new();
+        let item_id = ItemId::new();
+
+        TestHarness::given([
+            DomainEvent::CartCreated { cart_id },
+            DomainEvent::CartItemAdded {
+                cart_id,
+                description: Faker.fake(),
+                image: Faker.fake(),
+                price: Faker.fake(),
+                item_id,
+                product_id: ProductId::new(),
+                fingerprint: default_fingerprint(),
+            },
+            DomainEvent::CartForgotten { cart_id },
+        ])
+        .when(SubmitCartCommand { cart_id })
+        .then_err(CartError::CartForgotten)
+    }
 }
@@ -0,0 +1,519 @@
+//! Verify slice. Replays every cart history, archived events included, to find histories that
+//! violate the cart invariants, would take an item count out of range or would cause a panic when
+//! folded into command state or a live read model.
+//! Nothing is modified, the event store and archive are only read.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    panic::{AssertUnwindSafe, catch_unwind},
+};
+
+use anyhow::Context;
+use disintegrate::{Decision, StateMutate, query};
+use futures::{StreamExt, TryStreamExt};
+use rust_decimal::Decimal;
+
+use crate::domain::{CartStream, EventArchive, EventStore};
+
+use super::{
+    AddItemCommand, CartId, ItemId, ProductId, RemoveItemCommand, SubmitCartCommand,
+    add_item::AddItemState, archive_item::ArchiveItemCommand, cart_items,
+    clear_cart::ClearCartCommand, forget_cart::ForgetCartCommand, submit_cart::SubmitCartState,
+};
+
+/// Maximum number of items a cart can hold.
+const MAX_ITEMS: usize = 3;
+
+/// A problem found in the history of a cart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartViolation {
+    pub cart_id: CartId,
+    /// The event at which the problem was found, if it can be pinned to a single event.
+    pub event_id: Option<i64>,
+    /// The invariant check, command state or read model that found the problem.
+    pub check: &'static str,
+    pub problem: String,
+}
+
+impl fmt::Display for CartViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.event_id {
+            Some(event_id) => write!(
+                f,
+                "Cart {} event {event_id} [{}]: {}",
+                self.cart_id, self.check, self.problem
+            ),
+            None => write!(
+                f,
+                "Cart {} [{}]: {}",
+                self.cart_id, self.check, self.problem
+            ),
+        }
+    }
+}
+
+//----------------------- Implementation --------------------------
+
+/// Reads every cart stream from the event store and the archive and verifies each one.
+/// Returns the total number of carts verified and the violations found.
+pub async fn verify_carts(
+    event_store: &EventStore,
+    event_archive: &EventArchive,
+) -> Result<(usize, Vec<CartViolation>), anyhow::Error> {
+    let mut carts = BTreeMap::<CartId, Vec<(i64, CartStream)>>::new();
+
+    let mut archived_events = event_archive.archived_events_after(0);
+    while let Some(event) = archived_events
+        .try_next()
+        .await
+        .context("Problem in verify_carts() reading archived cart events.")?
+    {
+        let event_id = event.id();
+        if let Ok(event) = CartStream::try_from(event.into_inner()) {
+            carts
+                .entry(cart_id_of(&event))
+                .or_default()
+                .push((event_id, event));
+        }
+    }
+
+    let query = query!(CartStream);
+    let mut events = disintegrate::EventStore::stream(event_store, &query);
+    while let Some(event) = events.next().await {
+        let event = event.context("Problem in verify_carts() reading cart events.")?;
+        let event_id = event.id();
+        let event = event.into_inner();
+        carts
+            .entry(cart_id_of(&event))
+            .or_default()
+            .push((event_id, event));
+    }
+    // A forgotten cart has events appended after those that were archived.
+    for events in carts.values_mut() {
+        events.sort_by_key(|(event_id, _)| *event_id);
+    }
+
+    let violations = carts
+        .iter()
+        .flat_map(|(cart_id, events)| verify_cart(*cart_id, events))
+        .collect();
+
+    Ok((carts.len(), violations))
+}
+
+/// Verifies the history of a single cart. Events must be in the order they were persisted.
+pub fn verify_cart(cart_id: CartId, events: &[(i64, CartStream)]) -> Vec<CartViolation> {
+    let mut violations = check_invariants(cart_id, events);
+
+    let item_ids: HashSet<ItemId> = events
+        .iter()
+        .filter_map(|(_, event)| match event {
+            CartStream::CartItemAdded { item_id, .. }
+            | CartStream::CartItemRemoved { item_id, .. }
+            | CartStream::ItemArchivedEvent { item_id, .. } => Some(*item_id),
+            _ => None,
+        })
+        .collect();
+
+    let add_item = AddItemCommand {
+        cart_id,
+        description: String::new(),
+        image: Default::default(),
+        price: Decimal::ZERO,
+        item_id: ItemId::new(),
+        product_id: ProductId::new(),
+        fingerprint: String::new(),
+    };
+    violations.extend(replay_state(
+        cart_id,
+        "AddItemState",
+        add_item.state_query(),
+        Some(|state: &AddItemState| state.item_count()),
+        events,
+    ));
+    violations.extend(replay_state(
+        cart_id,
+        "ClearCartState",
+        ClearCartCommand { cart_id }.state_query(),
+        None,
+        events,
+    ));
+    violations.extend(replay_state(
+        cart_id,
+        "ForgetCartState",
+        ForgetCartCommand { cart_id }.state_query(),
+        None,
+        events,
+    ));
+    violations.extend(replay_state(
+        cart_id,
+        "SubmitCartState",
+        SubmitCartCommand { cart_id }.state_query(),
+        Some(|state: &SubmitCartState| state.item_count()),
+        events,
+    ));
+    for item_id in item_ids {
+        violations.extend(replay_state(
+            cart_id,
+            "RemoveItemState",
+            RemoveItemCommand { cart_id, item_id }.state_query(),
+            None,
+            events,
+        ));
+        violations.extend(replay_state(
+            cart_id,
+            "ArchiveItemState",
+            ArchiveItemCommand {
+                cart_id,
+                item_id,
+                price_changed_event_id: 0,
+            }
+            .state_query(),
+            None,
+            events,
+        ));
+    }
+
+    violations.extend(replay_cart_items(cart_id, events));
+    violations
+}
+
+/// Checks the business rules a cart history is expected to obey.
+fn check_invariants(cart_id: CartId, events: &[(i64, CartStream)]) -> Vec<CartViolation> {
+    let mut violations = Vec::new();
+    let mut violation = |event_id: i64, problem: String| {
+        violations.push(CartViolation {
+            cart_id,
+            event_id: Some(event_id),
+            check: "invariants",
+            problem,
+        })
+    };
+
+    let mut created = false;
+    let mut submitted = false;
+    let mut forgotten = false;
+    let mut items = HashSet::<ItemId>::new();
+
+    for (event_id, event) in events {
+        let event_id = *event_id;
+        if !created && !matches!(event, CartStream::CartCreated { .. }) {
+            violation(event_id, "Event precedes CartCreated.".to_owned());
+        }
+        if submitted
+            && !matches!(
+                event,
+                CartStream::CartSubmitted { .. } | CartStream::CartForgotten { .. }
+            )
+        {
+            violation(event_id, "Cart altered after it was submitted.".to_owned());
+        }
+
+        match event {
+            CartStream::CartCreated { .. } => {
+                if created {
+                    violation(event_id, "Cart created more than once.".to_owned());
+                }
+                created = true;
+            }
+            CartStream::CartItemAdded { item_id, .. } => {
+                if forgotten {
+                    violation(event_id, "Item added after cart was forgotten.".to_owned());
+                }
+                if !items.insert(*item_id) {
+                    violation(event_id, format!("Item {item_id} added more than once."));
+                }
+                if items.len() > MAX_ITEMS {
+                    violation(
+                        event_id,
+                        format!("Cart holds {} items (max {MAX_ITEMS}).", items.len()),
+                    );
+                }
+            }
+            CartStream::CartItemRemoved { item_id, .. } => {
+                if !items.remove(item_id) {
+                    violation(event_id, format!("Item {item_id} removed but not in cart."));
+                }
+            }
+            CartStream::ItemArchivedEvent { item_id, .. } => {
+                if !items.remove(item_id) {
+                    violation(
+                        event_id,
+                        format!("Item {item_id} archived but not in cart."),
+                    );
+                }
+            }
+            CartStream::CartCleared { .. } => items.clear(),
+            CartStream::CartSubmitted { .. } => {
+                if submitted {
+                    violation(event_id, "Cart submitted more than once.".to_owned());
+                }
+                if items.is_empty() {
+                    violation(event_id, "Empty cart submitted.".to_owned());
+                }
+                submitted = true;
+            }
+            CartStream::CartForgotten { .. } => {
+                if forgotten {
+                    violation(event_id, "Cart forgotten more than once.".to_owned());
+                }
+                forgotten = true;
+            }
+        }
+    }
+
+    violations
+}
+
+/// Folds the events into command state, reporting the first event that panics or, for state with
+/// an item count, would take the count out of range. The count is checked before the event is
+/// folded as it would wrap, rather than panic, in a release build.
+fn replay_state<S>(
+    cart_id: CartId,
+    check: &'static str,
+    mut state: S,
+    item_count: Option<fn(&S) -> u8>,
+    events: &[(i64, CartStream)],
+) -> Option<CartViolation>
+where
+    S: StateMutate<Event = CartStream>,
+{
+    for (event_id, event) in events {
+        if let Some(problem) = item_count.and_then(|count| item_count_problem(count(&state), event))
+        {
+            return Some(CartViolation {
+                cart_id,
+                event_id: Some(*event_id),
+                check,
+                problem: problem.to_owned(),
+            });
+        }
+        let result = catch_unwind(AssertUnwindSafe(|| state.mutate(event.clone())));
+        if let Err(panic) = result {
+            return Some(CartViolation {
+                cart_id,
+                event_id: Some(*event_id),
+                check,
+                problem: format!("Panicked: {}", panic_message(panic.as_ref())),
+            });
+        }
+    }
+    None
+}
+
+/// Folds the events into the cart items live read model, reporting the first event that panics.
+fn replay_cart_items(cart_id: CartId, events: &[(i64, CartStream)]) -> Option<CartViolation> {
+    let mut read_model = None;
+    for (event_id, event) in events {
+        let current = read_model.take();
+        match catch_unwind(AssertUnwindSafe(|| {
+            cart_items::apply_event(current, event.clone())
+        })) {
+            Ok(next) => read_model = next,
+            Err(panic) => {
+                return Some(CartViolation {
+                    cart_id,
+                    event_id: Some(*event_id),
+                    check: "CartItemsReadModel",
+                    problem: format!("Panicked: {}", panic_message(panic.as_ref())),
+                });
+            }
+        }
+    }
+    None
+}
+
+/// The problem with folding the event into state holding `item_count` items, if any.
+fn item_count_problem(item_count: u8, event: &CartStream) -> Option<&'static str> {
+    match event {
+        CartStream::CartItemAdded { .. } if item_count == u8::MAX => {
+            Some("Item count would exceed its maximum.")
+        }
+        CartStream::CartItemRemoved { .. } | CartStream::ItemArchivedEvent { .. }
+            if item_count == 0 =>
+        {
+            Some("Item count would drop below zero.")
+        }
+        _ => None,
+    }
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+fn cart_id_of(event: &CartStream) -> CartId {
+    match event {
+        CartStream::CartCreated { cart_id }
+        | CartStream::CartItemAdded { cart_id, .. }
+        | CartStream::CartItemRemoved { cart_id, .. }
+        | CartStream::CartCleared { cart_id }
+        | CartStream::ItemArchivedEvent { cart_id, .. }
+        | CartStream::CartSubmitted { cart_id, .. }
+        | CartStream::CartForgotten { cart_id } => *cart_id,
+    }
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use disintegrate::EventStore as _;
+    use sqlx::PgPool;
+
+    use crate::domain::{
+        DomainEvent, EventCodec, EventSerde, KeyStore, ListenerMonitor, create_eventstore,
+        helpers::fake::{FingerPrint, Price},
+    };
+
+    use super::*;
+    use fake::{Fake, Faker};
+
+    fn item_added(cart_id: CartId, item_id: ItemId) -> CartStream {
+        CartStream::CartItemAdded {
+            cart_id,
+            description: Faker.fake(),
+            image: Faker.fake(),
+            price: Price.fake(),
+            item_id,
+            product_id: ProductId::new(),
+            fingerprint: FingerPrint.fake(),
+        }
+    }
+
+    fn numbered(events: Vec<CartStream>) -> Vec<(i64, CartStream)> {
+        (1..).zip(events).collect()
+    }
+
+    #[test]
+    fn valid_history_has_no_violations() {
+        let cart_id = CartId::new();
+        let item_id = ItemId::new();
+        let events = numbered(vec![
+            CartStream::CartCreated { cart_id },
+            item_added(cart_id, item_id),
+            item_added(cart_id, ItemId::new()),
+            CartStream::CartItemRemoved { cart_id, item_id },
+            CartStream::CartSubmitted {
+                cart_id,
+                ordered_product: Vec::new(),
+                total_price: Decimal::ZERO,
+            },
+        ]);
+
+        assert_eq!(verify_cart(cart_id, &events), Vec::new());
+    }
+
+    #[test]
+    fn removing_unknown_item_is_reported() {
+        let cart_id = CartId::new();
+        let item_id = ItemId::new();
+        let events = numbered(vec![
+            CartStream::CartCreated { cart_id },
+            item_added(cart_id, ItemId::new()),
+            CartStream::CartItemRemoved { cart_id, item_id },
+        ]);
+
+        let violations = verify_cart(cart_id, &events);
+
+        let checks: Vec<_> = violations.iter().map(|v| v.check).collect();
+        assert!(checks.contains(&"invariants"));
+        assert!(checks.contains(&"SubmitCartState"));
+        assert!(violations.iter().all(|v| v.event_id == Some(3)));
+    }
+
+    #[test]
+    fn item_count_underflow_is_reported_rather_than_wrapped() {
+        let cart_id = CartId::new();
+        let events = numbered(vec![
+            CartStream::CartCreated { cart_id },
+            CartStream::CartItemRemoved {
+                cart_id,
+                item_id: ItemId::new(),
+            },
+        ]);
+
+        let violations = verify_cart(cart_id, &events);
+
+        let add_item = violations
+            .iter()
+            .find(|v| v.check == "AddItemState")
+            .expect("AddItemState should report the removed item.");
+        assert_eq!(add_item.event_id, Some(2));
+        assert_eq!(add_item.problem, "Item count would drop below zero.");
+    }
+
+    #[test]
+    fn event_before_cart_created_is_reported() {
+        let cart_id = CartId::new();
+        let events = numbered(vec![
+            item_added(cart_id, ItemId::new()),
+            CartStream::CartCreated { cart_id },
+        ]);
+
+        let violations = verify_cart(cart_id, &events);
+
+        let checks: Vec<_> = violations.iter().map(|v| v.check).collect();
+        assert!(checks.contains(&"invariants"));
+        assert!(checks.contains(&"CartItemsReadModel"));
+    }
+
+    #[test]
+    fn too_many_items_is_reported() {
+        let cart_id = CartId::new();
+        let events = numbered(vec![
+            CartStream::CartCreated { cart_id },
+            item_added(cart_id, ItemId::new()),
+            item_added(cart_id, ItemId::new()),
+            item_added(cart_id, ItemId::new()),
+            item_added(cart_id, ItemId::new()),
+        ]);
+
+        let violations = verify_cart(cart_id, &events);
+
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].event_id, Some(5));
+    }
+
+    #[sqlx::test]
+    async fn archived_events_are_verified_with_the_rest_of_the_cart(pool: PgPool) {
+        let serde = EventSerde::new(KeyStore::load(&pool).await.unwrap(), EventCodec::Json);
+        let event_store = create_eventstore(&pool, serde.clone()).await.unwrap();
+        let archive = EventArchive::new(pool.clone(), serde);
+        let cart_id = CartId::new();
+        let item_id = ItemId::new();
+        event_store
+            .append_without_validation(vec![
+                DomainEvent::CartCreated { cart_id },
+                item_added(cart_id, item_id).into(),
+                DomainEvent::CartPublished { cart_id },
+            ])
+            .await
+            .unwrap();
+        sqlx::query("UPDATE event SET inserted_at = now() - INTERVAL '31 days' WHERE cart_id = $1")
+            .bind(cart_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        archive
+            .archive_published_carts(30, &ListenerMonitor::in_memory())
+            .await
+            .unwrap();
+        event_store
+            .append_without_validation(vec![DomainEvent::CartItemRemoved { cart_id, item_id }])
+            .await
+            .unwrap();
+
+        let (cart_count, violations) = verify_carts(&event_store, &archive).await.unwrap();
+
+        assert_eq!(cart_count, 1);
+        assert_eq!(violations, Vec::new());
+    }
+}
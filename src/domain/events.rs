@@ -3,11 +3,17 @@ use rust_decimal::Decimal;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize, disintegrate::Event)]
-#[stream(CartStream, [CartCreated, CartItemAdded, CartItemRemoved, CartCleared, ItemArchivedEvent, CartSubmitted])]
+#[stream(CartStream, [CartCreated, CartItemAdded, CartItemRemoved, CartCleared, ItemArchivedEvent, CartSubmitted, CartForgotten])]
 #[stream(EmptyStream, [EmptyEvent])]
+#[stream(ForgottenStream, [CartForgotten])]
 #[stream(InventoryStream, [InventoryChanged])]
+#[stream(LowStockStream, [LowStockDetected])]
 #[stream(PricingStream, [PriceChanged])]
+#[stream(PublicationStream, [CartSubmitted, CartPublished, CartPublicationFailed, CartRepublishRequested])]
 #[stream(PublishedStream, [CartPublished, CartPublicationFailed])]
+#[stream(RepublishCartStream, [CartSubmitted, CartPublished, CartPublicationFailed, CartRepublishRequested, CartForgotten])]
+#[stream(RepublishStream, [CartRepublishRequested])]
+#[stream(StockLevelStream, [InventoryChanged, LowStockDetected])]
 #[stream(SubmittedStream, [CartSubmitted])]
 pub enum DomainEvent {
     CartCleared {
@@ -18,6 +24,10 @@ pub enum DomainEvent {
         #[id]
         cart_id: CartId,
     },
+    CartForgotten {
+        #[id]
+        cart_id: CartId,
+    },
     CartItemAdded {
         #[id]
         cart_id: CartId,
@@ -45,6 +55,13 @@ pub enum DomainEvent {
         #[id]
         cart_id: CartId,
     },
+    /// The submitted cart is to be published again after its publication failed.
+    CartRepublishRequested {
+        #[id]
+        cart_id: CartId,
+        ordered_product: Vec<OrderedProduct>,
+        total_price: Decimal,
+    },
     CartSubmitted {
         #[id]
         cart_id: CartId,
@@ -57,6 +74,13 @@ pub enum DomainEvent {
         product_id: ProductId,
         inventory: i32,
     },
+    /// The product's inventory fell below the low-stock threshold.
+    LowStockDetected {
+        #[id]
+        product_id: ProductId,
+        inventory: i32,
+        threshold: i32,
+    },
     ItemArchivedEvent {
         #[id]
         cart_id: CartId,
@@ -0,0 +1,635 @@
+//! Archival of the events of long-finished carts.
+//!
+//! Events of carts published long ago are moved into the `event_archive` table once every event
+//! listener handling them has processed them. Archived events are only read when asked for, and
+//! `ArchivedCarts` middleware rejects commands about an archived cart unless they can be decided on
+//! its archived events, as forgetting a cart can.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use disintegrate::{DecisionError, EventStore as _, PersistedEvent, query};
+use futures::{StreamExt, TryStreamExt, stream::BoxStream};
+use sqlx::PgPool;
+
+use crate::domain::{
+    CartStream, Command, CommandEnvelope, DomainEvent, EventSerde, EventStore, ListenerMonitor,
+    Middleware, Next, PublicationStream,
+    cart::{CartError, CartId},
+};
+
+use super::decision_maker::DecisionResult;
+
+#[derive(Clone)]
+pub struct EventArchive {
+    pool: Option<PgPool>,
+    serde: EventSerde,
+}
+
+impl EventArchive {
+    /// `serde` must be the same Serde the event store was created with.
+    pub fn new(pool: PgPool, serde: EventSerde) -> Self {
+        Self {
+            pool: Some(pool),
+            serde,
+        }
+    }
+
+    pub fn in_memory(serde: EventSerde) -> Self {
+        Self { pool: None, serde }
+    }
+
+    /// Moves the events of carts published more than `after_days` days ago into the archive.
+    /// Returns the number of events archived.
+    ///
+    /// A cart is archived only once every listener registered with the monitor has processed
+    /// those of its events that the listener's query selects. Disintegrate only moves a
+    /// listener's checkpoint on to events it handles, so a listener with nothing to do keeps an
+    /// old checkpoint without holding up archival.
+    ///
+    /// Nor is a cart archived while any of its events is parked and not yet replayed, as parked
+    /// events are replayed, and events held behind them found, from the `event` table.
+    pub async fn archive_published_carts(
+        &self,
+        after_days: i32,
+        listeners: &ListenerMonitor,
+    ) -> Result<u64, anyhow::Error> {
+        let Some(pool) = &self.pool else {
+            return Ok(0);
+        };
+        let mut tx = pool
+            .begin()
+            .await
+            .context("Problem in archive_published_carts() starting transaction.")?;
+
+        let listeners_exist: bool =
+            sqlx::query_scalar("SELECT to_regclass('event_listener') IS NOT NULL")
+                .fetch_one(&mut *tx)
+                .await
+                .context("Problem in archive_published_carts() finding event listeners.")?;
+        let checkpoints: HashMap<String, i64> = if listeners_exist {
+            sqlx::query_as("SELECT id, COALESCE(last_processed_event_id, 0) FROM event_listener")
+                .fetch_all(&mut *tx)
+                .await
+                .context(
+                    "Problem in archive_published_carts() reading event listener checkpoints.",
+                )?
+                .into_iter()
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        // Each event type a listener handles, with that listener's checkpoint. A listener that
+        // has not run yet has processed nothing.
+        let (handled_types, handled_checkpoints): (Vec<&str>, Vec<i64>) = listeners
+            .event_types()
+            .into_iter()
+            .flat_map(|(listener_id, event_types)| {
+                let checkpoint = checkpoints.get(listener_id).copied().unwrap_or(0);
+                event_types
+                    .into_iter()
+                    .map(move |event_type| (event_type, checkpoint))
+            })
+            .unzip();
+
+        let result = sqlx::query(
+            r#"WITH handled AS (
+                   SELECT * FROM UNNEST($2::TEXT[], $3::BIGINT[]) AS handled(event_type, checkpoint)
+               ),
+               archivable AS (
+                   SELECT DISTINCT published.cart_id
+                   FROM event published
+                   WHERE published.event_type = 'CartPublished'
+                     AND published.inserted_at < now() - make_interval(days => $1)
+                     AND NOT EXISTS (
+                         SELECT 1
+                         FROM event e
+                         JOIN handled ON handled.event_type = e.event_type
+                         WHERE e.cart_id = published.cart_id
+                           AND e.event_id > handled.checkpoint
+                     )
+                     AND NOT EXISTS (
+                         SELECT 1
+                         FROM projection_failures failure
+                         JOIN event parked ON parked.event_id = failure.event_id
+                         WHERE parked.cart_id = published.cart_id
+                           AND failure.replayed_at IS NULL
+                     )
+               ),
+               archived AS (
+                   DELETE FROM event
+                   USING archivable
+                   WHERE event.cart_id = archivable.cart_id
+                   RETURNING event.event_id, event.event_type, event.payload, event.cart_id, event.inserted_at
+               )
+               INSERT INTO event_archive (event_id, event_type, payload, cart_id, inserted_at)
+               SELECT event_id, event_type, payload, cart_id, inserted_at FROM archived"#,
+        )
+        .bind(after_days)
+        .bind(&handled_types)
+        .bind(&handled_checkpoints)
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("Problem in archive_published_carts(after_days: {after_days})."))?;
+
+        tx.commit()
+            .await
+            .context("Problem in archive_published_carts() committing transaction.")?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Whether the cart's events have been archived.
+    pub async fn is_archived(&self, cart_id: &CartId) -> Result<bool, anyhow::Error> {
+        let Some(pool) = &self.pool else {
+            return Ok(false);
+        };
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM event_archive WHERE cart_id = $1) as "archived!""#,
+            cart_id as &CartId
+        )
+        .fetch_one(pool)
+        .await
+        .with_context(|| format!("Problem in is_archived(cart_id: {cart_id})."))
+    }
+
+    /// Rejects the command if it is about an archived cart.
+    pub async fn reject_archived(
+        &self,
+        command: &dyn Command,
+    ) -> Result<(), DecisionError<CartError>> {
+        let Some(cart_id) = command.cart_id() else {
+            return Ok(());
+        };
+        match self.is_archived(&cart_id).await {
+            Ok(false) => Ok(()),
+            Ok(true) => Err(DecisionError::Domain(CartError::CartArchived(cart_id))),
+            Err(e) => Err(DecisionError::StateStore(e.into())),
+        }
+    }
+
+    /// Returns the archived events of a cart in the order they were persisted.
+    pub async fn archived_cart_events(
+        &self,
+        cart_id: &CartId,
+    ) -> Result<Vec<PersistedEvent<i64, DomainEvent>>, anyhow::Error> {
+        let Some(pool) = &self.pool else {
+            return Ok(Vec::new());
+        };
+        let rows = sqlx::query!(
+            "SELECT event_id, payload FROM event_archive WHERE cart_id = $1 ORDER BY event_id",
+            cart_id as &CartId
+        )
+        .fetch_all(pool)
+        .await
+        .with_context(|| format!("Problem in archived_cart_events(cart_id: {cart_id})."))?;
+
+        let mut events = Vec::with_capacity(rows.len());
+        for row in rows {
+            let event = self
+                .serde
+                .deserialize_loading_key(row.payload)
+                .await
+                .with_context(|| {
+                    format!("Problem deserialising archived event {}.", row.event_id)
+                })?;
+            events.push(PersistedEvent::new(row.event_id, event));
+        }
+        Ok(events)
+    }
+
+    /// Streams every archived event after `event_id`, in the order they were persisted.
+    pub fn archived_events_after(
+        &self,
+        event_id: i64,
+    ) -> BoxStream<'_, Result<PersistedEvent<i64, DomainEvent>, anyhow::Error>> {
+        let Some(pool) = &self.pool else {
+            return futures::stream::empty().boxed();
+        };
+        sqlx::query!(
+            "SELECT event_id, payload FROM event_archive WHERE event_id > $1 ORDER BY event_id",
+            event_id
+        )
+        .fetch(pool)
+        .map_err(move |e| {
+            anyhow::Error::new(e).context(format!(
+                "Problem in archived_events_after(event_id: {event_id})."
+            ))
+        })
+        .and_then(move |row| async move {
+            self.serde
+                .deserialize_loading_key(row.payload)
+                .await
+                .map(|event| PersistedEvent::new(row.event_id, event))
+                .with_context(|| format!("Problem deserialising archived event {}.", row.event_id))
+        })
+        .boxed()
+    }
+
+    /// Returns the events of a cart, optionally including those that have been archived.
+    pub async fn cart_events(
+        &self,
+        event_store: &EventStore,
+        cart_id: &CartId,
+        include_archive: bool,
+    ) -> Result<Vec<PersistedEvent<i64, DomainEvent>>, anyhow::Error> {
+        let mut events = if include_archive {
+            self.archived_cart_events(cart_id).await?
+        } else {
+            Vec::new()
+        };
+
+        // Events without a cart_id, e.g. InventoryChanged, would match a cart_id filter on
+        // DomainEvent, so the query is built from the streams of cart events.
+        let query = query!(CartStream; cart_id == *cart_id)
+            .union(&query!(PublicationStream; cart_id == *cart_id));
+        let live_events: Vec<_> = event_store
+            .stream(&query)
+            .try_collect()
+            .await
+            .with_context(|| format!("Problem in cart_events(cart_id: {cart_id})."))?;
+        events.extend(live_events);
+
+        Ok(events)
+    }
+}
+
+/// Decides commands about archived carts, which the decider cannot as it only reads the event
+/// store. Other commands are passed on.
+pub struct ArchivedCarts {
+    archive: EventArchive,
+    event_store: EventStore,
+}
+
+impl ArchivedCarts {
+    pub fn new(archive: EventArchive, event_store: EventStore) -> Self {
+        Self {
+            archive,
+            event_store,
+        }
+    }
+
+    /// Decides the command with `Command::decide_archived` on all of the cart's events and
+    /// appends the resulting events, on the condition that no other event for the cart has been
+    /// appended meanwhile.
+    async fn decide(&self, cart_id: CartId, command: &dyn Command) -> DecisionResult {
+        let events = self
+            .archive
+            .cart_events(&self.event_store, &cart_id, true)
+            .await
+            .map_err(|e| DecisionError::StateStore(e.into()))?;
+        let last_event_id = events.last().map(|event| event.id()).unwrap_or(0);
+        let changes = command
+            .decide_archived(events.into_iter().map(|event| event.into_inner()).collect())
+            .unwrap_or(Err(CartError::CartArchived(cart_id)))
+            .map_err(DecisionError::Domain)?;
+        self.event_store
+            .append(
+                changes,
+                query!(CartStream; cart_id == cart_id),
+                last_event_id,
+            )
+            .await
+            .map_err(|e| DecisionError::EventStore(Box::new(e)))
+    }
+}
+
+#[async_trait]
+impl Middleware for ArchivedCarts {
+    async fn handle(&self, envelope: &CommandEnvelope<'_>, next: Next<'_>) -> DecisionResult {
+        let Some(cart_id) = envelope.command.cart_id() else {
+            return next.run(envelope).await;
+        };
+        let archived = self
+            .archive
+            .is_archived(&cart_id)
+            .await
+            .map_err(|e| DecisionError::StateStore(e.into()))?;
+        if archived {
+            self.decide(cart_id, envelope.command).await
+        } else {
+            next.run(envelope).await
+        }
+    }
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use fake::{Fake, Faker};
+
+    use super::*;
+    use disintegrate::EventListener;
+
+    use crate::domain::{
+        CommandBus, CommandLog, CommandOrigin, EventCodec, ExpectedEventId, ForgottenStream,
+        KeyStore, REDACTED,
+        cart::{
+            AddItemCommand, CartForgottenEventHandler, CartItemsReadModelProjection,
+            ForgetCartCommand, ProductId,
+        },
+        create_eventstore_and_decider, create_eventstore_and_decider_with,
+        helpers::test_events::{append_events, set_checkpoint},
+        park_event,
+    };
+    use crate::infra::{ListenerSettings, SnapshotSettings};
+
+    #[sqlx::test]
+    async fn events_of_long_published_carts_are_archived(pool: PgPool) {
+        let (event_store, decider) = create_eventstore_and_decider(&pool)
+            .await
+            .expect("EventStore and Decider should be created.");
+        let key_store = KeyStore::load(&pool).await.unwrap();
+        let archive = EventArchive::new(pool.clone(), EventSerde::new(key_store, EventCodec::Json));
+
+        let recent_cart_id = CartId::new();
+        let old_cart_id = CartId::new();
+        for cart_id in [recent_cart_id, old_cart_id] {
+            decider
+                .make(AddItemCommand {
+                    cart_id,
+                    ..Faker.fake()
+                })
+                .await
+                .expect("Add item should succeed.");
+            event_store
+                .append_without_validation(vec![DomainEvent::CartPublished { cart_id }])
+                .await
+                .expect("CartPublished should be appended.");
+        }
+        sqlx::query("UPDATE event SET inserted_at = now() - INTERVAL '31 days' WHERE cart_id = $1")
+            .bind(old_cart_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let archived = archive
+            .archive_published_carts(30, &ListenerMonitor::in_memory())
+            .await
+            .unwrap();
+
+        assert_eq!(archived, 3);
+        let live = archive
+            .cart_events(&event_store, &old_cart_id, false)
+            .await
+            .unwrap();
+        assert!(live.is_empty());
+        let all = archive
+            .cart_events(&event_store, &old_cart_id, true)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 3);
+        let recent = archive
+            .cart_events(&event_store, &recent_cart_id, false)
+            .await
+            .unwrap();
+        assert_eq!(recent.len(), 3);
+    }
+
+    #[sqlx::test]
+    async fn carts_are_archived_once_the_listeners_handling_their_events_caught_up(pool: PgPool) {
+        let key_store = KeyStore::load(&pool).await.unwrap();
+        let archive = EventArchive::new(
+            pool.clone(),
+            EventSerde::new(key_store.clone(), EventCodec::Json),
+        );
+        let listeners = ListenerMonitor::new(pool.clone(), ListenerSettings::default());
+        listeners.watch(CartItemsReadModelProjection::new(pool.clone()));
+        listeners.watch(CartForgottenEventHandler::new(key_store));
+        let cart_id = CartId::new();
+        let events = append_events(
+            &pool,
+            [
+                DomainEvent::CartCreated { cart_id },
+                DomainEvent::CartCleared { cart_id },
+                DomainEvent::CartPublished { cart_id },
+            ],
+        )
+        .await;
+        sqlx::query("UPDATE event SET inserted_at = now() - INTERVAL '31 days' WHERE cart_id = $1")
+            .bind(cart_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        // No cart has been forgotten, so the cart_forgotten listener has never moved.
+        set_checkpoint(&pool, "cart_forgotten", 0).await;
+        set_checkpoint(&pool, "cart_items_from_db", events[0].id()).await;
+
+        let lagging = archive
+            .archive_published_carts(30, &listeners)
+            .await
+            .unwrap();
+        set_checkpoint(&pool, "cart_items_from_db", events[1].id()).await;
+        let caught_up = archive
+            .archive_published_carts(30, &listeners)
+            .await
+            .unwrap();
+
+        assert_eq!(lagging, 0);
+        assert_eq!(caught_up, 3);
+    }
+
+    #[sqlx::test]
+    async fn carts_with_parked_events_are_not_archived(pool: PgPool) {
+        let key_store = KeyStore::load(&pool).await.unwrap();
+        let archive = EventArchive::new(pool.clone(), EventSerde::new(key_store, EventCodec::Json));
+        let cart_id = CartId::new();
+        let events = append_events(
+            &pool,
+            [
+                DomainEvent::CartCreated { cart_id },
+                DomainEvent::CartCleared { cart_id },
+                DomainEvent::CartPublished { cart_id },
+            ],
+        )
+        .await;
+        sqlx::query("UPDATE event SET inserted_at = now() - INTERVAL '31 days' WHERE cart_id = $1")
+            .bind(cart_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        park_event(&pool, "cart_items_from_db", events[1].id(), "Failed.", 1)
+            .await
+            .unwrap();
+
+        let parked = archive
+            .archive_published_carts(30, &ListenerMonitor::in_memory())
+            .await
+            .unwrap();
+        sqlx::query("UPDATE projection_failures SET replayed_at = now()")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let replayed = archive
+            .archive_published_carts(30, &ListenerMonitor::in_memory())
+            .await
+            .unwrap();
+
+        assert_eq!(parked, 0);
+        assert_eq!(replayed, 3);
+    }
+
+    #[sqlx::test]
+    async fn cart_events_are_only_those_of_the_cart(pool: PgPool) {
+        let (event_store, decider) = create_eventstore_and_decider(&pool)
+            .await
+            .expect("EventStore and Decider should be created.");
+        let key_store = KeyStore::load(&pool).await.unwrap();
+        let archive = EventArchive::new(pool.clone(), EventSerde::new(key_store, EventCodec::Json));
+        let cart_id = CartId::new();
+        decider
+            .make(AddItemCommand {
+                cart_id,
+                ..Faker.fake()
+            })
+            .await
+            .expect("Add item should succeed.");
+        event_store
+            .append_without_validation(vec![
+                DomainEvent::InventoryChanged {
+                    product_id: ProductId::new(),
+                    inventory: 5,
+                },
+                DomainEvent::CartPublished { cart_id },
+            ])
+            .await
+            .expect("Events should be appended.");
+
+        let events = archive
+            .cart_events(&event_store, &cart_id, false)
+            .await
+            .unwrap();
+
+        assert_eq!(events.len(), 3);
+        assert!(
+            events
+                .iter()
+                .all(|event| !matches!(**event, DomainEvent::InventoryChanged { .. }))
+        );
+    }
+
+    #[sqlx::test]
+    async fn commands_on_archived_carts_are_rejected(pool: PgPool) {
+        let (event_store, decider) = create_eventstore_and_decider(&pool)
+            .await
+            .expect("EventStore and Decider should be created.");
+        let key_store = KeyStore::load(&pool).await.unwrap();
+        let archive = EventArchive::new(pool.clone(), EventSerde::new(key_store, EventCodec::Json));
+        let command_bus =
+            CommandBus::standard(decider, CommandLog::new(pool.clone()), archive.clone());
+        let cart_id = CartId::new();
+        command_bus
+            .dispatch(
+                AddItemCommand {
+                    cart_id,
+                    ..Faker.fake()
+                },
+                CommandOrigin::Http,
+            )
+            .await
+            .expect("Add item should succeed.");
+        event_store
+            .append_without_validation(vec![DomainEvent::CartPublished { cart_id }])
+            .await
+            .expect("CartPublished should be appended.");
+        sqlx::query("UPDATE event SET inserted_at = now() - INTERVAL '31 days' WHERE cart_id = $1")
+            .bind(cart_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        archive
+            .archive_published_carts(30, &ListenerMonitor::in_memory())
+            .await
+            .unwrap();
+
+        let add_item = AddItemCommand {
+            cart_id,
+            ..Faker.fake()
+        };
+        let dispatched = command_bus
+            .dispatch(add_item.clone(), CommandOrigin::Http)
+            .await;
+        let dry_run = command_bus.dry_run(add_item, ExpectedEventId(None)).await;
+
+        assert!(matches!(
+            dispatched,
+            Err(DecisionError::Domain(CartError::CartArchived(id))) if id == cart_id
+        ));
+        assert!(matches!(
+            dry_run,
+            Err(DecisionError::Domain(CartError::CartArchived(id))) if id == cart_id
+        ));
+    }
+
+    #[sqlx::test]
+    async fn archived_carts_can_be_forgotten(pool: PgPool) {
+        let key_store = KeyStore::load(&pool).await.unwrap();
+        let serde = EventSerde::new(key_store.clone(), EventCodec::Json);
+        let (event_store, decider) =
+            create_eventstore_and_decider_with(&pool, serde.clone(), &SnapshotSettings::default())
+                .await
+                .expect("EventStore and Decider should be created.");
+        let archive = EventArchive::new(pool.clone(), serde);
+        let command_bus =
+            CommandBus::standard(decider, CommandLog::new(pool.clone()), archive.clone());
+        let cart_id = CartId::new();
+        command_bus
+            .dispatch(
+                AddItemCommand {
+                    cart_id,
+                    ..Faker.fake()
+                },
+                CommandOrigin::Http,
+            )
+            .await
+            .expect("Add item should succeed.");
+        event_store
+            .append_without_validation(vec![DomainEvent::CartPublished { cart_id }])
+            .await
+            .expect("CartPublished should be appended.");
+        sqlx::query("UPDATE event SET inserted_at = now() - INTERVAL '31 days' WHERE cart_id = $1")
+            .bind(cart_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        archive
+            .archive_published_carts(30, &ListenerMonitor::in_memory())
+            .await
+            .unwrap();
+
+        let forgotten = command_bus
+            .dispatch(ForgetCartCommand { cart_id }, CommandOrigin::Http)
+            .await
+            .expect("Archived cart should be forgotten.");
+        let forgotten_again = command_bus
+            .dispatch(ForgetCartCommand { cart_id }, CommandOrigin::Http)
+            .await;
+        CartForgottenEventHandler::new(key_store)
+            .handle(PersistedEvent::new(
+                forgotten[0].id(),
+                ForgottenStream::CartForgotten { cart_id },
+            ))
+            .await
+            .expect("Key should be forgotten.");
+
+        assert_eq!(*forgotten[0], DomainEvent::CartForgotten { cart_id });
+        assert!(matches!(
+            forgotten_again,
+            Err(DecisionError::Domain(CartError::CartForgotten))
+        ));
+        let events = archive
+            .cart_events(&event_store, &cart_id, true)
+            .await
+            .unwrap();
+        let fingerprints: Vec<_> = events
+            .iter()
+            .filter_map(|event| match &**event {
+                DomainEvent::CartItemAdded { fingerprint, .. } => Some(fingerprint.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fingerprints, vec![REDACTED]);
+    }
+}
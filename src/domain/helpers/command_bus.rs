@@ -0,0 +1,341 @@
+//! Command dispatch.
+//!
+//! Every command, whether it comes from an HTTP endpoint, a Kafka translator or a processor, is
+//! dispatched through the `CommandBus`. The bus passes the command through a chain of
+//! `Middleware` before the decision is made, so cross-cutting concerns such as validation,
+//! tracing, metrics and retries are handled once rather than at every call site.
+//! Middleware sees the command as a `CommandEnvelope` and calls `Next::run` to continue the
+//! chain, or returns early to stop it.
+//!
+//! Authorization is one of the middleware: each command names the origins it may be dispatched
+//! from, so e.g. a price change read from Kafka is accepted but an archived item cannot be
+//! forced over HTTP. Which HTTP callers may reach an endpoint at all is decided by the web
+//! server's route middleware, such as the admin token, before the command is built.
+
+use std::{any::type_name, fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+use disintegrate::{Decision, DecisionError, LoadState, PersistDecision};
+use disintegrate_postgres::PgEventId;
+use futures::{FutureExt, future::BoxFuture};
+
+use crate::domain::{
+    ArchivedCarts, DecisionMaker, DomainEvent, EventArchive, ExpectedEventId,
+    cart::{CartError, CartId},
+};
+
+use super::{
+    command_log::CommandLog,
+    command_middleware::{Authorization, CommandMetrics, Metrics, Retry, Tracing, Validation},
+    decision_maker::{DecisionResult, DryRun, StateStore},
+};
+
+/// A command that can be dispatched through the `CommandBus`.
+pub trait Command: CommandPayload + Debug + Send + Sync {
+    /// Checks the command is well formed before any state is loaded.
+    fn validate(&self) -> Result<(), CartError> {
+        Ok(())
+    }
+
+    /// The origins the command may be dispatched from. By default only HTTP endpoints may
+    /// dispatch a command.
+    fn origins(&self) -> &'static [CommandOrigin] {
+        &[CommandOrigin::Http]
+    }
+
+    /// The cart the command is about, if it is about a single cart.
+    fn cart_id(&self) -> Option<CartId> {
+        None
+    }
+
+    /// Decides the command for a cart whose events have been archived, on all of the cart's
+    /// events. `None`, the default, means the command cannot be made on an archived cart.
+    fn decide_archived(
+        &self,
+        _events: Vec<DomainEvent>,
+    ) -> Option<Result<Vec<DomainEvent>, CartError>> {
+        None
+    }
+}
+
+/// The command as JSON, e.g. for the command log. Personal data should be skipped when the
+/// command is serialised.
+pub trait CommandPayload {
+    fn payload(&self) -> serde_json::Value;
+}
+
+impl<T: serde::Serialize> CommandPayload for T {
+    fn payload(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_else(|e| serde_json::Value::String(e.to_string()))
+    }
+}
+
+/// Where a command came from.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, strum_macros::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum CommandOrigin {
+    Http,
+    Kafka,
+    Processor,
+}
+
+/// A command as it passes through the middleware.
+pub struct CommandEnvelope<'a> {
+    /// The command's type name, e.g. `AddItemCommand`.
+    pub name: &'static str,
+    pub origin: CommandOrigin,
+    pub expected_event_id: ExpectedEventId,
+    pub command: &'a dyn Command,
+}
+
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    async fn handle(&self, envelope: &CommandEnvelope<'_>, next: Next<'_>) -> DecisionResult;
+}
+
+/// The remainder of the middleware chain, ending with the decision itself.
+/// `run` may be called more than once, e.g. to retry the decision.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    decide: &'a (dyn Fn() -> BoxFuture<'a, DecisionResult> + Send + Sync),
+}
+
+impl<'a> Next<'a> {
+    pub(super) fn new(
+        middleware: &'a [Arc<dyn Middleware>],
+        decide: &'a (dyn Fn() -> BoxFuture<'a, DecisionResult> + Send + Sync),
+    ) -> Self {
+        Self { middleware, decide }
+    }
+
+    pub async fn run(self, envelope: &CommandEnvelope<'_>) -> DecisionResult {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => {
+                middleware
+                    .handle(
+                        envelope,
+                        Next {
+                            middleware: rest,
+                            decide: self.decide,
+                        },
+                    )
+                    .await
+            }
+            None => (self.decide)().await,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CommandBus {
+    decider: DecisionMaker,
+    middleware: Vec<Arc<dyn Middleware>>,
+    metrics: CommandMetrics,
+    /// Also checked by dry runs, which run no middleware.
+    archive: Option<EventArchive>,
+}
+
+impl CommandBus {
+    /// A bus without middleware. Commands are decided exactly once.
+    pub fn new(decider: DecisionMaker) -> Self {
+        Self {
+            decider,
+            middleware: Vec::new(),
+            metrics: CommandMetrics::default(),
+            archive: None,
+        }
+    }
+
+    /// The bus used by the application: tracing, metrics, the command log, authorization,
+    /// validation, deciding commands about archived carts and retry on concurrency conflicts, in
+    /// that order.
+    pub fn standard(
+        decider: DecisionMaker,
+        command_log: CommandLog,
+        event_archive: EventArchive,
+    ) -> Self {
+        let metrics = CommandMetrics::default();
+        let archived_carts =
+            ArchivedCarts::new(event_archive.clone(), decider.event_store().clone());
+        Self {
+            metrics: metrics.clone(),
+            archive: Some(event_archive.clone()),
+            ..Self::new(decider)
+        }
+        .with(Tracing)
+        .with(Metrics::new(metrics.clone()))
+        .with(command_log)
+        .with(Authorization)
+        .with(Validation)
+        .with(archived_carts)
+        .with(Retry::new(metrics))
+    }
+
+    /// Adds middleware to the end of the chain, i.e. closest to the decision.
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    pub fn decider(&self) -> &DecisionMaker {
+        &self.decider
+    }
+
+    pub fn metrics(&self) -> &CommandMetrics {
+        &self.metrics
+    }
+
+    pub async fn dispatch<D, S>(&self, command: D, origin: CommandOrigin) -> DecisionResult
+    where
+        D: Command + Decision<StateQuery = S, Event = DomainEvent, Error = CartError>,
+        StateStore:
+            LoadState<PgEventId, S, DomainEvent> + PersistDecision<PgEventId, S, DomainEvent>,
+        S: Send,
+    {
+        self.dispatch_expecting(command, origin, ExpectedEventId(None))
+            .await
+    }
+
+    /// Dispatches the command on the condition that no event newer than `expected_event_id` has
+    /// changed the command's state.
+    pub async fn dispatch_expecting<D, S>(
+        &self,
+        command: D,
+        origin: CommandOrigin,
+        expected_event_id: ExpectedEventId,
+    ) -> DecisionResult
+    where
+        D: Command + Decision<StateQuery = S, Event = DomainEvent, Error = CartError>,
+        StateStore:
+            LoadState<PgEventId, S, DomainEvent> + PersistDecision<PgEventId, S, DomainEvent>,
+        S: Send,
+    {
+        let envelope = CommandEnvelope {
+            name: command_name::<D>(),
+            origin,
+            expected_event_id,
+            command: &command,
+        };
+        let decide = || self.decider.decide(&command, expected_event_id).boxed();
+        Next::new(&self.middleware, &decide).run(&envelope).await
+    }
+
+    /// Dry runs the command: it is validated and decided, but nothing is appended. Middleware is
+    /// not run because nothing is dispatched, though a command about an archived cart is still
+    /// rejected, as the decision is only made against the event store.
+    pub async fn dry_run<D, S>(
+        &self,
+        command: D,
+        expected_event_id: ExpectedEventId,
+    ) -> Result<DryRun<S>, DecisionError<CartError>>
+    where
+        D: Command + Decision<StateQuery = S, Event = DomainEvent, Error = CartError>,
+        S: Clone,
+        StateStore: LoadState<PgEventId, S, DomainEvent>,
+    {
+        command.validate().map_err(DecisionError::Domain)?;
+        if let Some(archive) = &self.archive {
+            archive.reject_archived(&command).await?;
+        }
+        self.decider.dry_run(&command, expected_event_id).await
+    }
+}
+
+fn command_name<D>() -> &'static str {
+    let name = type_name::<D>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use fake::{Fake, Faker};
+
+    use super::*;
+    use crate::{
+        domain::{
+            cart::{AddItemCommand, CartId},
+            create_in_memory_eventstore_and_decider,
+        },
+        infra::ClientError,
+    };
+
+    /// Records the commands it sees and stops those from the given origin.
+    struct OriginFilter {
+        denied: CommandOrigin,
+        seen: Arc<Mutex<Vec<(&'static str, CommandOrigin)>>>,
+    }
+
+    #[async_trait]
+    impl Middleware for OriginFilter {
+        async fn handle(&self, envelope: &CommandEnvelope<'_>, next: Next<'_>) -> DecisionResult {
+            self.seen
+                .lock()
+                .unwrap()
+                .push((envelope.name, envelope.origin));
+            if envelope.origin == self.denied {
+                return Err(DecisionError::Domain(CartError::CartCannotBeAltered));
+            }
+            next.run(envelope).await
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_sees_every_command_and_can_stop_it() {
+        let (_event_store, decider) = create_in_memory_eventstore_and_decider();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let bus = CommandBus::new(decider).with(OriginFilter {
+            denied: CommandOrigin::Kafka,
+            seen: seen.clone(),
+        });
+        let cart_id = CartId::new();
+
+        let accepted = bus
+            .dispatch(
+                AddItemCommand {
+                    cart_id,
+                    ..Faker.fake()
+                },
+                CommandOrigin::Http,
+            )
+            .await;
+        let denied = bus
+            .dispatch(
+                AddItemCommand {
+                    cart_id,
+                    ..Faker.fake()
+                },
+                CommandOrigin::Kafka,
+            )
+            .await;
+
+        assert_eq!(accepted.unwrap().len(), 2);
+        assert!(matches!(
+            denied,
+            Err(DecisionError::Domain(CartError::CartCannotBeAltered))
+        ));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                ("AddItemCommand", CommandOrigin::Http),
+                ("AddItemCommand", CommandOrigin::Kafka)
+            ]
+        );
+    }
+
+    #[test]
+    fn exhausted_conflicts_are_a_client_conflict() {
+        let decision_error = DecisionError::<CartError>::StateStore(Box::new(
+            disintegrate_postgres::Error::Concurrency,
+        ));
+        let client_error: ClientError = decision_error.into();
+        assert!(matches!(client_error, ClientError::Conflict(_)));
+    }
+}
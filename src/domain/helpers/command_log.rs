@@ -0,0 +1,280 @@
+//! Persistent log of every command.
+//!
+//! Only accepted commands leave events behind, so a command the domain rejected, e.g. with
+//! `CartError::CannotAddItemCartFull`, would otherwise leave no trace. The `CommandLog`
+//! middleware records every command dispatched through the `CommandBus` in the `command_log`
+//! table: its payload, origin, outcome, the ids of the events it appended or the error it failed
+//! with, and how long it took. Support can then see why a customer's action failed.
+//! Alongside the in-memory event store there is no table and nothing is logged.
+
+use std::time::Instant;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use disintegrate::DecisionError;
+use jiff_sqlx::ToSqlx;
+use sqlx::PgPool;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        cart::CartId,
+        helpers::command_bus::{CommandEnvelope, Middleware, Next},
+    },
+    infra::ClientError,
+};
+
+use super::decision_maker::DecisionResult;
+
+/// Most entries returned by one query.
+const MAX_ENTRIES: i64 = 1000;
+
+/// How a dispatched command ended.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, strum_macros::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum CommandOutcome {
+    Accepted,
+    /// Refused by the domain.
+    Rejected,
+    Failed,
+}
+
+impl CommandOutcome {
+    fn of(result: &DecisionResult) -> Self {
+        match result {
+            Ok(_) => CommandOutcome::Accepted,
+            Err(DecisionError::Domain(_)) => CommandOutcome::Rejected,
+            Err(_) => CommandOutcome::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CommandLogEntry {
+    pub command_log_id: i64,
+    pub command_name: String,
+    pub origin: String,
+    pub cart_id: Option<Uuid>,
+    pub payload: serde_json::Value,
+    pub outcome: String,
+    pub event_ids: Vec<i64>,
+    pub error: Option<String>,
+    pub latency_us: i64,
+    pub dispatched_at: jiff::Timestamp,
+}
+
+/// Filters for `CommandLog::find`. Entries are returned newest first.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct CommandLogFilter {
+    pub cart_id: Option<Uuid>,
+    pub command_name: Option<String>,
+    pub outcome: Option<CommandOutcome>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Clone)]
+pub struct CommandLog {
+    pool: Option<PgPool>,
+}
+
+impl CommandLog {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool: Some(pool) }
+    }
+
+    pub fn in_memory() -> Self {
+        Self { pool: None }
+    }
+
+    async fn record(
+        &self,
+        envelope: &CommandEnvelope<'_>,
+        result: &DecisionResult,
+        started: Instant,
+        dispatched_at: jiff::Timestamp,
+    ) -> Result<(), anyhow::Error> {
+        let Some(pool) = &self.pool else {
+            return Ok(());
+        };
+        let (event_ids, error) = match result {
+            Ok(events) => (events.iter().map(|e| e.id()).collect(), None),
+            Err(DecisionError::Domain(cart_error)) => (Vec::new(), Some(cart_error.to_string())),
+            Err(e) => (Vec::new(), Some(e.to_string())),
+        };
+        let latency_us = i64::try_from(started.elapsed().as_micros()).unwrap_or(i64::MAX);
+
+        sqlx::query!(
+            r#"INSERT INTO command_log
+                   (command_name, origin, cart_id, payload, outcome, event_ids, error, latency_us, dispatched_at)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+            envelope.name,
+            envelope.origin.to_string(),
+            envelope.command.cart_id() as Option<CartId>,
+            envelope.command.payload(),
+            CommandOutcome::of(result).to_string(),
+            &event_ids,
+            error,
+            latency_us,
+            dispatched_at.to_sqlx() as jiff_sqlx::Timestamp,
+        )
+        .execute(pool)
+        .await
+        .with_context(|| format!("Problem in record(command: {}).", envelope.name))?;
+
+        Ok(())
+    }
+
+    pub async fn find(
+        &self,
+        filter: &CommandLogFilter,
+    ) -> Result<Vec<CommandLogEntry>, anyhow::Error> {
+        let Some(pool) = &self.pool else {
+            return Ok(Vec::new());
+        };
+        let limit = filter.limit.unwrap_or(100).clamp(1, MAX_ENTRIES);
+
+        let rows = sqlx::query!(
+            r#"SELECT command_log_id, command_name, origin, cart_id, payload, outcome, event_ids,
+                      error, latency_us, dispatched_at as "dispatched_at: jiff_sqlx::Timestamp"
+               FROM command_log
+               WHERE ($1::UUID IS NULL OR cart_id = $1)
+                 AND ($2::TEXT IS NULL OR command_name = $2)
+                 AND ($3::TEXT IS NULL OR outcome = $3)
+               ORDER BY command_log_id DESC
+               LIMIT $4"#,
+            filter.cart_id,
+            filter.command_name,
+            filter.outcome.map(|outcome| outcome.to_string()),
+            limit,
+        )
+        .fetch_all(pool)
+        .await
+        .with_context(|| format!("Problem in find(filter: {filter:?})."))?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| CommandLogEntry {
+                command_log_id: row.command_log_id,
+                command_name: row.command_name,
+                origin: row.origin,
+                cart_id: row.cart_id,
+                payload: row.payload,
+                outcome: row.outcome,
+                event_ids: row.event_ids,
+                error: row.error,
+                latency_us: row.latency_us,
+                dispatched_at: row.dispatched_at.to_jiff(),
+            })
+            .collect())
+    }
+}
+
+/// Logs the command once it has run. A command is never failed because it could not be logged.
+#[async_trait]
+impl Middleware for CommandLog {
+    async fn handle(&self, envelope: &CommandEnvelope<'_>, next: Next<'_>) -> DecisionResult {
+        let dispatched_at = jiff::Timestamp::now();
+        let started = Instant::now();
+        let result = next.run(envelope).await;
+        if let Err(e) = self.record(envelope, &result, started, dispatched_at).await {
+            error!("CommandLog: {e:?}");
+        }
+        result
+    }
+}
+
+//------------------------- Web API ----------------------------
+
+/// e.g. `GET /commandlog?cart_id=...&outcome=rejected`
+pub async fn command_log_endpoint(
+    State(command_log): State<CommandLog>,
+    Query(filter): Query<CommandLogFilter>,
+) -> Result<Json<Vec<CommandLogEntry>>, ClientError> {
+    Ok(Json(command_log.find(&filter).await?))
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use fake::{Fake, Faker};
+
+    use super::*;
+    use crate::domain::{
+        CommandBus, CommandOrigin,
+        cart::{AddItemCommand, CartError, RemoveItemCommand},
+        create_in_memory_eventstore_and_decider,
+    };
+
+    #[sqlx::test]
+    async fn accepted_and_rejected_commands_are_logged(pool: PgPool) {
+        let (_event_store, decider) = create_in_memory_eventstore_and_decider();
+        let command_log = CommandLog::new(pool);
+        let bus = CommandBus::new(decider).with(command_log.clone());
+        let cart_id = CartId::new();
+        let add_item = AddItemCommand {
+            cart_id,
+            ..Faker.fake()
+        };
+
+        let events = bus
+            .dispatch(add_item.clone(), CommandOrigin::Http)
+            .await
+            .unwrap();
+        let _ = bus
+            .dispatch(
+                RemoveItemCommand {
+                    cart_id,
+                    item_id: Faker.fake(),
+                },
+                CommandOrigin::Kafka,
+            )
+            .await;
+
+        let entries = command_log
+            .find(&CommandLogFilter {
+                cart_id: Some(cart_id.into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let rejected = &entries[0];
+        assert_eq!(rejected.command_name, "RemoveItemCommand");
+        assert_eq!(rejected.origin, "kafka");
+        assert_eq!(rejected.outcome, "rejected");
+        assert_eq!(
+            rejected.error.as_deref(),
+            Some(CartError::CannotRemoveItem.to_string().as_str())
+        );
+        assert!(rejected.event_ids.is_empty());
+
+        let accepted = &entries[1];
+        assert_eq!(accepted.command_name, "AddItemCommand");
+        assert_eq!(accepted.outcome, "accepted");
+        assert_eq!(
+            accepted.event_ids,
+            events.iter().map(|e| e.id()).collect::<Vec<_>>()
+        );
+        assert_eq!(accepted.payload["description"], add_item.description);
+        assert!(accepted.payload.get("fingerprint").is_none());
+
+        let only_rejected = command_log
+            .find(&CommandLogFilter {
+                outcome: Some(CommandOutcome::Rejected),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(only_rejected.len(), 1);
+    }
+}
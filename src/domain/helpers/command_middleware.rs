@@ -0,0 +1,384 @@
+//! Middleware used by the `CommandBus`.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use backon::{ExponentialBuilder, Retryable};
+use disintegrate::DecisionError;
+use tracing::{Instrument, debug, error, info, info_span, warn};
+
+use crate::domain::cart::CartError;
+
+use super::{
+    command_bus::{CommandEnvelope, Middleware, Next},
+    decision_maker::{DecisionResult, is_concurrency_conflict},
+};
+
+/// Number of times a decision is re-run after a concurrency conflict.
+const CONFLICT_RETRIES: usize = 5;
+
+//------------------------ Authorization -------------------------
+
+/// Rejects commands dispatched from an origin not in `Command::origins`.
+pub struct Authorization;
+
+#[async_trait]
+impl Middleware for Authorization {
+    async fn handle(&self, envelope: &CommandEnvelope<'_>, next: Next<'_>) -> DecisionResult {
+        if !envelope.command.origins().contains(&envelope.origin) {
+            return Err(DecisionError::Domain(CartError::CommandNotAllowed {
+                command: envelope.name,
+                origin: envelope.origin,
+            }));
+        }
+        next.run(envelope).await
+    }
+}
+
+//------------------------- Validation ---------------------------
+
+/// Rejects commands that fail `Command::validate`.
+pub struct Validation;
+
+#[async_trait]
+impl Middleware for Validation {
+    async fn handle(&self, envelope: &CommandEnvelope<'_>, next: Next<'_>) -> DecisionResult {
+        envelope.command.validate().map_err(DecisionError::Domain)?;
+        next.run(envelope).await
+    }
+}
+
+//--------------------------- Tracing ----------------------------
+
+/// Runs the command in a span and logs its outcome. The command is logged as its payload, which
+/// skips personal data.
+pub struct Tracing;
+
+#[async_trait]
+impl Middleware for Tracing {
+    async fn handle(&self, envelope: &CommandEnvelope<'_>, next: Next<'_>) -> DecisionResult {
+        let span = info_span!("command", name = envelope.name, origin = %envelope.origin);
+        async {
+            debug!("{}", envelope.command.payload());
+            let result = next.run(envelope).await;
+            match &result {
+                Ok(events) => debug!("Accepted with {} events.", events.len()),
+                Err(DecisionError::Domain(e)) => info!("Rejected: {e}"),
+                Err(e) => error!("Failed: {e}"),
+            }
+            result
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+//--------------------------- Metrics ----------------------------
+
+/// Counts of the outcomes of one type of command since the server started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct CommandCounts {
+    pub dispatched: u64,
+    pub accepted: u64,
+    /// Refused by the domain, e.g. `CartError::CannotAddItemCartFull`.
+    pub rejected: u64,
+    pub failed: u64,
+    /// Every concurrency conflict, including those resolved by retrying the decision.
+    pub conflicts: u64,
+    /// Commands that still conflicted once all retries were used.
+    pub retries_exhausted: u64,
+}
+
+/// Concurrency conflicts met by decisions since the server started, across every type of command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct ConflictCounts {
+    /// Every conflict, including those resolved by re-running the decision.
+    pub conflicts: u64,
+    /// Decisions that still conflicted once all retries were used.
+    pub retries_exhausted: u64,
+}
+
+/// Command counts keyed by command name.
+#[derive(Clone, Default)]
+pub struct CommandMetrics {
+    counts: Arc<Mutex<BTreeMap<&'static str, CommandCounts>>>,
+}
+
+impl CommandMetrics {
+    pub fn counts(&self) -> BTreeMap<&'static str, CommandCounts> {
+        self.counts
+            .lock()
+            .expect("CommandMetrics lock should not be poisoned.")
+            .clone()
+    }
+
+    pub fn conflict_counts(&self) -> ConflictCounts {
+        self.counts()
+            .values()
+            .fold(ConflictCounts::default(), |total, counts| ConflictCounts {
+                conflicts: total.conflicts + counts.conflicts,
+                retries_exhausted: total.retries_exhausted + counts.retries_exhausted,
+            })
+    }
+
+    fn record(&self, name: &'static str, update: impl FnOnce(&mut CommandCounts)) {
+        update(
+            self.counts
+                .lock()
+                .expect("CommandMetrics lock should not be poisoned.")
+                .entry(name)
+                .or_default(),
+        );
+    }
+}
+
+/// Records the outcome of every command.
+pub struct Metrics {
+    metrics: CommandMetrics,
+}
+
+impl Metrics {
+    pub fn new(metrics: CommandMetrics) -> Self {
+        Self { metrics }
+    }
+}
+
+#[async_trait]
+impl Middleware for Metrics {
+    async fn handle(&self, envelope: &CommandEnvelope<'_>, next: Next<'_>) -> DecisionResult {
+        let result = next.run(envelope).await;
+        self.metrics.record(envelope.name, |counts| {
+            counts.dispatched += 1;
+            match &result {
+                Ok(_) => counts.accepted += 1,
+                Err(DecisionError::Domain(_)) => counts.rejected += 1,
+                Err(_) => counts.failed += 1,
+            }
+        });
+        result
+    }
+}
+
+//---------------------------- Retry -----------------------------
+
+/// Re-runs a decision, against the updated state and with backoff, when its events conflict with
+/// events appended concurrently.
+pub struct Retry {
+    metrics: CommandMetrics,
+}
+
+impl Retry {
+    pub fn new(metrics: CommandMetrics) -> Self {
+        Self { metrics }
+    }
+}
+
+#[async_trait]
+impl Middleware for Retry {
+    async fn handle(&self, envelope: &CommandEnvelope<'_>, next: Next<'_>) -> DecisionResult {
+        let result = (|| next.run(envelope))
+            .retry(
+                ExponentialBuilder::default()
+                    .with_min_delay(Duration::from_millis(10))
+                    .with_max_delay(Duration::from_millis(200))
+                    .with_max_times(CONFLICT_RETRIES)
+                    .with_jitter(),
+            )
+            .when(is_concurrency_conflict)
+            .sleep(tokio::time::sleep)
+            .notify(|_err, dur| {
+                self.metrics
+                    .record(envelope.name, |counts| counts.conflicts += 1);
+                warn!(
+                    "Retry: Concurrency conflict on {}. Retrying after {dur:?}.",
+                    envelope.name
+                );
+            })
+            .await;
+
+        if let Err(e) = &result
+            && is_concurrency_conflict(e)
+        {
+            self.metrics.record(envelope.name, |counts| {
+                counts.conflicts += 1;
+                counts.retries_exhausted += 1;
+            });
+            warn!(
+                "Retry: Concurrency conflict on {} persisted after {CONFLICT_RETRIES} retries.",
+                envelope.name
+            );
+        }
+        result
+    }
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::{FutureExt, future::BoxFuture};
+
+    use super::*;
+    use crate::domain::{
+        ExpectedEventId,
+        cart::CartId,
+        helpers::command_bus::{Command, CommandOrigin},
+    };
+
+    #[derive(Debug, serde::Serialize)]
+    struct TestCommand {
+        valid: bool,
+    }
+
+    impl Command for TestCommand {
+        fn validate(&self) -> Result<(), CartError> {
+            if self.valid {
+                Ok(())
+            } else {
+                Err(CartError::CartDoesNotExist(CartId::new()))
+            }
+        }
+    }
+
+    fn conflict() -> DecisionResult {
+        Err(DecisionError::StateStore(Box::new(
+            disintegrate_postgres::Error::Concurrency,
+        )))
+    }
+
+    /// Runs the middleware against a decision that conflicts `conflicts` times before succeeding.
+    /// Returns the result and the number of times the decision was made.
+    async fn run(
+        middleware: Vec<Arc<dyn Middleware>>,
+        command: &TestCommand,
+        conflicts: usize,
+    ) -> (DecisionResult, usize) {
+        run_from(CommandOrigin::Http, middleware, command, conflicts).await
+    }
+
+    async fn run_from(
+        origin: CommandOrigin,
+        middleware: Vec<Arc<dyn Middleware>>,
+        command: &TestCommand,
+        conflicts: usize,
+    ) -> (DecisionResult, usize) {
+        let attempts = AtomicUsize::new(0);
+        let decide = || -> BoxFuture<'_, DecisionResult> {
+            let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+            async move {
+                if attempt < conflicts {
+                    conflict()
+                } else {
+                    Ok(Vec::new())
+                }
+            }
+            .boxed()
+        };
+        let envelope = CommandEnvelope {
+            name: "TestCommand",
+            origin,
+            expected_event_id: ExpectedEventId(None),
+            command,
+        };
+        let result = Next::new(&middleware, &decide).run(&envelope).await;
+        (result, attempts.load(Ordering::Relaxed))
+    }
+
+    #[tokio::test]
+    async fn conflicting_decision_is_retried() {
+        let metrics = CommandMetrics::default();
+        let middleware: Vec<Arc<dyn Middleware>> = vec![Arc::new(Retry::new(metrics.clone()))];
+
+        let (result, attempts) = run(middleware, &TestCommand { valid: true }, 2).await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts, 3);
+        assert_eq!(metrics.counts()["TestCommand"].conflicts, 2);
+        assert_eq!(metrics.counts()["TestCommand"].retries_exhausted, 0);
+        assert_eq!(
+            metrics.conflict_counts(),
+            ConflictCounts {
+                conflicts: 2,
+                retries_exhausted: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn conflict_is_reported_once_retries_are_exhausted() {
+        let metrics = CommandMetrics::default();
+        let middleware: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(Metrics::new(metrics.clone())),
+            Arc::new(Retry::new(metrics.clone())),
+        ];
+
+        let (result, attempts) = run(middleware, &TestCommand { valid: true }, usize::MAX).await;
+
+        assert!(is_concurrency_conflict(&result.unwrap_err()));
+        assert_eq!(attempts, CONFLICT_RETRIES + 1);
+        assert_eq!(
+            metrics.counts()["TestCommand"],
+            CommandCounts {
+                dispatched: 1,
+                failed: 1,
+                conflicts: CONFLICT_RETRIES as u64 + 1,
+                retries_exhausted: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_command_is_rejected_before_it_is_decided() {
+        let metrics = CommandMetrics::default();
+        let middleware: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(Metrics::new(metrics.clone())),
+            Arc::new(Validation),
+        ];
+
+        let (result, attempts) = run(middleware, &TestCommand { valid: false }, 0).await;
+
+        assert!(matches!(
+            result,
+            Err(DecisionError::Domain(CartError::CartDoesNotExist(_)))
+        ));
+        assert_eq!(attempts, 0);
+        assert_eq!(metrics.counts()["TestCommand"].rejected, 1);
+    }
+
+    #[tokio::test]
+    async fn command_from_a_disallowed_origin_is_rejected_before_it_is_decided() {
+        let command = TestCommand { valid: true };
+
+        let (allowed, _) = run_from(
+            CommandOrigin::Http,
+            vec![Arc::new(Authorization)],
+            &command,
+            0,
+        )
+        .await;
+        let (denied, attempts) = run_from(
+            CommandOrigin::Kafka,
+            vec![Arc::new(Authorization)],
+            &command,
+            0,
+        )
+        .await;
+
+        assert!(allowed.is_ok());
+        assert!(matches!(
+            denied,
+            Err(DecisionError::Domain(CartError::CommandNotAllowed {
+                command: "TestCommand",
+                origin: CommandOrigin::Kafka
+            }))
+        ));
+        assert_eq!(attempts, 0);
+    }
+}
@@ -0,0 +1,584 @@
+//! Crypto-shredding of personal data held in events.
+//!
+//! Events are immutable, so personal data written to the event store can never be deleted.
+//! Instead each cart is given its own encryption key and personal data is encrypted with that key
+//! as the event is serialised. Forgetting a cart deletes its key, after which the personal data
+//! can no longer be decrypted and is rendered as [`REDACTED`] whenever the event is read.
+//!
+//! Encryption happens inside the event store's Serde implementation so every reader of events,
+//! projections and live read models alike, sees either the decrypted or the redacted value and
+//! never has to know encryption is taking place.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng},
+};
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use disintegrate::serde::{Deserializer, Serializer};
+use sqlx::{PgExecutor, PgPool};
+use tracing::{error, warn};
+
+use crate::domain::{DomainEvent, cart::CartId};
+
+use super::event_codec::EventCodec;
+
+/// The value personal data is replaced with once a cart has been forgotten.
+pub const REDACTED: &str = "[redacted]";
+
+/// Prefix identifying an encrypted value.
+const ENCRYPTED_PREFIX: &str = "enc:";
+
+/// Size in bytes of the AES-GCM nonce stored in front of the ciphertext.
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone)]
+enum CartKey {
+    Active(Key<Aes256Gcm>),
+    Forgotten,
+}
+
+impl CartKey {
+    fn from_column(key: Option<Vec<u8>>) -> Self {
+        match key {
+            Some(key) => CartKey::Active(*Key::<Aes256Gcm>::from_slice(&key)),
+            None => CartKey::Forgotten,
+        }
+    }
+}
+
+/// Holds the encryption key of every cart.
+/// Keys are stored in the `cart_key` table and cached in memory because the event store
+/// serialises and deserialises events synchronously. The event store ensures a cart has a key
+/// before its events are appended and loads keys it has not cached, e.g. those created by another
+/// instance, before it reads on. An in-memory KeyStore has no table and only holds keys for as
+/// long as the process runs.
+#[derive(Clone)]
+pub struct KeyStore {
+    pool: Option<PgPool>,
+    keys: Arc<RwLock<HashMap<CartId, CartKey>>>,
+}
+
+impl KeyStore {
+    /// Loads all known keys into memory.
+    pub async fn load(pool: &PgPool) -> Result<Self, sqlx::Error> {
+        let rows = sqlx::query!(r#"SELECT cart_id as "cart_id: CartId", key FROM cart_key"#)
+            .fetch_all(pool)
+            .await?;
+
+        let keys = rows
+            .into_iter()
+            .map(|row| (row.cart_id, CartKey::from_column(row.key)))
+            .collect();
+
+        Ok(Self {
+            pool: Some(pool.clone()),
+            keys: Arc::new(RwLock::new(keys)),
+        })
+    }
+
+    /// A KeyStore whose keys are never persisted, for use with the in-memory event store.
+    pub fn in_memory() -> Self {
+        Self {
+            pool: None,
+            keys: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Ensures a key exists for the cart before any of its personal data is appended to the
+    /// event store. If another process created the key first, that key is adopted.
+    pub async fn ensure_key(&self, cart_id: &CartId) -> Result<(), sqlx::Error> {
+        if let Some(CartKey::Active(_) | CartKey::Forgotten) = self.cached(cart_id) {
+            return Ok(());
+        }
+
+        let key = Aes256Gcm::generate_key(OsRng);
+        let Some(pool) = &self.pool else {
+            self.cache(*cart_id, CartKey::Active(key));
+            return Ok(());
+        };
+        let stored_key = persist_key(pool, cart_id, key.as_slice()).await?;
+        self.cache(*cart_id, CartKey::from_column(stored_key));
+        Ok(())
+    }
+
+    /// Ensures a key exists for every cart whose personal data is held in the events.
+    pub async fn ensure_keys(&self, events: &[DomainEvent]) -> Result<(), sqlx::Error> {
+        for event in events {
+            if let DomainEvent::CartItemAdded { cart_id, .. } = event {
+                self.ensure_key(cart_id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the key of a cart that is not cached, e.g. one created by another instance since
+    /// the keys were loaded, once data encrypted with it has been read. Returns false if the cart
+    /// has no key. A miss is not cached, so a key stored since is found the next time.
+    pub async fn prefetch(&self, cart_id: &CartId) -> Result<bool, sqlx::Error> {
+        if self.cached(cart_id).is_some() {
+            return Ok(true);
+        }
+        let Some(pool) = &self.pool else {
+            return Ok(false);
+        };
+        match load_key(pool, cart_id).await? {
+            Some(key) => {
+                self.cache(*cart_id, CartKey::from_column(key));
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Deletes the key of a cart. Its personal data can no longer be decrypted.
+    pub async fn forget(&self, cart_id: &CartId) -> Result<(), anyhow::Error> {
+        if let Some(pool) = &self.pool {
+            sqlx::query!(
+                r#"INSERT INTO cart_key (cart_id, key, forgotten_at)
+               VALUES ($1, NULL, now())
+               ON CONFLICT(cart_id)
+               DO UPDATE SET
+                  key = NULL,
+                  forgotten_at = now()"#,
+                cart_id as &CartId
+            )
+            .execute(pool)
+            .await
+            .with_context(|| format!("Problem in forget(cart_id: {cart_id})."))?;
+        }
+
+        self.cache(*cart_id, CartKey::Forgotten);
+        Ok(())
+    }
+
+    /// Returns true if the cart has been forgotten.
+    pub fn is_forgotten(&self, cart_id: &CartId) -> bool {
+        matches!(self.cached(cart_id), Some(CartKey::Forgotten))
+    }
+
+    fn cached(&self, cart_id: &CartId) -> Option<CartKey> {
+        self.keys
+            .read()
+            .expect("KeyStore lock should not be poisoned.")
+            .get(cart_id)
+            .cloned()
+    }
+
+    fn cache(&self, cart_id: CartId, key: CartKey) {
+        self.keys
+            .write()
+            .expect("KeyStore lock should not be poisoned.")
+            .insert(cart_id, key);
+    }
+
+    /// Loads an uncached key without waiting for it, so a reader that cannot wait, e.g. an
+    /// event listener, finds it cached when it tries again.
+    fn prefetch_in_background(&self, cart_id: CartId) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let key_store = self.clone();
+        runtime.spawn(async move {
+            if let Err(e) = key_store.prefetch(&cart_id).await {
+                warn!("KeyStore: Failed to load key for cart {cart_id}. {e}");
+            }
+        });
+    }
+
+    fn encrypt(&self, cart_id: &CartId, plain_text: &str) -> String {
+        match self.cached(cart_id) {
+            Some(CartKey::Active(key)) => {
+                let cipher = Aes256Gcm::new(&key);
+                let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+                let cipher_text = cipher
+                    .encrypt(&nonce, plain_text.as_bytes())
+                    .expect("AES-GCM encryption should not fail.");
+                let mut payload = nonce.to_vec();
+                payload.extend(cipher_text);
+                format!("{ENCRYPTED_PREFIX}{}", STANDARD.encode(payload))
+            }
+            Some(CartKey::Forgotten) => REDACTED.to_owned(),
+            // Data encrypted with a key that was never stored could not be decrypted after a
+            // restart, so it is not stored at all.
+            None => {
+                error!("KeyStore: No key ensured for cart {cart_id}. Personal data is redacted.");
+                REDACTED.to_owned()
+            }
+        }
+    }
+
+    fn decrypt(&self, cart_id: &CartId, value: String) -> Result<String, CryptoError> {
+        // Values written before crypto-shredding was introduced are stored as plain text.
+        let Some(encoded) = value.strip_prefix(ENCRYPTED_PREFIX) else {
+            return Ok(value);
+        };
+        let key = match self.cached(cart_id) {
+            Some(CartKey::Active(key)) => key,
+            Some(CartKey::Forgotten) => return Ok(REDACTED.to_owned()),
+            None => {
+                self.prefetch_in_background(*cart_id);
+                return Err(CryptoError::MissingKey(*cart_id));
+            }
+        };
+
+        let payload = STANDARD.decode(encoded)?;
+        if payload.len() < NONCE_LEN {
+            return Err(CryptoError::Truncated);
+        }
+        let (nonce, cipher_text) = payload.split_at(NONCE_LEN);
+        let plain_text = Aes256Gcm::new(&key)
+            .decrypt(Nonce::from_slice(nonce), cipher_text)
+            .map_err(|_| CryptoError::Decryption)?;
+        Ok(String::from_utf8(plain_text)?)
+    }
+}
+
+async fn persist_key(
+    executor: impl PgExecutor<'_>,
+    cart_id: &CartId,
+    key: &[u8],
+) -> Result<Option<Vec<u8>>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"INSERT INTO cart_key (cart_id, key)
+           VALUES ($1, $2)
+           ON CONFLICT(cart_id)
+           DO UPDATE SET cart_id = EXCLUDED.cart_id
+           RETURNING key"#,
+        cart_id as &CartId,
+        key
+    )
+    .fetch_one(executor)
+    .await
+}
+
+/// The cart's key, `Some(None)` if it has been forgotten, or `None` if it has never had one.
+async fn load_key(
+    executor: impl PgExecutor<'_>,
+    cart_id: &CartId,
+) -> Result<Option<Option<Vec<u8>>>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT key FROM cart_key WHERE cart_id = $1",
+        cart_id as &CartId
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+/// The cart whose key was not cached when an event failed to deserialise, if that is why it
+/// failed.
+pub fn missing_key(error: &disintegrate_serde::Error) -> Option<CartId> {
+    let disintegrate_serde::Error::Deserialization(error) = error else {
+        return None;
+    };
+    match error.downcast_ref::<CryptoError>()? {
+        CryptoError::MissingKey(cart_id) => Some(*cart_id),
+        _ => None,
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum CryptoError {
+    #[error(transparent)]
+    Encoding(#[from] base64::DecodeError),
+    #[error("Encrypted value is truncated.")]
+    Truncated,
+    #[error("Encrypted value could not be decrypted.")]
+    Decryption,
+    #[error("No key is known for cart {0}.")]
+    MissingKey(CartId),
+    #[error(transparent)]
+    Utf8(#[from] std::string::FromUtf8Error),
+}
+
+//------------------------- Serde --------------------------
+
+/// Serialisation of DomainEvents which encrypts personal data on write and decrypts, or redacts,
+/// it on read. Events are written with the configured codec and read whatever their codec.
+#[derive(Clone)]
+pub struct EventSerde {
+    key_store: KeyStore,
+    codec: EventCodec,
+}
+
+impl EventSerde {
+    pub fn new(key_store: KeyStore, codec: EventCodec) -> Self {
+        Self { key_store, codec }
+    }
+
+    pub fn key_store(&self) -> &KeyStore {
+        &self.key_store
+    }
+
+    /// Deserialises an event read outside the event store, loading its cart's key first if it
+    /// is not cached.
+    pub async fn deserialize_loading_key(
+        &self,
+        data: Vec<u8>,
+    ) -> Result<DomainEvent, disintegrate_serde::Error> {
+        match self.deserialize(data.clone()) {
+            Err(error) => match missing_key(&error) {
+                Some(cart_id) if self.key_store.prefetch(&cart_id).await.unwrap_or(false) => {
+                    self.deserialize(data)
+                }
+                _ => Err(error),
+            },
+            event => event,
+        }
+    }
+}
+
+impl Serializer<DomainEvent> for EventSerde {
+    fn serialize(&self, event: DomainEvent) -> Vec<u8> {
+        let event = match event {
+            DomainEvent::CartItemAdded {
+                cart_id,
+                description,
+                image,
+                price,
+                item_id,
+                product_id,
+                fingerprint,
+            } => DomainEvent::CartItemAdded {
+                fingerprint: self.key_store.encrypt(&cart_id, &fingerprint),
+                cart_id,
+                description,
+                image,
+                price,
+                item_id,
+                product_id,
+            },
+            event => event,
+        };
+        self.codec.encode(&event)
+    }
+}
+
+impl Deserializer<DomainEvent> for EventSerde {
+    fn deserialize(&self, data: Vec<u8>) -> Result<DomainEvent, disintegrate_serde::Error> {
+        match EventCodec::decode(&data)? {
+            DomainEvent::CartItemAdded {
+                cart_id,
+                description,
+                image,
+                price,
+                item_id,
+                product_id,
+                fingerprint,
+            } => Ok(DomainEvent::CartItemAdded {
+                fingerprint: self
+                    .key_store
+                    .decrypt(&cart_id, fingerprint)
+                    .map_err(|e| disintegrate_serde::Error::Deserialization(Box::new(e)))?,
+                cart_id,
+                description,
+                image,
+                price,
+                item_id,
+                product_id,
+            }),
+            event => Ok(event),
+        }
+    }
+}
+
+/// Replaces the personal data held in an event with [`REDACTED`], e.g. before returning events
+/// that never pass through the event store to a client.
+pub fn redact_personal_data(event: DomainEvent) -> DomainEvent {
+    match event {
+        DomainEvent::CartItemAdded {
+            cart_id,
+            description,
+            image,
+            price,
+            item_id,
+            product_id,
+            ..
+        } => DomainEvent::CartItemAdded {
+            cart_id,
+            description,
+            image,
+            price,
+            item_id,
+            product_id,
+            fingerprint: REDACTED.to_owned(),
+        },
+        event => event,
+    }
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use disintegrate::{EventStore as _, StreamQuery, query};
+    use disintegrate_postgres::PgEventId;
+    use futures::TryStreamExt;
+
+    use crate::domain::{cart::ItemId, cart::ProductId, create_eventstore, fake::FingerPrint};
+
+    use super::*;
+    use fake::{Fake, Faker};
+
+    fn cart_item_added(cart_id: CartId, fingerprint: String) -> DomainEvent {
+        DomainEvent::CartItemAdded {
+            cart_id,
+            description: Faker.fake(),
+            image: Faker.fake(),
+            price: Faker.fake(),
+            item_id: ItemId::new(),
+            product_id: ProductId::new(),
+            fingerprint,
+        }
+    }
+
+    fn fingerprint_of(event: &DomainEvent) -> String {
+        match event {
+            DomainEvent::CartItemAdded { fingerprint, .. } => fingerprint.clone(),
+            _ => panic!("Event not a CartItemAdded event!"),
+        }
+    }
+
+    #[sqlx::test]
+    async fn personal_data_is_encrypted_and_decrypted(pool: PgPool) {
+        let key_store = KeyStore::load(&pool).await.expect("Keys should load.");
+        let serde = EventSerde::new(key_store.clone(), EventCodec::Json);
+        let cart_id = CartId::new();
+        key_store
+            .ensure_key(&cart_id)
+            .await
+            .expect("Key should be created.");
+        let fingerprint: String = FingerPrint.fake();
+        let event = cart_item_added(cart_id, fingerprint.clone());
+
+        let data = serde.serialize(event.clone());
+
+        assert!(!String::from_utf8_lossy(&data).contains(&fingerprint));
+        let read_event = serde.deserialize(data).expect("Event should deserialize.");
+        assert_eq!(read_event, event);
+    }
+
+    #[sqlx::test]
+    async fn personal_data_is_redacted_once_cart_is_forgotten(pool: PgPool) {
+        let key_store = KeyStore::load(&pool).await.expect("Keys should load.");
+        let serde = EventSerde::new(key_store.clone(), EventCodec::Json);
+        let cart_id = CartId::new();
+        key_store
+            .ensure_key(&cart_id)
+            .await
+            .expect("Key should be created.");
+        let data = serde.serialize(cart_item_added(cart_id, FingerPrint.fake()));
+
+        key_store
+            .forget(&cart_id)
+            .await
+            .expect("Key should be forgotten.");
+
+        let read_event = serde.deserialize(data).expect("Event should deserialize.");
+        assert_eq!(fingerprint_of(&read_event), REDACTED);
+
+        // A fresh KeyStore must also see the cart as forgotten.
+        let key_store = KeyStore::load(&pool).await.expect("Keys should load.");
+        key_store
+            .ensure_key(&cart_id)
+            .await
+            .expect("Ensuring a key should succeed.");
+        assert!(key_store.is_forgotten(&cart_id));
+    }
+
+    #[sqlx::test]
+    async fn plain_text_personal_data_is_still_readable(pool: PgPool) {
+        let key_store = KeyStore::load(&pool).await.expect("Keys should load.");
+        let serde = EventSerde::new(key_store, EventCodec::Json);
+        let event = cart_item_added(CartId::new(), FingerPrint.fake());
+
+        let data = EventCodec::Json.encode(&event);
+
+        let read_event = serde.deserialize(data).expect("Event should deserialize.");
+        assert_eq!(read_event, event);
+    }
+
+    #[sqlx::test]
+    async fn key_created_elsewhere_is_loaded_rather_than_redacted(pool: PgPool) {
+        let stale_key_store = KeyStore::load(&pool).await.expect("Keys should load.");
+        let stale_event_store =
+            create_eventstore(&pool, EventSerde::new(stale_key_store, EventCodec::Json))
+                .await
+                .unwrap();
+        let key_store = KeyStore::load(&pool).await.expect("Keys should load.");
+        let event_store = create_eventstore(&pool, EventSerde::new(key_store, EventCodec::Json))
+            .await
+            .unwrap();
+        let cart_id = CartId::new();
+        let event = cart_item_added(cart_id, FingerPrint.fake());
+
+        // The event store ensures the cart has a key before appending.
+        event_store
+            .append_without_validation(vec![event.clone()])
+            .await
+            .expect("Event should be appended.");
+
+        let query: StreamQuery<PgEventId, DomainEvent> = query!(DomainEvent; cart_id == cart_id);
+        let read_events: Vec<DomainEvent> = stale_event_store
+            .stream(&query)
+            .map_ok(|event| event.into_inner())
+            .try_collect()
+            .await
+            .expect("Events should be read.");
+
+        assert_eq!(read_events, vec![event]);
+    }
+
+    #[sqlx::test]
+    async fn personal_data_without_a_key_is_redacted_rather_than_stored(pool: PgPool) {
+        let key_store = KeyStore::load(&pool).await.expect("Keys should load.");
+        let fingerprint: String = FingerPrint.fake();
+        let data = EventSerde::new(key_store.clone(), EventCodec::Json)
+            .serialize(cart_item_added(CartId::new(), fingerprint.clone()));
+
+        let read_event = EventSerde::new(key_store, EventCodec::Json)
+            .deserialize(data)
+            .expect("Event should deserialize.");
+
+        assert_eq!(fingerprint_of(&read_event), REDACTED);
+    }
+
+    #[tokio::test]
+    async fn unknown_key_is_an_error_not_a_redaction() {
+        let cart_id = CartId::new();
+        let key_store = KeyStore::in_memory();
+        key_store
+            .ensure_key(&cart_id)
+            .await
+            .expect("Key should be created.");
+        let data = EventSerde::new(key_store, EventCodec::Json)
+            .serialize(cart_item_added(cart_id, FingerPrint.fake()));
+
+        let result = EventSerde::new(KeyStore::in_memory(), EventCodec::Json)
+            .deserialize_loading_key(data)
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[sqlx::test]
+    async fn missing_key_is_found_once_stored(pool: PgPool) {
+        let key_store = KeyStore::load(&pool).await.expect("Keys should load.");
+        let cart_id = CartId::new();
+        assert!(!key_store.prefetch(&cart_id).await.unwrap());
+
+        KeyStore::load(&pool)
+            .await
+            .expect("Keys should load.")
+            .ensure_key(&cart_id)
+            .await
+            .expect("Key should be created.");
+
+        assert!(key_store.prefetch(&cart_id).await.unwrap());
+    }
+}
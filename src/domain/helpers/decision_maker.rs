@@ -0,0 +1,413 @@
+//! Makes decisions against the event store.
+//!
+//! Wraps Disintegrate's decision making so a decision can also be made on the condition that the
+//! client's view of the state is current. The client sends the id of the last event it has seen
+//! in an `If-Match` header and the decision fails with `CartError::CartChanged` if the state has
+//! moved on since. The condition is checked against the same state version that is used to
+//! validate the append, so it also holds when events are appended concurrently.
+//!
+//! Decisions are normally made through the `CommandBus`, which retries those that conflict with
+//! events appended concurrently.
+//!
+//! A decision can also be dry run. It is made against the current state but nothing is appended,
+//! so a client can preview its outcome, e.g. the `total_price` of a cart it is about to submit.
+
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use disintegrate::{
+    BoxDynError, Decision, DecisionError, EventSourcedStateStore, LoadState, LoadedState,
+    PersistDecision, PersistedEvent, WithSnapshot,
+};
+use disintegrate_postgres::{Error, PgEventId};
+
+use crate::{
+    domain::{DomainEvent, EventStore, Snapshotter, cart::CartError},
+    infra::ClientError,
+};
+
+use super::crypto_shredding::redact_personal_data;
+
+pub type StateStore = EventSourcedStateStore<
+    PgEventId,
+    DomainEvent,
+    EventStore,
+    WithSnapshot<PgEventId, Snapshotter>,
+>;
+
+pub type DecisionResult =
+    Result<Vec<PersistedEvent<PgEventId, DomainEvent>>, DecisionError<CartError>>;
+
+#[derive(Clone)]
+pub struct DecisionMaker {
+    event_store: EventStore,
+    state_store: StateStore,
+}
+
+impl DecisionMaker {
+    pub fn new(event_store: EventStore, snapshotter: Snapshotter) -> Self {
+        Self {
+            state_store: EventSourcedStateStore::new(
+                event_store.clone(),
+                WithSnapshot::new(snapshotter),
+            ),
+            event_store,
+        }
+    }
+
+    /// The event store decisions are made against.
+    pub fn event_store(&self) -> &EventStore {
+        &self.event_store
+    }
+
+    /// Makes the decision, persisting the resulting events in the event store.
+    pub async fn make<D, S>(&self, decision: D) -> DecisionResult
+    where
+        D: Decision<StateQuery = S, Event = DomainEvent, Error = CartError>,
+        StateStore:
+            LoadState<PgEventId, S, DomainEvent> + PersistDecision<PgEventId, S, DomainEvent>,
+    {
+        self.decide(&decision, ExpectedEventId(None)).await
+    }
+
+    /// Makes the decision only if no event newer than `expected_event_id` has changed its state.
+    pub async fn make_expecting<D, S>(
+        &self,
+        decision: D,
+        expected_event_id: ExpectedEventId,
+    ) -> DecisionResult
+    where
+        D: Decision<StateQuery = S, Event = DomainEvent, Error = CartError>,
+        StateStore:
+            LoadState<PgEventId, S, DomainEvent> + PersistDecision<PgEventId, S, DomainEvent>,
+    {
+        self.decide(&decision, expected_event_id).await
+    }
+
+    /// Makes the decision once. Borrows the decision so it can be made again, e.g. after a
+    /// concurrency conflict.
+    pub async fn decide<D, S>(
+        &self,
+        decision: &D,
+        ExpectedEventId(expected_event_id): ExpectedEventId,
+    ) -> DecisionResult
+    where
+        D: Decision<StateQuery = S, Event = DomainEvent, Error = CartError>,
+        StateStore:
+            LoadState<PgEventId, S, DomainEvent> + PersistDecision<PgEventId, S, DomainEvent>,
+    {
+        let loaded_state = self.load(decision).await?;
+        check_expected(&loaded_state, expected_event_id).map_err(DecisionError::Domain)?;
+        let changes = decision
+            .process(loaded_state.state())
+            .map_err(DecisionError::Domain)?;
+        self.state_store
+            .persist(loaded_state, changes, decision.validation_query())
+            .await
+            .map_err(DecisionError::StateStore)
+    }
+
+    /// Makes the decision against the current state without appending its events.
+    /// A decision the domain rejects is still a successful dry run.
+    pub async fn dry_run<D, S>(
+        &self,
+        decision: &D,
+        ExpectedEventId(expected_event_id): ExpectedEventId,
+    ) -> Result<DryRun<S>, DecisionError<CartError>>
+    where
+        D: Decision<StateQuery = S, Event = DomainEvent, Error = CartError>,
+        S: Clone,
+        StateStore: LoadState<PgEventId, S, DomainEvent>,
+    {
+        let loaded_state = self.load(decision).await?;
+        let outcome = check_expected(&loaded_state, expected_event_id)
+            .and_then(|()| decision.process(loaded_state.state()));
+        let (events, rejection) = match outcome {
+            Ok(events) => (events.into_iter().map(redact_personal_data).collect(), None),
+            Err(cart_error) => (Vec::new(), Some(cart_error.to_string())),
+        };
+        Ok(DryRun {
+            last_event_id: loaded_state.version(),
+            state: loaded_state.state().clone(),
+            events,
+            rejection,
+        })
+    }
+
+    async fn load<D, S>(
+        &self,
+        decision: &D,
+    ) -> Result<LoadedState<PgEventId, S>, DecisionError<CartError>>
+    where
+        D: Decision<StateQuery = S, Event = DomainEvent, Error = CartError>,
+        StateStore: LoadState<PgEventId, S, DomainEvent>,
+    {
+        self.state_store
+            .load(decision.state_query())
+            .await
+            .map_err(DecisionError::StateStore)
+    }
+}
+
+fn check_expected<S>(
+    loaded_state: &LoadedState<PgEventId, S>,
+    expected_event_id: Option<i64>,
+) -> Result<(), CartError> {
+    match expected_event_id {
+        Some(expected_event_id) if loaded_state.version() > expected_event_id => {
+            Err(CartError::CartChanged {
+                expected_event_id,
+                last_event_id: loaded_state.version(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The outcome of a decision that was made without appending its events.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DryRun<S> {
+    /// The id of the last event the state was built from.
+    pub last_event_id: i64,
+    /// The decision's state query as built from the events.
+    pub state: S,
+    /// The events the decision would append, without their personal data. Empty if it would be
+    /// rejected.
+    pub events: Vec<DomainEvent>,
+    /// Why the domain would reject the decision, if it would.
+    pub rejection: Option<String>,
+}
+
+/// True if the decision failed because events were appended concurrently.
+pub fn is_concurrency_conflict<DE>(decision_error: &DecisionError<DE>) -> bool {
+    let is_conflict = |e: &BoxDynError| matches!(e.downcast_ref(), Some(Error::Concurrency));
+    match decision_error {
+        DecisionError::EventStore(e) | DecisionError::StateStore(e) => is_conflict(e),
+        DecisionError::Domain(_) => false,
+    }
+}
+
+//------------------------- Web API ----------------------------
+
+/// The id of the last event the client has seen, taken from the `If-Match` header.
+/// The id may be quoted like an ETag, e.g. `If-Match: "42"`. `If-Match: *` matches any state, so
+/// no event id is expected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExpectedEventId(pub Option<i64>);
+
+impl<S: Send + Sync> FromRequestParts<S> for ExpectedEventId {
+    type Rejection = ClientError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(axum::http::header::IF_MATCH) else {
+            return Ok(ExpectedEventId(None));
+        };
+        let invalid = || ClientError::Payload("Invalid If-Match header.".to_owned());
+        let value = value.to_str().map_err(|_| invalid())?.trim();
+        if value == "*" {
+            return Ok(ExpectedEventId(None));
+        }
+        value
+            .trim_matches('"')
+            .parse()
+            .map(|event_id| ExpectedEventId(Some(event_id)))
+            .map_err(|_| invalid())
+    }
+}
+
+/// Set by the `dry_run=true` query parameter. The command is decided but not appended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DryRunParam(pub bool);
+
+#[derive(serde::Deserialize)]
+struct DryRunParams {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for DryRunParam {
+    type Rejection = ClientError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<DryRunParams>::try_from_uri(&parts.uri)
+            .map_err(|e| ClientError::Payload(format!("Invalid dry_run. {e}")))?;
+        Ok(DryRunParam(params.dry_run))
+    }
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+    use disintegrate::{EventStore as _, query};
+    use fake::{Fake, Faker};
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::domain::{
+        cart::{AddItemCommand, CartId, RemoveItemCommand, SubmitCartCommand},
+        create_in_memory_eventstore_and_decider,
+    };
+
+    #[tokio::test]
+    async fn decision_fails_when_cart_changed_after_expected_event() {
+        let (_event_store, decider) = create_in_memory_eventstore_and_decider();
+        let cart_id = CartId::new();
+        let add_item = AddItemCommand {
+            cart_id,
+            ..Faker.fake()
+        };
+        let first = decider.make(add_item.clone()).await.unwrap();
+        let seen_event_id = first.last().unwrap().id();
+        decider
+            .make(AddItemCommand {
+                cart_id,
+                ..Faker.fake()
+            })
+            .await
+            .unwrap();
+
+        let result = decider
+            .make_expecting(
+                RemoveItemCommand {
+                    cart_id,
+                    item_id: add_item.item_id,
+                },
+                ExpectedEventId(Some(seen_event_id)),
+            )
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(DecisionError::Domain(CartError::CartChanged {
+                expected_event_id: 2,
+                last_event_id: 3
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn decision_is_made_when_expected_event_is_current() {
+        let (_event_store, decider) = create_in_memory_eventstore_and_decider();
+        let cart_id = CartId::new();
+        let add_item = AddItemCommand {
+            cart_id,
+            ..Faker.fake()
+        };
+        let events = decider.make(add_item.clone()).await.unwrap();
+        let seen_event_id = events.last().unwrap().id();
+
+        let result = decider
+            .make_expecting(
+                RemoveItemCommand {
+                    cart_id,
+                    item_id: add_item.item_id,
+                },
+                ExpectedEventId(Some(seen_event_id)),
+            )
+            .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn dry_run_returns_state_and_events_without_appending() {
+        let (event_store, decider) = create_in_memory_eventstore_and_decider();
+        let cart_id = CartId::new();
+        let add_item = AddItemCommand {
+            cart_id,
+            ..Faker.fake()
+        };
+        decider.make(add_item.clone()).await.unwrap();
+
+        let dry_run = decider
+            .dry_run(&SubmitCartCommand { cart_id }, ExpectedEventId(None))
+            .await
+            .unwrap();
+
+        assert_eq!(dry_run.last_event_id, 2);
+        assert!(dry_run.rejection.is_none());
+        assert!(matches!(
+            dry_run.events.as_slice(),
+            [DomainEvent::CartSubmitted { total_price, .. }] if *total_price == add_item.price
+        ));
+        let stored: Vec<_> = event_store
+            .stream(&query!(DomainEvent; cart_id == cart_id))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(stored.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn dry_run_explains_rejection() {
+        let (_event_store, decider) = create_in_memory_eventstore_and_decider();
+        let cart_id = CartId::new();
+
+        let dry_run = decider
+            .dry_run(&SubmitCartCommand { cart_id }, ExpectedEventId(None))
+            .await
+            .unwrap();
+
+        assert!(dry_run.events.is_empty());
+        assert_eq!(
+            dry_run.rejection,
+            Some(CartError::CartDoesNotExist(cart_id).to_string())
+        );
+        assert_eq!(
+            serde_json::to_value(&dry_run).unwrap()["state"]["cart_exists"],
+            false
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run_is_read_from_query() {
+        let (mut parts, _) = Request::post("/submitcart/1?dry_run=true")
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(
+            DryRunParam::from_request_parts(&mut parts, &())
+                .await
+                .unwrap(),
+            DryRunParam(true)
+        );
+        let (mut parts, _) = Request::post("/submitcart/1")
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(
+            DryRunParam::from_request_parts(&mut parts, &())
+                .await
+                .unwrap(),
+            DryRunParam(false)
+        );
+    }
+
+    #[tokio::test]
+    async fn expected_event_id_is_read_from_if_match() {
+        async fn extract(request: Request<()>) -> Result<ExpectedEventId, ClientError> {
+            let (mut parts, _) = request.into_parts();
+            ExpectedEventId::from_request_parts(&mut parts, &()).await
+        }
+
+        let quoted = Request::post("/")
+            .header("If-Match", "\"42\"")
+            .body(())
+            .unwrap();
+        assert_eq!(extract(quoted).await.unwrap(), ExpectedEventId(Some(42)));
+        let bare = Request::post("/").header("If-Match", "7").body(()).unwrap();
+        assert_eq!(extract(bare).await.unwrap(), ExpectedEventId(Some(7)));
+        let absent = Request::post("/").body(()).unwrap();
+        assert_eq!(extract(absent).await.unwrap(), ExpectedEventId(None));
+        let any = Request::post("/").header("If-Match", "*").body(()).unwrap();
+        assert_eq!(extract(any).await.unwrap(), ExpectedEventId(None));
+        let invalid = Request::post("/")
+            .header("If-Match", "\"abc\"")
+            .body(())
+            .unwrap();
+        assert!(extract(invalid).await.is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! Encoding of DomainEvents for storage in the event store.
+//!
+//! Events are written with the codec selected in configuration, but events in either format can
+//! always be read so the codec can be changed without downtime. Existing events can then be
+//! re-encoded with [`reencode_events`].
+
+use anyhow::Context;
+use disintegrate_serde::Error;
+use sqlx::{PgPool, Row};
+
+use crate::domain::DomainEvent;
+
+/// Prefix of MessagePack encoded events. 0xC1 is never used by MessagePack and can never start a
+/// JSON document, so it distinguishes the two formats.
+const MESSAGE_PACK_MARKER: u8 = 0xC1;
+
+/// Number of events re-encoded per batch.
+const REENCODE_BATCH_SIZE: i64 = 500;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventCodec {
+    #[default]
+    Json,
+    /// MessagePack with named fields. Smaller and faster to decode than JSON while remaining
+    /// self-describing, so events stay readable as fields are added.
+    MessagePack,
+}
+
+impl EventCodec {
+    /// The codec an encoded event was written with.
+    pub fn of(data: &[u8]) -> Self {
+        match data.first() {
+            Some(&MESSAGE_PACK_MARKER) => EventCodec::MessagePack,
+            _ => EventCodec::Json,
+        }
+    }
+
+    pub fn encode(&self, event: &DomainEvent) -> Vec<u8> {
+        match self {
+            EventCodec::Json => {
+                serde_json::to_vec(event).expect("DomainEvent should serialise to JSON.")
+            }
+            EventCodec::MessagePack => {
+                let mut data = vec![MESSAGE_PACK_MARKER];
+                rmp_serde::encode::write_named(&mut data, event)
+                    .expect("DomainEvent should serialise to MessagePack.");
+                data
+            }
+        }
+    }
+
+    /// Decodes an event written with any codec.
+    pub fn decode(data: &[u8]) -> Result<DomainEvent, Error> {
+        match EventCodec::of(data) {
+            EventCodec::Json => {
+                serde_json::from_slice(data).map_err(|e| Error::Deserialization(Box::new(e)))
+            }
+            EventCodec::MessagePack => {
+                rmp_serde::from_slice(&data[1..]).map_err(|e| Error::Deserialization(Box::new(e)))
+            }
+        }
+    }
+}
+
+/// Re-encodes all stored events, including archived events, that were not written with `codec`.
+/// Personal data stays encrypted as it is re-encoded. Returns the number of events re-encoded.
+pub async fn reencode_events(pool: &PgPool, codec: EventCodec) -> Result<u64, anyhow::Error> {
+    let mut reencoded = 0;
+    for table in ["event", "event_archive"] {
+        let mut last_event_id = 0i64;
+        loop {
+            let rows = sqlx::query(&format!(
+                "SELECT event_id, payload FROM {table} WHERE event_id > $1 ORDER BY event_id LIMIT $2"
+            ))
+            .bind(last_event_id)
+            .bind(REENCODE_BATCH_SIZE)
+            .fetch_all(pool)
+            .await
+            .with_context(|| format!("Problem in reencode_events() reading {table}."))?;
+
+            let Some(last_row) = rows.last() else {
+                break;
+            };
+            last_event_id = last_row.get(0);
+
+            for row in rows {
+                let event_id: i64 = row.get(0);
+                let payload: Vec<u8> = row.get(1);
+                if EventCodec::of(&payload) == codec {
+                    continue;
+                }
+                let event = EventCodec::decode(&payload)
+                    .with_context(|| format!("Problem decoding event {event_id} in {table}."))?;
+                sqlx::query(&format!(
+                    "UPDATE {table} SET payload = $2 WHERE event_id = $1"
+                ))
+                .bind(event_id)
+                .bind(codec.encode(&event))
+                .execute(pool)
+                .await
+                .with_context(|| format!("Problem re-encoding event {event_id} in {table}."))?;
+                reencoded += 1;
+            }
+        }
+    }
+    Ok(reencoded)
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use fake::{Fake, Faker};
+
+    use super::*;
+    use crate::domain::{
+        cart::{CartId, ItemId, ProductId},
+        fake::FingerPrint,
+    };
+
+    fn cart_item_added() -> DomainEvent {
+        DomainEvent::CartItemAdded {
+            cart_id: CartId::new(),
+            description: Faker.fake(),
+            image: Faker.fake(),
+            price: Faker.fake(),
+            item_id: ItemId::new(),
+            product_id: ProductId::new(),
+            fingerprint: FingerPrint.fake(),
+        }
+    }
+
+    #[test]
+    fn events_round_trip_through_every_codec() {
+        let event = cart_item_added();
+        for codec in [EventCodec::Json, EventCodec::MessagePack] {
+            let data = codec.encode(&event);
+            assert_eq!(EventCodec::of(&data), codec);
+            assert_eq!(EventCodec::decode(&data).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn message_pack_is_smaller_than_json() {
+        let event = cart_item_added();
+        assert!(
+            EventCodec::MessagePack.encode(&event).len() < EventCodec::Json.encode(&event).len()
+        );
+    }
+
+    #[test]
+    fn unit_events_round_trip() {
+        let data = EventCodec::MessagePack.encode(&DomainEvent::EmptyEvent);
+        assert_eq!(EventCodec::decode(&data).unwrap(), DomainEvent::EmptyEvent);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn stored_events_are_reencoded(pool: PgPool) {
+        for table in ["event", "event_archive"] {
+            sqlx::query(&format!(
+                "CREATE TABLE {table} (event_id BIGINT PRIMARY KEY, payload BYTEA)"
+            ))
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let events: Vec<_> = (1..=3).map(|_| cart_item_added()).collect();
+        for (event_id, event) in (1i64..).zip(&events) {
+            sqlx::query("INSERT INTO event (event_id, payload) VALUES ($1, $2)")
+                .bind(event_id)
+                .bind(EventCodec::Json.encode(event))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let reencoded = reencode_events(&pool, EventCodec::MessagePack)
+            .await
+            .unwrap();
+        assert_eq!(reencoded, 3);
+        assert_eq!(
+            reencode_events(&pool, EventCodec::MessagePack)
+                .await
+                .unwrap(),
+            0
+        );
+
+        let payloads: Vec<Vec<u8>> =
+            sqlx::query_scalar("SELECT payload FROM event ORDER BY event_id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        let read_events: Vec<_> = payloads
+            .iter()
+            .map(|payload| EventCodec::decode(payload).unwrap())
+            .collect();
+        assert_eq!(read_events, events);
+    }
+}
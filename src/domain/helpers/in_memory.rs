@@ -0,0 +1,306 @@
+//! In-memory event store for tests and for running the server without a database.
+//!
+//! Events are serialised with the same Serde as the Postgres event store, so they behave the same
+//! when read back, e.g. personal data is redacted once a cart is forgotten. Appends are validated
+//! with the same optimistic concurrency check: an append fails with `Error::Concurrency` if an
+//! event matching the validation query was appended after the events the decision was made on.
+
+use std::{
+    error::Error as StdError,
+    marker::PhantomData,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use disintegrate::{Event, EventStore, PersistedEvent, StreamQuery, serde::Serde};
+use disintegrate_postgres::{Error, PgEventId};
+use futures::{StreamExt, stream::BoxStream};
+
+/// An event id and its serialised event.
+type StoredEvent = (PgEventId, Vec<u8>);
+
+pub struct InMemoryEventStore<E, S> {
+    events: Arc<RwLock<Vec<StoredEvent>>>,
+    serde: S,
+    event_type: PhantomData<E>,
+}
+
+impl<E, S: Clone> Clone for InMemoryEventStore<E, S> {
+    fn clone(&self) -> Self {
+        Self {
+            events: self.events.clone(),
+            serde: self.serde.clone(),
+            event_type: PhantomData,
+        }
+    }
+}
+
+impl<E, S> InMemoryEventStore<E, S>
+where
+    E: Event,
+    S: Serde<E> + Send + Sync,
+{
+    pub fn new(serde: S) -> Self {
+        Self {
+            events: Arc::new(RwLock::new(Vec::new())),
+            serde,
+            event_type: PhantomData,
+        }
+    }
+
+    fn stored_events(&self) -> Vec<StoredEvent> {
+        self.events
+            .read()
+            .expect("InMemoryEventStore lock should not be poisoned.")
+            .clone()
+    }
+
+    fn insert(
+        &self,
+        stored: &mut Vec<StoredEvent>,
+        events: Vec<E>,
+    ) -> Vec<PersistedEvent<PgEventId, E>>
+    where
+        E: Clone,
+    {
+        let mut last_event_id = stored.last().map(|(id, _)| *id).unwrap_or_default();
+        events
+            .into_iter()
+            .map(|event| {
+                last_event_id += 1;
+                stored.push((last_event_id, self.serde.serialize(event.clone())));
+                PersistedEvent::new(last_event_id, event)
+            })
+            .collect()
+    }
+}
+
+/// Matches an event against a query in the same way the Postgres event store's SQL does. As
+/// there, an identifier the event doesn't declare, e.g. a `cart_id` filter on InventoryChanged,
+/// doesn't exclude it.
+fn matches<QE, E>(query: &StreamQuery<PgEventId, QE>, event_id: PgEventId, event: &E) -> bool
+where
+    QE: Event + Clone,
+    E: Event,
+{
+    let name = event.name();
+    let Some(event_info) = QE::SCHEMA.event_info(name) else {
+        return false;
+    };
+    query.filters().iter().any(|filter| {
+        event_id > filter.origin()
+            && filter.events().contains(&name)
+            && filter
+                .excluded_events()
+                .is_none_or(|excluded| !excluded.contains(&name))
+            && filter
+                .identifiers()
+                .iter()
+                .filter(|(ident, _)| event_info.has_domain_identifier(ident))
+                .all(|(ident, value)| event.domain_identifiers().get(ident) == Some(value))
+    })
+}
+
+#[async_trait]
+impl<E, S> EventStore<PgEventId, E> for InMemoryEventStore<E, S>
+where
+    E: Event + Send + Sync,
+    S: Serde<E> + Send + Sync,
+{
+    type Error = Error;
+
+    fn stream<'a, QE>(
+        &'a self,
+        query: &'a StreamQuery<PgEventId, QE>,
+    ) -> BoxStream<'a, Result<PersistedEvent<PgEventId, QE>, Self::Error>>
+    where
+        QE: TryFrom<E> + Event + 'static + Clone + Send + Sync,
+        <QE as TryFrom<E>>::Error: StdError + 'static + Send + Sync,
+    {
+        let events = self.stored_events();
+        futures::stream::iter(events)
+            .filter_map(move |(event_id, payload)| async move {
+                let event = match self.serde.deserialize(payload) {
+                    Ok(event) => event,
+                    Err(e) => return Some(Err(Error::Deserialization(e))),
+                };
+                if !matches(query, event_id, &event) {
+                    return None;
+                }
+                Some(
+                    QE::try_from(event)
+                        .map(|event| PersistedEvent::new(event_id, event))
+                        .map_err(|e| Error::QueryEventMapping(Box::new(e))),
+                )
+            })
+            .boxed()
+    }
+
+    async fn append<QE>(
+        &self,
+        events: Vec<E>,
+        query: StreamQuery<PgEventId, QE>,
+        version: PgEventId,
+    ) -> Result<Vec<PersistedEvent<PgEventId, E>>, Self::Error>
+    where
+        E: Clone + 'async_trait,
+        QE: Event + 'static + Clone + Send + Sync,
+    {
+        let mut stored = self
+            .events
+            .write()
+            .expect("InMemoryEventStore lock should not be poisoned.");
+
+        for (event_id, payload) in stored.iter().filter(|(event_id, _)| *event_id > version) {
+            let event = self.serde.deserialize(payload.clone())?;
+            if matches(&query, *event_id, &event) {
+                return Err(Error::Concurrency);
+            }
+        }
+
+        Ok(self.insert(&mut stored, events))
+    }
+
+    async fn append_without_validation(
+        &self,
+        events: Vec<E>,
+    ) -> Result<Vec<PersistedEvent<PgEventId, E>>, Self::Error>
+    where
+        E: Clone + 'async_trait,
+    {
+        let mut stored = self
+            .events
+            .write()
+            .expect("InMemoryEventStore lock should not be poisoned.");
+        Ok(self.insert(&mut stored, events))
+    }
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use disintegrate::query;
+    use fake::{Fake, Faker};
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::domain::{
+        CartStream, DomainEvent,
+        cart::{AddItemCommand, CartError, CartId, ProductId, RemoveItemCommand},
+        create_in_memory_eventstore_and_decider,
+    };
+
+    #[tokio::test]
+    async fn events_are_streamed_by_query() {
+        let (event_store, _decider) = create_in_memory_eventstore_and_decider();
+        let cart_id = CartId::new();
+        event_store
+            .append_without_validation(vec![
+                DomainEvent::CartCreated { cart_id },
+                DomainEvent::CartCreated {
+                    cart_id: CartId::new(),
+                },
+                DomainEvent::CartCleared { cart_id },
+            ])
+            .await
+            .expect("Events should be appended.");
+
+        let query = query!(CartStream; cart_id == cart_id);
+        let events: Vec<_> = event_store.stream(&query).try_collect().await.unwrap();
+
+        let event_ids: Vec<_> = events.iter().map(|e| e.id()).collect();
+        assert_eq!(event_ids, vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn identifiers_an_event_does_not_declare_do_not_exclude_it() {
+        let (event_store, _decider) = create_in_memory_eventstore_and_decider();
+        let cart_id = CartId::new();
+        event_store
+            .append_without_validation(vec![
+                DomainEvent::CartCreated { cart_id },
+                DomainEvent::InventoryChanged {
+                    product_id: ProductId::new(),
+                    inventory: 5,
+                },
+                DomainEvent::CartCreated {
+                    cart_id: CartId::new(),
+                },
+            ])
+            .await
+            .expect("Events should be appended.");
+
+        // As with the Postgres event store, InventoryChanged has no cart_id to filter on.
+        let query: StreamQuery<PgEventId, DomainEvent> = query!(DomainEvent; cart_id == cart_id);
+        let events: Vec<_> = event_store.stream(&query).try_collect().await.unwrap();
+
+        let event_ids: Vec<_> = events.iter().map(|e| e.id()).collect();
+        assert_eq!(event_ids, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn append_fails_when_a_matching_event_was_appended_concurrently() {
+        let (event_store, _decider) = create_in_memory_eventstore_and_decider();
+        let cart_id = CartId::new();
+        let query: StreamQuery<PgEventId, DomainEvent> = query!(DomainEvent; cart_id == cart_id);
+        event_store
+            .append(vec![DomainEvent::CartCreated { cart_id }], query.clone(), 0)
+            .await
+            .expect("First append should succeed.");
+
+        let result = event_store
+            .append(vec![DomainEvent::CartCleared { cart_id }], query, 0)
+            .await;
+
+        assert!(matches!(result, Err(Error::Concurrency)));
+    }
+
+    #[tokio::test]
+    async fn append_succeeds_when_only_unrelated_events_were_appended() {
+        let (event_store, _decider) = create_in_memory_eventstore_and_decider();
+        let cart_id = CartId::new();
+        event_store
+            .append_without_validation(vec![DomainEvent::CartCreated {
+                cart_id: CartId::new(),
+            }])
+            .await
+            .unwrap();
+
+        let query: StreamQuery<PgEventId, DomainEvent> = query!(DomainEvent; cart_id == cart_id);
+        let result = event_store
+            .append(vec![DomainEvent::CartCreated { cart_id }], query, 0)
+            .await;
+
+        assert_eq!(result.unwrap()[0].id(), 2);
+    }
+
+    #[tokio::test]
+    async fn decisions_are_made_against_the_in_memory_store() {
+        let (_event_store, decider) = create_in_memory_eventstore_and_decider();
+        let cart_id = CartId::new();
+        let add_item = AddItemCommand {
+            cart_id,
+            ..Faker.fake()
+        };
+
+        let events = decider
+            .make(add_item.clone())
+            .await
+            .expect("Add item should succeed.");
+        assert_eq!(events.len(), 2);
+
+        let result = decider
+            .make(RemoveItemCommand {
+                cart_id,
+                item_id: Faker.fake(),
+            })
+            .await;
+        assert!(matches!(
+            result,
+            Err(disintegrate::DecisionError::Domain(
+                CartError::CannotRemoveItem
+            ))
+        ));
+    }
+}
@@ -51,20 +51,50 @@ where
     }
 }
 
+/// Publishes a message that has no event to record alongside. A retried task may publish it
+/// again, so consumers should deduplicate by key.
+pub async fn publish<T>(
+    settings: &KafkaSettings,
+    topic: &str,
+    message: &T,
+) -> Result<(), PublishError>
+where
+    T: serde::Serialize + Hash,
+{
+    let producer = producer_config(settings).create::<FutureProducer>()?;
+    let payload = serde_json::to_vec(message)?;
+    producer
+        .send(
+            FutureRecord::to(topic)
+                .payload(&payload)
+                .key(&calculate_hash(message).to_string()),
+            Timeout::Never,
+        )
+        .await
+        .map_err(|(e, _)| e)?;
+    Ok(())
+}
+
 /// Constructs a Kafka producer for sending messages/events.
 fn create_transactional_producer(
     settings: &KafkaSettings,
     transactional_id: String,
 ) -> Result<FutureProducer, KafkaError> {
-    ClientConfig::new()
+    producer_config(settings)
+        .set("transactional.id", transactional_id)
+        .create::<FutureProducer>()
+}
+
+fn producer_config(settings: &KafkaSettings) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config
         .set("bootstrap.servers", settings.bootstrap_servers.clone())
         .set(
             "message.timeout.ms",
             settings.session_timeout_ms.to_string(),
         )
-        .set("enable.idempotence", "true")
-        .set("transactional.id", transactional_id)
-        .create::<FutureProducer>()
+        .set("enable.idempotence", "true");
+    config
 }
 
 fn calculate_hash<T: Hash>(t: &T) -> u64 {
@@ -0,0 +1,582 @@
+//! Status of the event listeners.
+//!
+//! Disintegrate keeps the checkpoint of each listener in the `event_listener` table but not how far
+//! behind the event log the listener is or why it stopped moving. Every listener is registered
+//! through a `ListenerMonitor`, which remembers the event types its query selects and records the
+//! last error it failed with in the `event_listener_error` table. A listener's status compares its
+//! checkpoint with the events of those types that have been appended since.
+//!
+//! The monitor also applies the listener's `FailurePolicy`. A failed event, including one whose
+//! handler panicked, is retried with backoff. Once the retries are exhausted the event is either
+//! parked in `projection_failures`, so that the listener moves on, or failed, which halts the
+//! listener at the event. Parked events are replayed through the registered listener.
+
+use std::{
+    collections::BTreeMap,
+    error::Error as StdError,
+    marker::PhantomData,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, anyhow};
+use async_trait::async_trait;
+use axum::{Json, extract::State};
+use backon::{ExponentialBuilder, Retryable};
+use disintegrate::{Event, EventListener, EventStore as _, PersistedEvent, StreamQuery};
+use futures::{FutureExt, StreamExt};
+use sqlx::{PgPool, Row};
+use tracing::{error, warn};
+
+use crate::{
+    domain::{DomainEvent, EventStore},
+    infra::{ClientError, FailureAction, FailurePolicy, ListenerSettings},
+};
+
+use super::projection_failures::park_event;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ListenerStatus {
+    pub listener_id: &'static str,
+    pub last_processed_event_id: i64,
+    /// The newest event the listener's query selects.
+    pub head_event_id: i64,
+    /// Events the listener has yet to handle.
+    pub lag_events: i64,
+    /// Age of the oldest event the listener has yet to handle.
+    pub lag_seconds: f64,
+    pub last_error: Option<ListenerError>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ListenerError {
+    pub event_id: i64,
+    pub error: String,
+    pub failed_at: jiff::Timestamp,
+}
+
+/// A listener registered with the `ListenerMonitor`.
+#[derive(Clone)]
+struct Registration {
+    /// The event types the listener's query selects.
+    event_types: Vec<&'static str>,
+    replayer: Arc<dyn Replayer>,
+}
+
+#[derive(Clone)]
+pub struct ListenerMonitor {
+    pool: Option<PgPool>,
+    settings: ListenerSettings,
+    listeners: Arc<Mutex<BTreeMap<&'static str, Registration>>>,
+}
+
+impl ListenerMonitor {
+    pub fn new(pool: PgPool, settings: ListenerSettings) -> Self {
+        Self {
+            pool: Some(pool),
+            settings,
+            listeners: Arc::default(),
+        }
+    }
+
+    /// Alongside the in-memory event store no listeners run.
+    pub fn in_memory() -> Self {
+        Self {
+            pool: None,
+            settings: ListenerSettings::default(),
+            listeners: Arc::default(),
+        }
+    }
+
+    /// Registers the listener, returning it wrapped so that its failure policy is applied and its
+    /// errors are recorded.
+    pub fn watch<L, QE>(&self, listener: L) -> Monitored<L>
+    where
+        L: EventListener<i64, QE, Error = anyhow::Error> + 'static,
+        QE: TryFrom<DomainEvent> + Event + Clone + Send + Sync + 'static,
+        <QE as TryFrom<DomainEvent>>::Error: StdError + Send + Sync + 'static,
+    {
+        let listener = Arc::new(listener);
+        let event_types = listener
+            .query()
+            .filters()
+            .iter()
+            .flat_map(|filter| filter.events().iter().copied())
+            .collect();
+        let replayer = Arc::new(ListenerReplayer {
+            listener: listener.clone(),
+            event: PhantomData,
+        });
+        self.listeners
+            .lock()
+            .expect("ListenerMonitor lock should not be poisoned.")
+            .insert(
+                listener.id(),
+                Registration {
+                    event_types,
+                    replayer,
+                },
+            );
+        Monitored {
+            policy: self.settings.failure_policy_for(listener.id()),
+            listener,
+            monitor: self.clone(),
+        }
+    }
+
+    /// The event types each registered listener's query selects, keyed by listener id.
+    pub fn event_types(&self) -> BTreeMap<&'static str, Vec<&'static str>> {
+        self.listeners
+            .lock()
+            .expect("ListenerMonitor lock should not be poisoned.")
+            .iter()
+            .map(|(listener_id, registration)| (*listener_id, registration.event_types.clone()))
+            .collect()
+    }
+
+    /// Handles the event with the listener again, e.g. once the cause of its failure is fixed.
+    pub async fn replay(
+        &self,
+        event_store: &EventStore,
+        listener_id: &str,
+        event_id: i64,
+    ) -> Result<(), anyhow::Error> {
+        let replayer = self
+            .listeners
+            .lock()
+            .expect("ListenerMonitor lock should not be poisoned.")
+            .get(listener_id)
+            .map(|registration| registration.replayer.clone())
+            .with_context(|| format!("Listener {listener_id} is not running here."))?;
+        replayer.replay(event_store, event_id).await
+    }
+
+    async fn park(
+        &self,
+        listener_id: &str,
+        event_id: i64,
+        error: &str,
+        attempts: i32,
+    ) -> Result<(), anyhow::Error> {
+        match &self.pool {
+            Some(pool) => park_event(pool, listener_id, event_id, error, attempts).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn record_error(
+        &self,
+        listener_id: &str,
+        event_id: i64,
+        error: &str,
+    ) -> Result<(), anyhow::Error> {
+        let Some(pool) = &self.pool else {
+            return Ok(());
+        };
+        sqlx::query!(
+            r#"INSERT INTO event_listener_error (id, event_id, error, failed_at)
+               VALUES ($1, $2, $3, now())
+               ON CONFLICT (id)
+               DO UPDATE SET event_id = $2, error = $3, failed_at = now()"#,
+            listener_id,
+            event_id,
+            error,
+        )
+        .execute(pool)
+        .await
+        .with_context(|| {
+            format!("Problem in record_error(listener_id: {listener_id}, event_id: {event_id}).")
+        })?;
+        Ok(())
+    }
+
+    /// The status of every registered listener, ordered by id.
+    pub async fn status(&self) -> Result<Vec<ListenerStatus>, anyhow::Error> {
+        let Some(pool) = &self.pool else {
+            return Ok(Vec::new());
+        };
+        let listeners = self
+            .listeners
+            .lock()
+            .expect("ListenerMonitor lock should not be poisoned.")
+            .clone();
+
+        let mut statuses = Vec::with_capacity(listeners.len());
+        for (listener_id, registration) in listeners {
+            statuses.push(listener_status(pool, listener_id, &registration.event_types).await?);
+        }
+        Ok(statuses)
+    }
+}
+
+async fn listener_status(
+    pool: &PgPool,
+    listener_id: &'static str,
+    event_types: &[&str],
+) -> Result<ListenerStatus, anyhow::Error> {
+    let row = sqlx::query(
+        r#"SELECT COALESCE(l.last_processed_event_id, 0),
+                  COALESCE(head.event_id, 0),
+                  lag.events,
+                  COALESCE(lag.seconds, 0),
+                  e.event_id,
+                  e.error,
+                  e.failed_at
+           FROM (SELECT $1::TEXT AS id) listener
+           LEFT JOIN event_listener l ON l.id = listener.id
+           LEFT JOIN event_listener_error e ON e.id = listener.id
+           CROSS JOIN LATERAL (
+               SELECT MAX(event_id) AS event_id FROM event WHERE event_type = ANY($2)
+           ) head
+           CROSS JOIN LATERAL (
+               SELECT COUNT(*) AS events,
+                      EXTRACT(EPOCH FROM LOCALTIMESTAMP - MIN(inserted_at))::FLOAT8 AS seconds
+               FROM event
+               WHERE event_type = ANY($2)
+                 AND event_id > COALESCE(l.last_processed_event_id, 0)
+           ) lag"#,
+    )
+    .bind(listener_id)
+    .bind(event_types)
+    .fetch_one(pool)
+    .await
+    .with_context(|| format!("Problem in listener_status(listener_id: {listener_id})."))?;
+
+    let last_error = row.get::<Option<i64>, _>(4).map(|event_id| ListenerError {
+        event_id,
+        error: row.get(5),
+        failed_at: row.get::<jiff_sqlx::Timestamp, _>(6).to_jiff(),
+    });
+    Ok(ListenerStatus {
+        listener_id,
+        last_processed_event_id: row.get(0),
+        head_event_id: row.get(1),
+        lag_events: row.get(2),
+        lag_seconds: row.get(3),
+        last_error,
+    })
+}
+
+/// An event listener whose failure policy is applied by the `ListenerMonitor`.
+pub struct Monitored<L> {
+    listener: Arc<L>,
+    monitor: ListenerMonitor,
+    policy: FailurePolicy,
+}
+
+#[async_trait]
+impl<L, QE> EventListener<i64, QE> for Monitored<L>
+where
+    L: EventListener<i64, QE, Error = anyhow::Error>,
+    QE: Event + Clone + Send + Sync + 'static,
+{
+    type Error = anyhow::Error;
+
+    fn id(&self) -> &'static str {
+        self.listener.id()
+    }
+
+    fn query(&self) -> &StreamQuery<i64, QE> {
+        self.listener.query()
+    }
+
+    async fn handle(&self, event: PersistedEvent<i64, QE>) -> Result<(), Self::Error> {
+        let id = self.id();
+        let event_id = event.id();
+        let result = (|| handle_catching_panics(self.listener.as_ref(), event.clone()))
+            .retry(
+                ExponentialBuilder::default()
+                    .with_min_delay(Duration::from_millis(self.policy.backoff_ms))
+                    .with_max_times(self.policy.retries)
+                    .with_jitter(),
+            )
+            .sleep(tokio::time::sleep)
+            .notify(|e, dur| {
+                warn!("{id}: Failed to handle event {event_id}. Retrying after {dur:?}. {e:#}");
+            })
+            .await;
+        let Err(e) = result else {
+            return Ok(());
+        };
+
+        let message = format!("{e:#}");
+        if let Err(record_error) = self.monitor.record_error(id, event_id, &message).await {
+            error!("ListenerMonitor: {record_error:?}");
+        }
+        match self.policy.then {
+            FailureAction::Halt => Err(e),
+            FailureAction::Park => {
+                let attempts = i32::try_from(self.policy.retries + 1).unwrap_or(i32::MAX);
+                // An event that cannot be parked must not be skipped.
+                self.monitor
+                    .park(id, event_id, &message, attempts)
+                    .await
+                    .with_context(|| format!("{id}: Failed to park event {event_id}. {message}"))?;
+                error!("{id}: Parked event {event_id} after {attempts} attempts. {message}");
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn handle_catching_panics<L, QE>(
+    listener: &L,
+    event: PersistedEvent<i64, QE>,
+) -> Result<(), anyhow::Error>
+where
+    L: EventListener<i64, QE, Error = anyhow::Error>,
+    QE: Event + Clone,
+{
+    AssertUnwindSafe(listener.handle(event))
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|panic| {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(anyhow!("Panicked: {message}"))
+        })
+}
+
+#[async_trait]
+trait Replayer: Send + Sync {
+    async fn replay(&self, event_store: &EventStore, event_id: i64) -> Result<(), anyhow::Error>;
+}
+
+struct ListenerReplayer<L, QE> {
+    listener: Arc<L>,
+    event: PhantomData<fn() -> QE>,
+}
+
+#[async_trait]
+impl<L, QE> Replayer for ListenerReplayer<L, QE>
+where
+    L: EventListener<i64, QE, Error = anyhow::Error>,
+    QE: TryFrom<DomainEvent> + Event + Clone + Send + Sync + 'static,
+    <QE as TryFrom<DomainEvent>>::Error: StdError + Send + Sync + 'static,
+{
+    /// Parked events are not archived, so the event is read from the event store alone.
+    async fn replay(&self, event_store: &EventStore, event_id: i64) -> Result<(), anyhow::Error> {
+        let query = self.listener.query().clone().change_origin(event_id - 1);
+        let event = event_store
+            .stream(&query)
+            .next()
+            .await
+            .transpose()
+            .with_context(|| format!("Problem in replay(event_id: {event_id}) reading event."))?
+            .filter(|event| event.id() == event_id)
+            .with_context(|| format!("Event {event_id} is no longer in the event store."))?;
+        handle_catching_panics(self.listener.as_ref(), event).await
+    }
+}
+
+//------------------------- Web API ----------------------------
+
+/// e.g. `GET /admin/listeners`
+pub async fn listener_status_endpoint(
+    State(monitor): State<ListenerMonitor>,
+) -> Result<Json<Vec<ListenerStatus>>, ClientError> {
+    Ok(Json(monitor.status().await?))
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use anyhow::bail;
+    use disintegrate::query;
+
+    use super::*;
+    use crate::domain::{
+        InventoryStream, ProjectionFailureFilter,
+        cart::{CartId, ChangeInventoryCommand, ProductId},
+        create_in_memory_eventstore_and_decider, find_projection_failures,
+        helpers::test_events::{append_events, set_appended_at, set_checkpoint},
+        replay_projection_failure,
+    };
+
+    fn policy(then: FailureAction) -> ListenerSettings {
+        ListenerSettings {
+            failure_policy: FailurePolicy {
+                retries: 1,
+                backoff_ms: 1,
+                then,
+            },
+            ..Default::default()
+        }
+    }
+
+    struct FailingListener {
+        query: StreamQuery<i64, InventoryStream>,
+    }
+
+    #[async_trait]
+    impl EventListener<i64, InventoryStream> for FailingListener {
+        type Error = anyhow::Error;
+
+        fn id(&self) -> &'static str {
+            "failing"
+        }
+
+        fn query(&self) -> &StreamQuery<i64, InventoryStream> {
+            &self.query
+        }
+
+        async fn handle(
+            &self,
+            event: PersistedEvent<i64, InventoryStream>,
+        ) -> Result<(), Self::Error> {
+            bail!("Cannot handle event {}.", event.id())
+        }
+    }
+
+    /// Panics while `failing` is set.
+    struct FlakyListener {
+        query: StreamQuery<i64, InventoryStream>,
+        failing: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl EventListener<i64, InventoryStream> for FlakyListener {
+        type Error = anyhow::Error;
+
+        fn id(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn query(&self) -> &StreamQuery<i64, InventoryStream> {
+            &self.query
+        }
+
+        async fn handle(
+            &self,
+            _event: PersistedEvent<i64, InventoryStream>,
+        ) -> Result<(), Self::Error> {
+            if self.failing.load(Ordering::SeqCst) {
+                panic!("Flaky listener failed.");
+            }
+            Ok(())
+        }
+    }
+
+    /// Appends inventory events around a cart event. The failing listener has handled the first
+    /// inventory event, appended a minute before the others. Returns the ids of the events.
+    async fn setup(pool: &PgPool) -> Vec<i64> {
+        let inventory_changed = || DomainEvent::InventoryChanged {
+            product_id: ProductId::new(),
+            inventory: 1,
+        };
+        let event_ids: Vec<i64> = append_events(
+            pool,
+            [
+                inventory_changed(),
+                DomainEvent::CartCreated {
+                    cart_id: CartId::new(),
+                },
+                inventory_changed(),
+                inventory_changed(),
+            ],
+        )
+        .await
+        .iter()
+        .map(|event| event.id())
+        .collect();
+        set_appended_at(
+            pool,
+            event_ids[0],
+            jiff::Timestamp::now() - jiff::SignedDuration::from_mins(1),
+        )
+        .await;
+        set_checkpoint(pool, "failing", event_ids[0]).await;
+        event_ids
+    }
+
+    #[sqlx::test]
+    async fn status_reports_lag_and_last_error(pool: PgPool) {
+        let event_ids = setup(&pool).await;
+        let monitor = ListenerMonitor::new(pool, policy(FailureAction::Halt));
+        let listener = monitor.watch(FailingListener {
+            query: query!(InventoryStream),
+        });
+
+        let event = PersistedEvent::new(
+            event_ids[2],
+            InventoryStream::InventoryChanged {
+                product_id: ProductId::new(),
+                inventory: 1,
+            },
+        );
+        assert!(listener.handle(event).await.is_err());
+
+        let statuses = monitor.status().await.unwrap();
+        assert_eq!(statuses.len(), 1);
+        let status = &statuses[0];
+        assert_eq!(status.listener_id, "failing");
+        assert_eq!(status.last_processed_event_id, event_ids[0]);
+        assert_eq!(status.head_event_id, event_ids[3]);
+        assert_eq!(status.lag_events, 2);
+        assert!(status.lag_seconds < 60.0);
+        let last_error = status.last_error.as_ref().unwrap();
+        assert_eq!(last_error.event_id, event_ids[2]);
+        assert_eq!(
+            last_error.error,
+            format!("Cannot handle event {}.", event_ids[2])
+        );
+    }
+
+    #[sqlx::test]
+    async fn failed_events_are_parked_and_replayed(pool: PgPool) {
+        let (event_store, decider) = create_in_memory_eventstore_and_decider();
+        let monitor = ListenerMonitor::new(pool.clone(), policy(FailureAction::Park));
+        let failing = Arc::new(AtomicBool::new(true));
+        let listener = monitor.watch(FlakyListener {
+            query: query!(InventoryStream),
+            failing: failing.clone(),
+        });
+        let event = decider
+            .make(ChangeInventoryCommand {
+                product_id: ProductId::new(),
+                inventory: 1,
+            })
+            .await
+            .unwrap()
+            .remove(0);
+        let event_id = event.id();
+        let event = PersistedEvent::new(event_id, event.into_inner().try_into().unwrap());
+
+        listener
+            .handle(event)
+            .await
+            .expect("The event should be parked.");
+
+        let parked = find_projection_failures(&pool, &ProjectionFailureFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(parked.len(), 1);
+        assert_eq!(parked[0].listener_id, "flaky");
+        assert_eq!(parked[0].event_id, event_id);
+        assert_eq!(parked[0].attempts, 2);
+        assert_eq!(parked[0].error, "Panicked: Flaky listener failed.");
+
+        failing.store(false, Ordering::SeqCst);
+        let replayed = replay_projection_failure(
+            &pool,
+            &monitor,
+            &event_store,
+            parked[0].projection_failure_id,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(replayed.replayed_at.is_some());
+        let parked = find_projection_failures(&pool, &ProjectionFailureFilter::default())
+            .await
+            .unwrap();
+        assert!(parked.is_empty());
+    }
+}
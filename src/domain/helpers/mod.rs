@@ -1,11 +1,26 @@
+pub mod archive;
+pub mod command_bus;
+pub mod command_log;
+pub mod command_middleware;
+pub mod crypto_shredding;
+pub mod decision_maker;
 pub mod device_fingerprint_calculator;
+pub mod event_codec;
 
 pub mod fake;
+pub mod in_memory;
 
 mod kafka;
+pub mod listener_monitor;
 pub mod live_read_models;
 mod macros;
+pub mod projection_failures;
+pub mod projections;
+pub mod read_your_writes;
+pub mod snapshots;
 mod stateless;
+#[cfg(test)]
+pub mod test_events;
 
-pub use kafka::{PublishError, publish_with_events};
+pub use kafka::{PublishError, publish, publish_with_events};
 pub use stateless::Stateless;
@@ -0,0 +1,203 @@
+//! Events parked by the event listeners.
+//!
+//! A listener whose `FailurePolicy` parks events records each event it could not handle in the
+//! `projection_failures` table and moves on. Once the cause has been fixed a parked event is
+//! replayed through the listener. Replays happen after the listener has moved on. A
+//! `ProjectionTransaction` applies a parked event despite its checkpoint having moved on, and marks
+//! it replayed in the same transaction. For a read model keyed by e.g. cart, later events for the
+//! parked event's cart are parked behind it, and must be replayed after it, oldest first. A
+//! replay that would be applied over a later event for its cart fails instead.
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use sqlx::PgPool;
+
+use crate::{
+    domain::{EventStore, ListenerMonitor},
+    infra::ClientError,
+};
+
+/// Most parked events returned by one query.
+const MAX_FAILURES: i64 = 1000;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ProjectionFailure {
+    pub projection_failure_id: i64,
+    pub listener_id: String,
+    pub event_id: i64,
+    pub error: String,
+    pub attempts: i32,
+    pub parked_at: jiff::Timestamp,
+    pub replayed_at: Option<jiff::Timestamp>,
+}
+
+/// Filters for `find_projection_failures`. Parked events are returned oldest first.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct ProjectionFailureFilter {
+    pub listener_id: Option<String>,
+    /// Also return events that have since been replayed.
+    #[serde(default)]
+    pub include_replayed: bool,
+    pub limit: Option<i64>,
+}
+
+/// Parks the event. An event parked again, e.g. after a failed replay, is parked anew.
+pub async fn park_event(
+    pool: &PgPool,
+    listener_id: &str,
+    event_id: i64,
+    error: &str,
+    attempts: i32,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO projection_failures (listener_id, event_id, error, attempts, parked_at)
+           VALUES ($1, $2, $3, $4, now())
+           ON CONFLICT (listener_id, event_id)
+           DO UPDATE SET
+              error = $3,
+              attempts = projection_failures.attempts + $4,
+              parked_at = now(),
+              replayed_at = NULL"#,
+        listener_id,
+        event_id,
+        error,
+        attempts,
+    )
+    .execute(pool)
+    .await
+    .with_context(|| {
+        format!("Problem in park_event(listener_id: {listener_id}, event_id: {event_id}).")
+    })?;
+    Ok(())
+}
+
+pub async fn find_projection_failures(
+    pool: &PgPool,
+    filter: &ProjectionFailureFilter,
+) -> Result<Vec<ProjectionFailure>, anyhow::Error> {
+    let limit = filter.limit.unwrap_or(100).clamp(1, MAX_FAILURES);
+    let rows = sqlx::query!(
+        r#"SELECT projection_failure_id, listener_id, event_id, error, attempts,
+                  parked_at as "parked_at: jiff_sqlx::Timestamp",
+                  replayed_at as "replayed_at: jiff_sqlx::Timestamp"
+           FROM projection_failures
+           WHERE ($1::TEXT IS NULL OR listener_id = $1)
+             AND ($2 OR replayed_at IS NULL)
+           ORDER BY projection_failure_id
+           LIMIT $3"#,
+        filter.listener_id,
+        filter.include_replayed,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("Problem in find_projection_failures(filter: {filter:?})."))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| ProjectionFailure {
+            projection_failure_id: row.projection_failure_id,
+            listener_id: row.listener_id,
+            event_id: row.event_id,
+            error: row.error,
+            attempts: row.attempts,
+            parked_at: row.parked_at.to_jiff(),
+            replayed_at: row.replayed_at.map(|replayed_at| replayed_at.to_jiff()),
+        })
+        .collect())
+}
+
+async fn find_projection_failure(
+    pool: &PgPool,
+    projection_failure_id: i64,
+) -> Result<Option<ProjectionFailure>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT projection_failure_id, listener_id, event_id, error, attempts,
+                  parked_at as "parked_at: jiff_sqlx::Timestamp",
+                  replayed_at as "replayed_at: jiff_sqlx::Timestamp"
+           FROM projection_failures
+           WHERE projection_failure_id = $1"#,
+        projection_failure_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .with_context(|| format!("Problem in find_projection_failure({projection_failure_id})."))?;
+
+    Ok(row.map(|row| ProjectionFailure {
+        projection_failure_id: row.projection_failure_id,
+        listener_id: row.listener_id,
+        event_id: row.event_id,
+        error: row.error,
+        attempts: row.attempts,
+        parked_at: row.parked_at.to_jiff(),
+        replayed_at: row.replayed_at.map(|replayed_at| replayed_at.to_jiff()),
+    }))
+}
+
+/// Replays the parked event through its listener. Returns the parked event, marked as replayed if
+/// the listener handled it and with the new error if it failed again.
+pub async fn replay_projection_failure(
+    pool: &PgPool,
+    monitor: &ListenerMonitor,
+    event_store: &EventStore,
+    projection_failure_id: i64,
+) -> Result<Option<ProjectionFailure>, anyhow::Error> {
+    let Some(failure) = find_projection_failure(pool, projection_failure_id).await? else {
+        return Ok(None);
+    };
+
+    match monitor
+        .replay(event_store, &failure.listener_id, failure.event_id)
+        .await
+    {
+        Ok(()) => {
+            sqlx::query!(
+                r#"UPDATE projection_failures SET replayed_at = now()
+                   WHERE projection_failure_id = $1"#,
+                projection_failure_id,
+            )
+            .execute(pool)
+            .await
+            .with_context(|| {
+                format!("Problem in replay_projection_failure({projection_failure_id}).")
+            })?;
+        }
+        Err(e) => {
+            park_event(
+                pool,
+                &failure.listener_id,
+                failure.event_id,
+                &format!("{e:#}"),
+                1,
+            )
+            .await?;
+        }
+    }
+    find_projection_failure(pool, projection_failure_id).await
+}
+
+//------------------------- Web API ----------------------------
+
+/// e.g. `GET /admin/failures?listener_id=inventories`
+pub async fn projection_failures_endpoint(
+    State(pool): State<PgPool>,
+    Query(filter): Query<ProjectionFailureFilter>,
+) -> Result<Json<Vec<ProjectionFailure>>, ClientError> {
+    Ok(Json(find_projection_failures(&pool, &filter).await?))
+}
+
+/// e.g. `POST /admin/failures/7/replay`
+pub async fn replay_projection_failure_endpoint(
+    State(pool): State<PgPool>,
+    State(monitor): State<ListenerMonitor>,
+    State(event_store): State<EventStore>,
+    Path(projection_failure_id): Path<i64>,
+) -> Result<Json<ProjectionFailure>, ClientError> {
+    replay_projection_failure(&pool, &monitor, &event_store, projection_failure_id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ClientError::Payload(format!("No parked event {projection_failure_id}.")))
+}
@@ -0,0 +1,945 @@
+//! Projected read models: applying events to them and rebuilding them.
+//!
+//! A projection applies each event in a `ProjectionTransaction`, which advances the projection's
+//! own checkpoint together with its writes, so an event delivered twice is skipped. A read model
+//! is reset in place, or rebuilt into shadow tables that replace the live ones once caught up.
+
+use anyhow::{Context, bail};
+use async_trait::async_trait;
+use disintegrate::{Event, EventListener, EventStore as _, PersistedEvent};
+use futures::{StreamExt, stream::BoxStream};
+use sqlx::{PgConnection, PgPool, Postgres, Row, Transaction, postgres::PgPoolOptions};
+use std::{
+    ops::{Deref, DerefMut},
+    pin::Pin,
+};
+
+use crate::domain::{DomainEvent, EventArchive, EventStore};
+
+/// Schema the shadow tables of a blue/green rebuild are built in.
+const SHADOW_SCHEMA: &str = "projection_shadow";
+
+/// The transaction in which a projection applies one event. Committing it advances the
+/// projection's checkpoint past the event.
+pub struct ProjectionTransaction {
+    tx: Transaction<'static, Postgres>,
+    projection_id: &'static str,
+    event_id: i64,
+    /// The key the event changes, for a projection applying events in order.
+    key: Option<String>,
+}
+
+impl ProjectionTransaction {
+    /// Begins applying the event, or returns `None` if the projection has already applied it. An
+    /// event before the checkpoint is only applied if it was parked and hasn't been replayed yet.
+    pub async fn begin(
+        pool: &PgPool,
+        projection_id: &'static str,
+        event_id: i64,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        let mut tx = pool.begin().await.with_context(|| {
+            format!("Problem in ProjectionTransaction::begin(projection_id: {projection_id}, event_id: {event_id}) starting transaction.")
+        })?;
+        sqlx::query!(
+            r#"INSERT INTO projection_checkpoint (projection_id, last_event_id)
+               VALUES ($1, 0)
+               ON CONFLICT (projection_id) DO NOTHING"#,
+            projection_id
+        )
+        .execute(&mut *tx)
+        .await
+        .with_context(|| {
+            format!("Problem in ProjectionTransaction::begin(projection_id: {projection_id}, event_id: {event_id}) creating checkpoint.")
+        })?;
+        let applied = sqlx::query_scalar!(
+            r#"SELECT checkpoint.last_event_id >= $2 AND NOT EXISTS (
+                  SELECT 1 FROM projection_failures failure
+                  WHERE failure.listener_id = checkpoint.projection_id
+                    AND failure.event_id = $2
+                    AND failure.replayed_at IS NULL
+               ) as "applied!"
+               FROM projection_checkpoint checkpoint
+               WHERE checkpoint.projection_id = $1
+               FOR UPDATE OF checkpoint"#,
+            projection_id,
+            event_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .with_context(|| {
+            format!("Problem in ProjectionTransaction::begin(projection_id: {projection_id}, event_id: {event_id}) locking checkpoint.")
+        })?;
+        if applied {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            tx,
+            projection_id,
+            event_id,
+            key: None,
+        }))
+    }
+
+    /// Begins applying an event to a read model keyed by the domain identifier `key`, e.g.
+    /// `cart_id`, whose events must be applied in order. Returns `None` if the projection has
+    /// already applied the event, or if an earlier event for the same key is parked, in which case
+    /// this event is parked behind it. Fails if the event is replayed before an earlier parked
+    /// event for its key, or after a later event for its key has been applied.
+    pub async fn begin_in_order<E: Event>(
+        pool: &PgPool,
+        projection_id: &'static str,
+        event: &PersistedEvent<i64, E>,
+        key: &'static str,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        let event_id = event.id();
+        let Some(mut tx) = Self::begin(pool, projection_id, event_id).await? else {
+            return Ok(None);
+        };
+        let Some(value) = event
+            .domain_identifiers()
+            .iter()
+            .find(|(identifier, _)| ***identifier == key)
+            .map(|(_, value)| value.to_string())
+        else {
+            return Ok(Some(tx));
+        };
+
+        if let Some(parked_event_id) = tx.parked_before(key, &value).await? {
+            if tx.is_parked().await? {
+                bail!(
+                    "Event {event_id} for {key} {value} follows parked event {parked_event_id}. Replay that event first."
+                );
+            }
+            tx.hold(parked_event_id, key, &value).await?;
+            return Ok(None);
+        }
+        if let Some(applied_event_id) = tx.key_version(&value).await?
+            && applied_event_id > event_id
+        {
+            bail!(
+                "Event {event_id} for {key} {value} is older than event {applied_event_id}, which projection {projection_id} has already applied. Rebuild the projection to apply it."
+            );
+        }
+        tx.key = Some(value);
+        Ok(Some(tx))
+    }
+
+    /// When the event was appended, so that a rebuilt read model keeps the original times.
+    pub async fn appended_at(&mut self) -> Result<jiff_sqlx::Timestamp, anyhow::Error> {
+        let event_id = self.event_id;
+        let row = sqlx::query(
+            r#"SELECT COALESCE(
+                   (SELECT inserted_at::TIMESTAMPTZ FROM event WHERE event_id = $1),
+                   (SELECT inserted_at::TIMESTAMPTZ FROM event_archive WHERE event_id = $1),
+                   now())"#,
+        )
+        .bind(event_id)
+        .fetch_one(&mut *self.tx)
+        .await
+        .with_context(|| format!("Problem in ProjectionTransaction::appended_at({event_id})."))?;
+        Ok(row.get(0))
+    }
+
+    /// Advances the checkpoint, and the key's version, marks the event replayed if it was parked,
+    /// and commits.
+    pub async fn commit(mut self) -> Result<(), anyhow::Error> {
+        let (projection_id, event_id) = (self.projection_id, self.event_id);
+        self.advance_checkpoint().await?;
+        if let Some(key) = &self.key {
+            sqlx::query!(
+                r#"INSERT INTO projection_key_version (projection_id, key, last_event_id)
+                   VALUES ($1, $2, $3)
+                   ON CONFLICT (projection_id, key)
+                   DO UPDATE SET last_event_id = GREATEST(projection_key_version.last_event_id, $3)"#,
+                projection_id,
+                key,
+                event_id
+            )
+            .execute(&mut *self.tx)
+            .await
+            .with_context(|| {
+                format!("Problem in ProjectionTransaction::commit(projection_id: {projection_id}, event_id: {event_id}) saving version of {key}.")
+            })?;
+        }
+        sqlx::query!(
+            r#"UPDATE projection_failures SET replayed_at = now()
+               WHERE listener_id = $1 AND event_id = $2 AND replayed_at IS NULL"#,
+            projection_id,
+            event_id
+        )
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| {
+            format!("Problem in ProjectionTransaction::commit(projection_id: {projection_id}, event_id: {event_id}) marking replayed.")
+        })?;
+        self.tx.commit().await.with_context(|| {
+            format!("Problem in ProjectionTransaction::commit(projection_id: {projection_id}, event_id: {event_id}).")
+        })
+    }
+
+    async fn advance_checkpoint(&mut self) -> Result<(), anyhow::Error> {
+        let (projection_id, event_id) = (self.projection_id, self.event_id);
+        sqlx::query!(
+            r#"UPDATE projection_checkpoint
+               SET last_event_id = GREATEST(last_event_id, $2)
+               WHERE projection_id = $1"#,
+            projection_id,
+            event_id
+        )
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| {
+            format!("Problem in ProjectionTransaction::advance_checkpoint(projection_id: {projection_id}, event_id: {event_id}).")
+        })?;
+        Ok(())
+    }
+
+    /// The oldest parked event before this one whose `key` has the same value. Carts with parked
+    /// events are not archived, so parked events are always found in `event`.
+    async fn parked_before(
+        &mut self,
+        key: &str,
+        value: &str,
+    ) -> Result<Option<i64>, anyhow::Error> {
+        let (projection_id, event_id) = (self.projection_id, self.event_id);
+        sqlx::query_scalar(&format!(
+            r#"SELECT failure.event_id
+               FROM projection_failures failure
+               JOIN event parked ON parked.event_id = failure.event_id
+               WHERE failure.listener_id = $1
+                 AND failure.event_id < $2
+                 AND failure.replayed_at IS NULL
+                 AND parked.{key}::TEXT = $3
+               ORDER BY failure.event_id
+               LIMIT 1"#
+        ))
+        .bind(projection_id)
+        .bind(event_id)
+        .bind(value)
+        .fetch_optional(&mut *self.tx)
+        .await
+        .with_context(|| {
+            format!("Problem in ProjectionTransaction::parked_before(projection_id: {projection_id}, event_id: {event_id}, {key}: {value}).")
+        })
+    }
+
+    async fn is_parked(&mut self) -> Result<bool, anyhow::Error> {
+        let (projection_id, event_id) = (self.projection_id, self.event_id);
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS (
+                  SELECT 1 FROM projection_failures
+                  WHERE listener_id = $1 AND event_id = $2 AND replayed_at IS NULL
+               ) as "parked!""#,
+            projection_id,
+            event_id
+        )
+        .fetch_one(&mut *self.tx)
+        .await
+        .with_context(|| {
+            format!("Problem in ProjectionTransaction::is_parked(projection_id: {projection_id}, event_id: {event_id}).")
+        })
+    }
+
+    /// Parks the event behind the parked event for its key, and moves the checkpoint past it.
+    async fn hold(
+        mut self,
+        parked_event_id: i64,
+        key: &str,
+        value: &str,
+    ) -> Result<(), anyhow::Error> {
+        let (projection_id, event_id) = (self.projection_id, self.event_id);
+        sqlx::query!(
+            r#"INSERT INTO projection_failures (listener_id, event_id, error, attempts, parked_at)
+               VALUES ($1, $2, $3, 0, now())
+               ON CONFLICT (listener_id, event_id)
+               DO UPDATE SET error = $3, parked_at = now(), replayed_at = NULL"#,
+            projection_id,
+            event_id,
+            format!("Held behind parked event {parked_event_id} for {key} {value}."),
+        )
+        .execute(&mut *self.tx)
+        .await
+        .with_context(|| {
+            format!("Problem in ProjectionTransaction::hold(projection_id: {projection_id}, event_id: {event_id}) parking event.")
+        })?;
+        self.advance_checkpoint().await?;
+        self.tx.commit().await.with_context(|| {
+            format!("Problem in ProjectionTransaction::hold(projection_id: {projection_id}, event_id: {event_id}).")
+        })
+    }
+
+    /// The last event the projection applied for the key.
+    async fn key_version(&mut self, key: &str) -> Result<Option<i64>, anyhow::Error> {
+        let projection_id = self.projection_id;
+        sqlx::query_scalar!(
+            r#"SELECT last_event_id FROM projection_key_version
+               WHERE projection_id = $1 AND key = $2"#,
+            projection_id,
+            key
+        )
+        .fetch_optional(&mut *self.tx)
+        .await
+        .with_context(|| {
+            format!("Problem in ProjectionTransaction::key_version(projection_id: {projection_id}, key: {key}).")
+        })
+    }
+}
+
+impl Deref for ProjectionTransaction {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
+}
+
+impl DerefMut for ProjectionTransaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx
+    }
+}
+
+/// A projection whose read model can be deleted and rebuilt from the events.
+#[async_trait]
+pub trait RebuildableProjection<QE>: EventListener<i64, QE, Error = anyhow::Error>
+where
+    QE: Event + Clone,
+{
+    /// Every table the projection writes. Its SQL must leave them unqualified so that a shadow
+    /// copy writes to the shadow tables.
+    const TABLES: &'static [&'static str];
+
+    /// A copy of the projection writing through `pool` under a listener id of its own.
+    fn shadow(&self, pool: PgPool) -> Self
+    where
+        Self: Sized;
+
+    /// Deletes every row the projection has written.
+    async fn reset(&self, conn: &mut PgConnection) -> Result<(), anyhow::Error>;
+}
+
+/// Deletes the projection's read model and rewinds its checkpoints. A running listener replays the
+/// events on its next poll, parked events included. Fails if any of the events have been
+/// archived, as the listener wouldn't replay them; rebuild the projection instead.
+pub async fn reset_projection<P, QE>(pool: &PgPool, projection: &P) -> Result<(), anyhow::Error>
+where
+    P: RebuildableProjection<QE>,
+    QE: Event + Clone,
+{
+    let id = projection.id();
+    let event_types: Vec<String> = projection
+        .query()
+        .filters()
+        .iter()
+        .flat_map(|filter| filter.events())
+        .map(|name| name.to_string())
+        .collect();
+    let archived = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM event_archive WHERE event_type = ANY($1)) as "archived!""#,
+        &event_types
+    )
+    .fetch_one(pool)
+    .await
+    .with_context(|| format!("Problem in reset_projection(id: {id}) checking archive."))?;
+    if archived {
+        bail!("Events of projection {id} have been archived. Rebuild the projection instead.");
+    }
+    let mut tx = lock_checkpoint(pool, id).await?;
+    projection
+        .reset(&mut tx)
+        .await
+        .with_context(|| format!("Problem in reset_projection(id: {id}) deleting read model."))?;
+    save_checkpoint(&mut tx, id, 0).await?;
+    restart_projection_checkpoint(&mut tx, id, 0).await?;
+    tx.commit()
+        .await
+        .with_context(|| format!("Problem in reset_projection(id: {id}) committing."))
+}
+
+/// Replays the events after the projection's checkpoint, archived events included. Returns the
+/// number of events handled.
+pub async fn replay_projection<P, QE>(
+    pool: &PgPool,
+    event_store: &EventStore,
+    event_archive: &EventArchive,
+    projection: &P,
+) -> Result<u64, anyhow::Error>
+where
+    P: RebuildableProjection<QE>,
+    QE: TryFrom<DomainEvent> + Event + Clone + Send + Sync + 'static,
+    <QE as TryFrom<DomainEvent>>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let id = projection.id();
+    let mut tx = lock_checkpoint(pool, id).await?;
+    let checkpoint = read_checkpoint(&mut tx, id).await?;
+
+    let query = projection.query().clone().change_origin(checkpoint);
+    let live = event_store
+        .stream(&query)
+        .map(|event| event.map_err(anyhow::Error::new))
+        .boxed();
+    let archived = event_archive
+        .archived_events_after(checkpoint)
+        .filter_map(|event| {
+            let event = event.map(|event| {
+                let event_id = event.id();
+                QE::try_from(event.into_inner())
+                    .ok()
+                    .map(|event| PersistedEvent::new(event_id, event))
+                    .filter(|event| query.matches(event))
+            });
+            async move { event.transpose() }
+        })
+        .boxed();
+    let mut events = in_event_id_order(archived, live);
+    let mut last_processed_event_id = checkpoint;
+    let mut replayed = 0;
+    let mut failure = None;
+    while let Some(event) = events.next().await {
+        let result = match event {
+            Ok(event) => {
+                let event_id = event.id();
+                projection.handle(event).await.map(|()| event_id)
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(event_id) => {
+                last_processed_event_id = event_id;
+                replayed += 1;
+            }
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+    drop(events);
+
+    // Progress is kept even if the replay failed part way, as Disintegrate does.
+    save_checkpoint(&mut tx, id, last_processed_event_id).await?;
+    tx.commit()
+        .await
+        .with_context(|| format!("Problem in replay_projection(id: {id}) committing."))?;
+
+    match failure {
+        Some(e) => Err(e.context(format!(
+            "Problem in replay_projection(id: {id}) after event {last_processed_event_id}."
+        ))),
+        None => Ok(replayed),
+    }
+}
+
+/// Merges two streams of events, each in event id order, into one in event id order. An error
+/// is passed on as soon as it is reached.
+pub(crate) fn in_event_id_order<'a, E: Event + Send + Sync + 'a>(
+    first: BoxStream<'a, Result<PersistedEvent<i64, E>, anyhow::Error>>,
+    second: BoxStream<'a, Result<PersistedEvent<i64, E>, anyhow::Error>>,
+) -> BoxStream<'a, Result<PersistedEvent<i64, E>, anyhow::Error>> {
+    futures::stream::unfold(
+        (first.peekable(), second.peekable()),
+        |(mut first, mut second)| async move {
+            let take_first = match (
+                Pin::new(&mut first).peek().await,
+                Pin::new(&mut second).peek().await,
+            ) {
+                (Some(Ok(a)), Some(Ok(b))) => a.id() <= b.id(),
+                (_, Some(Err(_))) => false,
+                (Some(_), _) => true,
+                (None, _) => false,
+            };
+            let next = if take_first {
+                first.next().await
+            } else {
+                second.next().await
+            };
+            next.map(|event| (event, (first, second)))
+        },
+    )
+    .boxed()
+}
+
+/// Rebuilds the projection into shadow tables and swaps them in once they have caught up, archived
+/// events included. Endpoints keep serving the old read model until the swap is committed.
+/// Returns the number of events replayed.
+pub async fn rebuild_projection<P, QE>(
+    pool: &PgPool,
+    event_store: &EventStore,
+    event_archive: &EventArchive,
+    projection: &P,
+) -> Result<u64, anyhow::Error>
+where
+    P: RebuildableProjection<QE>,
+    QE: TryFrom<DomainEvent> + Event + Clone + Send + Sync + 'static,
+    <QE as TryFrom<DomainEvent>>::Error: std::error::Error + Send + Sync + 'static,
+{
+    if event_store.is_in_memory() {
+        bail!("Projections are only kept alongside the Postgres event store.");
+    }
+    let id = projection.id();
+    let mut conn = pool.acquire().await.with_context(|| {
+        format!("Problem in rebuild_projection(id: {id}) acquiring connection.")
+    })?;
+    create_listener_table(&mut conn).await?;
+    drop(conn);
+
+    // Held until the rebuild is done so that two rebuilds never share the shadow tables.
+    let mut guard = pool
+        .begin()
+        .await
+        .with_context(|| format!("Problem in rebuild_projection(id: {id}) starting guard."))?;
+    let rebuilding: bool = sqlx::query_scalar("SELECT pg_try_advisory_xact_lock(hashtext($1))")
+        .bind(format!("rebuild_projection:{id}"))
+        .fetch_one(&mut *guard)
+        .await
+        .with_context(|| format!("Problem in rebuild_projection(id: {id}) taking guard."))?;
+    if !rebuilding {
+        bail!("Projection {id} is already being rebuilt.");
+    }
+
+    create_shadow_tables(pool, P::TABLES).await?;
+    let shadow_pool = shadow_pool(pool).await?;
+    let shadow = projection.shadow(shadow_pool.clone());
+    let shadow_id = shadow.id();
+    sqlx::query("DELETE FROM event_listener WHERE id = $1")
+        .bind(shadow_id)
+        .execute(pool)
+        .await
+        .with_context(|| {
+            format!("Problem in rebuild_projection(id: {id}) clearing checkpoint {shadow_id}.")
+        })?;
+    let mut conn = pool.acquire().await.with_context(|| {
+        format!("Problem in rebuild_projection(id: {id}) acquiring connection.")
+    })?;
+    restart_projection_checkpoint(&mut conn, shadow_id, 0).await?;
+    drop(conn);
+
+    let mut replayed = replay_projection(pool, event_store, event_archive, &shadow).await?;
+
+    // The live listener is held off while the shadow replays the events appended meanwhile.
+    let mut tx = lock_checkpoint(pool, id).await?;
+    replayed += replay_projection(pool, event_store, event_archive, &shadow).await?;
+    let checkpoint = read_checkpoint(&mut tx, shadow_id).await?;
+    swap_shadow_tables(&mut tx, P::TABLES).await?;
+    save_checkpoint(&mut tx, id, checkpoint).await?;
+    restart_projection_checkpoint(&mut tx, id, checkpoint).await?;
+    sqlx::query!(
+        "UPDATE projection_key_version SET projection_id = $1 WHERE projection_id = $2",
+        id,
+        shadow_id
+    )
+    .execute(&mut *tx)
+    .await
+    .with_context(|| format!("Problem in rebuild_projection(id: {id}) moving key versions."))?;
+    restart_projection_checkpoint(&mut tx, shadow_id, 0).await?;
+    sqlx::query("DELETE FROM event_listener WHERE id = $1")
+        .bind(shadow_id)
+        .execute(&mut *tx)
+        .await
+        .with_context(|| {
+            format!("Problem in rebuild_projection(id: {id}) deleting checkpoint {shadow_id}.")
+        })?;
+    tx.commit()
+        .await
+        .with_context(|| format!("Problem in rebuild_projection(id: {id}) committing swap."))?;
+
+    shadow_pool.close().await;
+    guard
+        .rollback()
+        .await
+        .with_context(|| format!("Problem in rebuild_projection(id: {id}) releasing guard."))?;
+    Ok(replayed)
+}
+
+/// Creates empty copies of the tables in the shadow schema, replacing any left by a failed rebuild.
+async fn create_shadow_tables(pool: &PgPool, tables: &[&str]) -> Result<(), anyhow::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Problem in create_shadow_tables() starting transaction.")?;
+    sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {SHADOW_SCHEMA}"))
+        .execute(&mut *tx)
+        .await
+        .context("Problem in create_shadow_tables() creating schema.")?;
+    for table in tables {
+        sqlx::query(&format!("DROP TABLE IF EXISTS {SHADOW_SCHEMA}.{table}"))
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("Problem in create_shadow_tables() dropping {table}."))?;
+        sqlx::query(&format!(
+            "CREATE TABLE {SHADOW_SCHEMA}.{table} (LIKE public.{table} INCLUDING ALL)"
+        ))
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("Problem in create_shadow_tables() creating {table}."))?;
+    }
+    tx.commit()
+        .await
+        .context("Problem in create_shadow_tables() committing.")
+}
+
+/// Replaces the live tables by the shadow tables. Readers of the live tables wait for the commit.
+async fn swap_shadow_tables(conn: &mut PgConnection, tables: &[&str]) -> Result<(), anyhow::Error> {
+    for table in tables {
+        sqlx::query(&format!("DROP TABLE public.{table}"))
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("Problem in swap_shadow_tables() dropping {table}."))?;
+        sqlx::query(&format!(
+            "ALTER TABLE {SHADOW_SCHEMA}.{table} SET SCHEMA public"
+        ))
+        .execute(&mut *conn)
+        .await
+        .with_context(|| format!("Problem in swap_shadow_tables() moving {table}."))?;
+    }
+    Ok(())
+}
+
+/// A pool on the same database resolving unqualified tables to the shadow schema first.
+async fn shadow_pool(pool: &PgPool) -> Result<PgPool, anyhow::Error> {
+    let options = pool
+        .connect_options()
+        .as_ref()
+        .clone()
+        .options([("search_path", format!("{SHADOW_SCHEMA},public"))]);
+    PgPoolOptions::new()
+        .max_connections(2)
+        .connect_with(options)
+        .await
+        .context("Problem in shadow_pool() connecting.")
+}
+
+/// Begins a transaction holding the lock on the projection's checkpoint, creating the checkpoint
+/// if the listener has never run.
+async fn lock_checkpoint<'a>(
+    pool: &'a PgPool,
+    id: &str,
+) -> Result<Transaction<'a, Postgres>, anyhow::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .with_context(|| format!("Problem in lock_checkpoint(id: {id}) starting transaction."))?;
+    create_listener_table(&mut tx).await?;
+    sqlx::query(
+        "INSERT INTO event_listener (id, last_processed_event_id) VALUES ($1, 0) ON CONFLICT (id) DO NOTHING",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .with_context(|| format!("Problem in lock_checkpoint(id: {id}) creating checkpoint."))?;
+    sqlx::query("SELECT 1 FROM event_listener WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("Problem in lock_checkpoint(id: {id})."))?;
+    Ok(tx)
+}
+
+/// Creates Disintegrate's checkpoint table as Disintegrate does, for a projection rebuilt or reset
+/// before any listener has run, e.g. from the command line against a new database.
+async fn create_listener_table(conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+    sqlx::query(
+        r#"CREATE TABLE IF NOT EXISTS event_listener (
+               id TEXT PRIMARY KEY,
+               last_processed_event_id BIGINT,
+               updated_at TIMESTAMP DEFAULT now())"#,
+    )
+    .execute(conn)
+    .await
+    .context("Problem in create_listener_table().")?;
+    Ok(())
+}
+
+async fn read_checkpoint(conn: &mut PgConnection, id: &str) -> Result<i64, anyhow::Error> {
+    sqlx::query_scalar("SELECT last_processed_event_id FROM event_listener WHERE id = $1")
+        .bind(id)
+        .fetch_one(conn)
+        .await
+        .with_context(|| format!("Problem in read_checkpoint(id: {id})."))
+}
+
+async fn save_checkpoint(
+    conn: &mut PgConnection,
+    id: &str,
+    last_processed_event_id: i64,
+) -> Result<(), anyhow::Error> {
+    sqlx::query(
+        "UPDATE event_listener SET last_processed_event_id = $1, updated_at = now() WHERE id = $2",
+    )
+    .bind(last_processed_event_id)
+    .bind(id)
+    .execute(conn)
+    .await
+    .with_context(|| {
+        format!("Problem in save_checkpoint(id: {id}, last_processed_event_id: {last_processed_event_id}).")
+    })?;
+    Ok(())
+}
+
+/// Sets the projection's own checkpoint for a read model rebuilt up to `last_event_id`. Its parked
+/// events are dropped, as the rebuilt read model has applied them or will, as are its key versions.
+async fn restart_projection_checkpoint(
+    conn: &mut PgConnection,
+    id: &str,
+    last_event_id: i64,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO projection_checkpoint (projection_id, last_event_id)
+           VALUES ($1, $2)
+           ON CONFLICT (projection_id) DO UPDATE SET last_event_id = $2"#,
+        id,
+        last_event_id
+    )
+    .execute(&mut *conn)
+    .await
+    .with_context(|| {
+        format!(
+            "Problem in restart_projection_checkpoint(id: {id}, last_event_id: {last_event_id})."
+        )
+    })?;
+    sqlx::query!(
+        "DELETE FROM projection_failures WHERE listener_id = $1 AND replayed_at IS NULL",
+        id
+    )
+    .execute(&mut *conn)
+    .await
+    .with_context(|| {
+        format!("Problem in restart_projection_checkpoint(id: {id}) dropping parked events.")
+    })?;
+    sqlx::query!(
+        "DELETE FROM projection_key_version WHERE projection_id = $1",
+        id
+    )
+    .execute(&mut *conn)
+    .await
+    .with_context(|| {
+        format!("Problem in restart_projection_checkpoint(id: {id}) dropping key versions.")
+    })?;
+    Ok(())
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use disintegrate::PersistedEvent;
+
+    use super::*;
+    use crate::domain::{
+        CartStream, EventCodec, EventSerde, InventoryStream, KeyStore, ListenerMonitor,
+        ProjectionFailureFilter,
+        cart::{
+            CartId, CartsWithProductsReadModelProjection, InventoriesReadModelProjection, ItemId,
+            ProductId,
+        },
+        create_eventstore, find_projection_failures,
+        helpers::test_events::append_events,
+        park_event,
+    };
+
+    async fn inventory(pool: &PgPool, product_id: ProductId) -> Option<i32> {
+        sqlx::query_scalar("SELECT inventory FROM inventories WHERE product_id = $1")
+            .bind(uuid::Uuid::from(product_id))
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn shadow_tables_are_only_seen_once_swapped_in(pool: PgPool) {
+        type Projection = InventoriesReadModelProjection;
+        let product_id = ProductId::new();
+        create_shadow_tables(&pool, Projection::TABLES)
+            .await
+            .unwrap();
+        let shadow_pool = shadow_pool(&pool).await.unwrap();
+        let shadow = Projection::new(pool.clone()).shadow(shadow_pool.clone());
+        assert_ne!(shadow.id(), "inventories");
+
+        let event = append_events(
+            &pool,
+            [DomainEvent::InventoryChanged {
+                product_id,
+                inventory: 3,
+            }],
+        )
+        .await
+        .remove(0);
+        shadow
+            .handle(PersistedEvent::new(
+                event.id(),
+                InventoryStream::try_from(event.into_inner()).unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(inventory(&pool, product_id).await, None);
+        assert_eq!(inventory(&shadow_pool, product_id).await, Some(3));
+
+        let mut tx = pool.begin().await.unwrap();
+        swap_shadow_tables(&mut tx, Projection::TABLES)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        assert_eq!(inventory(&pool, product_id).await, Some(3));
+        shadow_pool.close().await;
+    }
+
+    fn item_added(cart_id: CartId, item_id: ItemId) -> DomainEvent {
+        DomainEvent::CartItemAdded {
+            cart_id,
+            description: "Bread".to_string(),
+            image: "bread.jpg".into(),
+            price: 2.into(),
+            item_id,
+            product_id: ProductId::new(),
+            fingerprint: "fingerprint".to_string(),
+        }
+    }
+
+    fn cart_event(event: &PersistedEvent<i64, DomainEvent>) -> PersistedEvent<i64, CartStream> {
+        PersistedEvent::new(event.id(), event.clone().into_inner().try_into().unwrap())
+    }
+
+    async fn items(pool: &PgPool) -> i64 {
+        sqlx::query_scalar("SELECT count(*) FROM carts_with_products")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn events_are_applied_once_and_never_over_later_events(pool: PgPool) {
+        let projection = CartsWithProductsReadModelProjection::new(pool.clone());
+        let (cart_id, item_id) = (CartId::new(), ItemId::new());
+        let events = append_events(
+            &pool,
+            [
+                item_added(cart_id, item_id),
+                DomainEvent::CartItemRemoved { cart_id, item_id },
+            ],
+        )
+        .await;
+        let (added, removed) = (cart_event(&events[0]), cart_event(&events[1]));
+
+        projection.handle(added.clone()).await.unwrap();
+        projection.handle(removed).await.unwrap();
+        projection.handle(added.clone()).await.unwrap();
+        assert_eq!(
+            items(&pool).await,
+            0,
+            "A redelivered event should be skipped."
+        );
+
+        park_event(&pool, "carts_with_products", added.id(), "Failed.", 1)
+            .await
+            .unwrap();
+        assert!(
+            projection.handle(added).await.is_err(),
+            "A replay older than the cart's last change should fail."
+        );
+        assert_eq!(
+            items(&pool).await,
+            0,
+            "The removed item should stay removed."
+        );
+    }
+
+    #[sqlx::test]
+    async fn events_after_a_parked_event_wait_for_it(pool: PgPool) {
+        let projection = CartsWithProductsReadModelProjection::new(pool.clone());
+        let (cart_id, item_id) = (CartId::new(), ItemId::new());
+        let other_cart_id = CartId::new();
+        let events = append_events(
+            &pool,
+            [
+                item_added(cart_id, item_id),
+                DomainEvent::CartItemRemoved { cart_id, item_id },
+                item_added(other_cart_id, ItemId::new()),
+            ],
+        )
+        .await;
+        let (added, removed, other_added) = (
+            cart_event(&events[0]),
+            cart_event(&events[1]),
+            cart_event(&events[2]),
+        );
+
+        park_event(&pool, "carts_with_products", added.id(), "Failed.", 1)
+            .await
+            .unwrap();
+        projection.handle(removed.clone()).await.unwrap();
+        projection.handle(other_added).await.unwrap();
+        let parked: Vec<i64> = find_projection_failures(&pool, &ProjectionFailureFilter::default())
+            .await
+            .unwrap()
+            .iter()
+            .map(|failure| failure.event_id)
+            .collect();
+        assert_eq!(parked, vec![added.id(), removed.id()]);
+        assert_eq!(
+            items(&pool).await,
+            1,
+            "Only the other cart should be changed."
+        );
+
+        assert!(
+            projection.handle(removed.clone()).await.is_err(),
+            "The cart's events should be replayed in order."
+        );
+        projection.handle(added).await.unwrap();
+        assert_eq!(items(&pool).await, 2);
+        projection.handle(removed).await.unwrap();
+        assert_eq!(items(&pool).await, 1);
+    }
+
+    #[sqlx::test]
+    async fn rebuilds_replay_archived_events_in_order(pool: PgPool) {
+        let projection = CartsWithProductsReadModelProjection::new(pool.clone());
+        let serde = EventSerde::new(KeyStore::load(&pool).await.unwrap(), EventCodec::Json);
+        let event_store = create_eventstore(&pool, serde.clone()).await.unwrap();
+        let archive = EventArchive::new(pool.clone(), serde);
+        let (archived_cart_id, live_cart_id) = (CartId::new(), CartId::new());
+        let item_id = ItemId::new();
+        for event in [
+            item_added(archived_cart_id, ItemId::new()),
+            item_added(live_cart_id, ItemId::new()),
+            item_added(archived_cart_id, item_id),
+            DomainEvent::CartItemRemoved {
+                cart_id: archived_cart_id,
+                item_id,
+            },
+            DomainEvent::CartPublished {
+                cart_id: archived_cart_id,
+            },
+        ] {
+            event_store
+                .append_without_validation(vec![event])
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE event SET inserted_at = now() - INTERVAL '31 days' WHERE cart_id = $1")
+            .bind(archived_cart_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            archive
+                .archive_published_carts(30, &ListenerMonitor::in_memory())
+                .await
+                .unwrap(),
+            4
+        );
+
+        assert!(
+            reset_projection(&pool, &projection).await.is_err(),
+            "A listener wouldn't replay the archived events."
+        );
+        let replayed = rebuild_projection(&pool, &event_store, &archive, &projection)
+            .await
+            .unwrap();
+
+        assert_eq!(replayed, 4);
+        assert_eq!(items(&pool).await, 2);
+    }
+}
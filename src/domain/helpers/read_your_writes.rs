@@ -0,0 +1,296 @@
+//! Read-your-writes for projected read models.
+//!
+//! Command endpoints return the id of the last event they appended. A client passes that id to a
+//! query endpoint as the `min_event_id` query parameter, or the `Min-Event-Id` header, and the
+//! query waits until the projection has caught up with it rather than serving stale data.
+//!
+//! Disintegrate only moves a listener's checkpoint on to events the listener handles, so a
+//! projection has also caught up when none of the events after its checkpoint, up to the minimum
+//! event id, are of a type it handles.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use disintegrate::{Event, StreamQuery};
+use sqlx::{PgPool, Row};
+
+use crate::infra::ClientError;
+
+/// How long a query waits for a projection to catch up before giving up.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the projection's position is checked while waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+const MIN_EVENT_ID_HEADER: &str = "min-event-id";
+
+/// The id of an event the caller expects a read model to reflect, if any.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MinEventId(pub Option<i64>);
+
+#[derive(serde::Deserialize)]
+struct MinEventIdParams {
+    min_event_id: Option<i64>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for MinEventId {
+    type Rejection = ClientError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<MinEventIdParams>::try_from_uri(&parts.uri)
+            .map_err(|e| ClientError::Payload(format!("Invalid min_event_id. {e}")))?;
+        if params.min_event_id.is_some() {
+            return Ok(MinEventId(params.min_event_id));
+        }
+
+        match parts.headers.get(MIN_EVENT_ID_HEADER) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .map(|min_event_id| MinEventId(Some(min_event_id)))
+                .ok_or_else(|| {
+                    ClientError::Payload(format!("Invalid {MIN_EVENT_ID_HEADER} header."))
+                }),
+            None => Ok(MinEventId(None)),
+        }
+    }
+}
+
+/// Waits until the projection has caught up with `min_event_id`. Returns
+/// `ClientError::Unavailable` with the projection's current position if it does not do so in time.
+pub async fn wait_for_projection<QE>(
+    pool: &PgPool,
+    projection_id: &str,
+    query: &StreamQuery<i64, QE>,
+    min_event_id: MinEventId,
+) -> Result<(), ClientError>
+where
+    QE: Event + Clone,
+{
+    wait_for_projection_with_timeout(pool, projection_id, query, min_event_id, WAIT_TIMEOUT).await
+}
+
+async fn wait_for_projection_with_timeout<QE>(
+    pool: &PgPool,
+    projection_id: &str,
+    query: &StreamQuery<i64, QE>,
+    MinEventId(min_event_id): MinEventId,
+    timeout: Duration,
+) -> Result<(), ClientError>
+where
+    QE: Event + Clone,
+{
+    let Some(min_event_id) = min_event_id else {
+        return Ok(());
+    };
+    let event_types = event_types(query);
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let (position, caught_up) =
+            projection_position(pool, projection_id, &event_types, min_event_id).await?;
+        if caught_up {
+            return Ok(());
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(ClientError::Unavailable(format!(
+                "Projection {projection_id} has not caught up with event {min_event_id}. It has processed events up to {position}. Please retry."
+            )));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Returns whether the projection has caught up with `min_event_id`, without waiting for it to.
+pub async fn has_caught_up<QE>(
+    pool: &PgPool,
+    projection_id: &str,
+    query: &StreamQuery<i64, QE>,
+    min_event_id: i64,
+) -> Result<bool, anyhow::Error>
+where
+    QE: Event + Clone,
+{
+    let (_, caught_up) =
+        projection_position(pool, projection_id, &event_types(query), min_event_id).await?;
+    Ok(caught_up)
+}
+
+fn event_types<QE>(query: &StreamQuery<i64, QE>) -> Vec<&'static str>
+where
+    QE: Event + Clone,
+{
+    query
+        .filters()
+        .iter()
+        .flat_map(|filter| filter.events().iter().copied())
+        .collect()
+}
+
+/// Returns the projection's last processed event id and whether it has caught up with
+/// `min_event_id`.
+async fn projection_position(
+    pool: &PgPool,
+    projection_id: &str,
+    event_types: &[&str],
+    min_event_id: i64,
+) -> Result<(i64, bool), anyhow::Error> {
+    let row = sqlx::query(
+        r#"SELECT COALESCE(l.last_processed_event_id, 0),
+                  NOT EXISTS (
+                      SELECT 1
+                      FROM event e
+                      WHERE e.event_id > COALESCE(l.last_processed_event_id, 0)
+                        AND e.event_id <= $2
+                        AND e.event_type = ANY($3)
+                  )
+           FROM event_listener l
+           WHERE l.id = $1"#,
+    )
+    .bind(projection_id)
+    .bind(min_event_id)
+    .bind(event_types)
+    .fetch_optional(pool)
+    .await
+    .with_context(|| format!("Problem in projection_position(projection_id: {projection_id})."))?;
+
+    // A projection that has not started yet has processed nothing.
+    Ok(row.map_or((0, false), |row| (row.get(0), row.get(1))))
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+    use disintegrate::query;
+
+    use super::*;
+    use crate::domain::{
+        CartStream, DomainEvent, InventoryStream,
+        cart::{CartId, ItemId, ProductId},
+        helpers::test_events::{append_events, set_checkpoint},
+    };
+
+    /// Appends a cart, an inventory and a cart event. The cart_items projection has handled the
+    /// first and the inventories projection the second. Returns the ids of the events.
+    async fn setup(pool: &PgPool) -> [i64; 3] {
+        let cart_id = CartId::new();
+        let events = append_events(
+            pool,
+            [
+                DomainEvent::CartCreated { cart_id },
+                DomainEvent::InventoryChanged {
+                    product_id: ProductId::new(),
+                    inventory: 1,
+                },
+                DomainEvent::CartItemAdded {
+                    cart_id,
+                    description: "Bread".to_string(),
+                    image: "bread.jpg".into(),
+                    price: 2.into(),
+                    item_id: ItemId::new(),
+                    product_id: ProductId::new(),
+                    fingerprint: "fingerprint".to_string(),
+                },
+            ],
+        )
+        .await;
+        set_checkpoint(pool, "cart_items", events[0].id()).await;
+        set_checkpoint(pool, "inventories", events[1].id()).await;
+        [events[0].id(), events[1].id(), events[2].id()]
+    }
+
+    async fn min_event_id(request: Request<()>) -> Result<MinEventId, ClientError> {
+        let (mut parts, _) = request.into_parts();
+        MinEventId::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn min_event_id_is_read_from_query_or_header() {
+        let from_query = Request::get("/inventories/1?min_event_id=7")
+            .body(())
+            .unwrap();
+        assert_eq!(min_event_id(from_query).await.unwrap(), MinEventId(Some(7)));
+
+        let from_header = Request::get("/inventories/1")
+            .header("Min-Event-Id", "8")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            min_event_id(from_header).await.unwrap(),
+            MinEventId(Some(8))
+        );
+
+        let absent = Request::get("/inventories/1").body(()).unwrap();
+        assert_eq!(min_event_id(absent).await.unwrap(), MinEventId(None));
+
+        let invalid = Request::get("/inventories/1")
+            .header("Min-Event-Id", "x")
+            .body(())
+            .unwrap();
+        assert!(min_event_id(invalid).await.is_err());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn projection_that_handled_no_later_events_has_caught_up(pool: PgPool) {
+        let [_, _, cart_item_added] = setup(&pool).await;
+
+        // The last event is not an inventory event, so the inventories projection has nothing to
+        // do.
+        let result = wait_for_projection_with_timeout(
+            &pool,
+            "inventories",
+            &query!(InventoryStream),
+            MinEventId(Some(cart_item_added)),
+            Duration::ZERO,
+        )
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn lagging_projection_is_unavailable_after_timeout(pool: PgPool) {
+        let [cart_created, _, cart_item_added] = setup(&pool).await;
+
+        let result = wait_for_projection_with_timeout(
+            &pool,
+            "cart_items",
+            &query!(CartStream),
+            MinEventId(Some(cart_item_added)),
+            Duration::from_millis(100),
+        )
+        .await;
+
+        assert!(
+            matches!(result, Err(ClientError::Unavailable(message)) if message.contains(&format!("up to {cart_created}")))
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn waiting_ends_once_projection_catches_up(pool: PgPool) {
+        let [_, _, cart_item_added] = setup(&pool).await;
+        let catch_up_pool = pool.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            set_checkpoint(&catch_up_pool, "cart_items", cart_item_added).await;
+        });
+
+        let result = wait_for_projection_with_timeout(
+            &pool,
+            "cart_items",
+            &query!(CartStream),
+            MinEventId(Some(cart_item_added)),
+            Duration::from_secs(5),
+        )
+        .await;
+
+        assert!(result.is_ok());
+    }
+}
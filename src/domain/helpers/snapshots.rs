@@ -0,0 +1,331 @@
+//! Snapshotting of command state.
+//!
+//! Replaces Disintegrate's `PgSnapshotter` to allow the snapshot frequency to be configured per
+//! state query and to cope with snapshots that were stored before a state query changed shape.
+//! `PgSnapshotter` falls back to the default state on a snapshot it cannot deserialise but keeps
+//! the snapshot's version, so events prior to the snapshot would be skipped. Here each snapshot is
+//! stored with the shape of its state query, and one of another shape, or one that cannot be
+//! deserialised, is deleted and the state is rebuilt by replaying all of its events.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use disintegrate::{
+    BoxDynError, Event, IntoState, StatePart, StateQuery, StateSnapshotter, StreamQuery,
+};
+use disintegrate_postgres::PgEventId;
+use serde::{Serialize, de::DeserializeOwned};
+use sqlx::{PgPool, Row};
+use tracing::warn;
+
+use crate::infra::SnapshotSettings;
+
+/// The shape of each state query's fields, keyed by state query name. Bump a state query's shape
+/// when its fields change so snapshots stored before the change are replaced. State queries that
+/// are not listed are at shape 1.
+const SHAPES: &[(&str, i32)] = &[
+    ("ArchiveItemState", 2),
+    ("ClearCartState", 2),
+    ("RemoveItemState", 2),
+    ("RepublishCartState", 2),
+    ("SubmitCartState", 2),
+];
+
+/// A Snapshotter without a pool never stores snapshots, e.g. alongside the in-memory event store.
+#[derive(Clone)]
+pub struct Snapshotter {
+    pool: Option<PgPool>,
+    every: u64,
+    queries: HashMap<String, u64>,
+    shapes: &'static [(&'static str, i32)],
+}
+
+impl Snapshotter {
+    pub fn new(pool: PgPool, settings: &SnapshotSettings) -> Self {
+        Self {
+            pool: Some(pool),
+            every: settings.every,
+            queries: settings.queries.clone(),
+            shapes: SHAPES,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            pool: None,
+            every: SnapshotSettings::default().every,
+            queries: HashMap::new(),
+            shapes: SHAPES,
+        }
+    }
+
+    /// Number of events between snapshots for the named state query.
+    pub fn every(&self, state_query_name: &str) -> u64 {
+        self.queries
+            .get(state_query_name)
+            .copied()
+            .unwrap_or(self.every)
+    }
+
+    /// Shape of the named state query's fields.
+    fn shape(&self, state_query_name: &str) -> i32 {
+        self.shapes
+            .iter()
+            .find(|(name, _)| *name == state_query_name)
+            .map_or(1, |(_, shape)| *shape)
+    }
+}
+
+#[async_trait]
+impl StateSnapshotter<PgEventId> for Snapshotter {
+    async fn load_snapshot<S>(&self, default: StatePart<PgEventId, S>) -> StatePart<PgEventId, S>
+    where
+        S: Send + Sync + DeserializeOwned + StateQuery + 'static,
+    {
+        let Some(pool) = &self.pool else {
+            return default;
+        };
+        let query = query_key(&default.query());
+        let stored_snapshot = sqlx::query(
+            "SELECT shape, version, payload FROM state_snapshot WHERE name = $1 AND query = $2",
+        )
+        .bind(S::NAME)
+        .bind(&query)
+        .fetch_optional(pool)
+        .await;
+
+        let row = match stored_snapshot {
+            Ok(Some(row)) => row,
+            Ok(None) => return default,
+            Err(e) => {
+                warn!(
+                    "Snapshotter: Failed to load snapshot for {} due to {e}",
+                    S::NAME
+                );
+                return default;
+            }
+        };
+
+        let shape: i32 = row.get(0);
+        let stale = if shape != self.shape(S::NAME) {
+            format!(
+                "Snapshot for {} has shape {shape} rather than {} and will be replaced.",
+                S::NAME,
+                self.shape(S::NAME)
+            )
+        } else {
+            match serde_json::from_str::<S>(row.get(2)) {
+                Ok(payload) => return StatePart::new(row.get(1), payload),
+                Err(e) => format!(
+                    "Snapshot for {} could not be deserialised and will be replaced. {e}",
+                    S::NAME
+                ),
+            }
+        };
+
+        warn!("Snapshotter: {stale}");
+        if let Err(e) = sqlx::query("DELETE FROM state_snapshot WHERE name = $1 AND query = $2")
+            .bind(S::NAME)
+            .bind(&query)
+            .execute(pool)
+            .await
+        {
+            warn!(
+                "Snapshotter: Failed to delete snapshot for {} due to {e}",
+                S::NAME
+            );
+        }
+        default
+    }
+
+    async fn store_snapshot<S>(&self, state: &StatePart<PgEventId, S>) -> Result<(), BoxDynError>
+    where
+        S: Send + Sync + Serialize + StateQuery + 'static,
+    {
+        let Some(pool) = &self.pool else {
+            return Ok(());
+        };
+        if state.applied_events() <= self.every(S::NAME) {
+            return Ok(());
+        }
+        let payload = serde_json::to_string(&state.clone().into_state())?;
+        sqlx::query(
+            r#"INSERT INTO state_snapshot (name, query, shape, version, payload)
+               VALUES ($1, $2, $3, $4, $5)
+               ON CONFLICT(name, query)
+               DO UPDATE SET
+                  shape = EXCLUDED.shape,
+                  version = EXCLUDED.version,
+                  payload = EXCLUDED.payload,
+                  inserted_at = now()
+               WHERE state_snapshot.version < EXCLUDED.version
+                  OR state_snapshot.shape <> EXCLUDED.shape"#,
+        )
+        .bind(S::NAME)
+        .bind(query_key(&state.query()))
+        .bind(self.shape(S::NAME))
+        .bind(state.version())
+        .bind(payload)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Deletes stored snapshots, either all of them or only those of the named state query.
+/// Returns the number of snapshots deleted.
+pub async fn purge_snapshots(
+    pool: &PgPool,
+    state_query_name: Option<&str>,
+) -> Result<u64, sqlx::Error> {
+    let result = match state_query_name {
+        Some(name) => {
+            sqlx::query("DELETE FROM state_snapshot WHERE name = $1")
+                .bind(name)
+                .execute(pool)
+                .await?
+        }
+        None => {
+            sqlx::query("DELETE FROM state_snapshot")
+                .execute(pool)
+                .await?
+        }
+    };
+    Ok(result.rows_affected())
+}
+
+/// Key identifying the stream query of a snapshot, i.e. the events and identifiers, such as the
+/// cart, its state was built from.
+fn query_key<E: Event + Clone>(query: &StreamQuery<PgEventId, E>) -> String {
+    query
+        .filters()
+        .iter()
+        .map(|f| {
+            let identifiers = f
+                .identifiers()
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>();
+            let excluded_events = f.excluded_events().map(|e| e.join(",")).unwrap_or_default();
+            format!(
+                "({}|{}|-{}|{})",
+                f.origin(),
+                f.events().join(","),
+                excluded_events,
+                identifiers.join(",")
+            )
+        })
+        .collect()
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use disintegrate::{Decision, PersistedEvent};
+
+    use super::*;
+    use crate::domain::{
+        CartStream,
+        cart::{CartId, SubmitCartCommand},
+    };
+
+    async fn snapshotter(pool: &PgPool, every: u64) -> Snapshotter {
+        let settings = SnapshotSettings {
+            every,
+            queries: HashMap::from([("SubmitCartState".to_owned(), 0)]),
+        };
+        Snapshotter::new(pool.clone(), &settings)
+    }
+
+    type SubmitCartState = <SubmitCartCommand as Decision>::StateQuery;
+
+    fn created_state(cart_id: CartId) -> StatePart<PgEventId, SubmitCartState> {
+        let mut state = StatePart::new(0, SubmitCartCommand { cart_id }.state_query());
+        state.mutate_part(PersistedEvent::new(7, CartStream::CartCreated { cart_id }));
+        state
+    }
+
+    #[sqlx::test]
+    async fn snapshot_frequency_can_be_set_per_state_query(pool: PgPool) {
+        let snapshotter = snapshotter(&pool, 100).await;
+        assert_eq!(snapshotter.every("SubmitCartState"), 0);
+        assert_eq!(snapshotter.every("AddItemState"), 100);
+    }
+
+    #[sqlx::test]
+    async fn snapshot_is_stored_and_loaded(pool: PgPool) {
+        let snapshotter = snapshotter(&pool, 100).await;
+        let cart_id = CartId::new();
+
+        snapshotter
+            .store_snapshot(&created_state(cart_id))
+            .await
+            .unwrap();
+        let default = StatePart::new(0, SubmitCartCommand { cart_id }.state_query());
+        let loaded = snapshotter.load_snapshot(default).await;
+
+        assert_eq!(loaded.version(), 7);
+    }
+
+    #[sqlx::test]
+    async fn incompatible_snapshot_falls_back_to_full_replay(pool: PgPool) {
+        let snapshotter = snapshotter(&pool, 100).await;
+        let cart_id = CartId::new();
+        snapshotter
+            .store_snapshot(&created_state(cart_id))
+            .await
+            .unwrap();
+        sqlx::query("UPDATE state_snapshot SET payload = '{\"old_shape\": true}'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let default = StatePart::new(0, SubmitCartCommand { cart_id }.state_query());
+        let loaded = snapshotter.load_snapshot(default).await;
+
+        assert_eq!(loaded.version(), 0);
+        assert_eq!(purge_snapshots(&pool, None).await.unwrap(), 0);
+    }
+
+    #[sqlx::test]
+    async fn snapshot_of_another_shape_falls_back_to_full_replay(pool: PgPool) {
+        let snapshotter = snapshotter(&pool, 100).await;
+        let cart_id = CartId::new();
+        snapshotter
+            .store_snapshot(&created_state(cart_id))
+            .await
+            .unwrap();
+        let reshaped = Snapshotter {
+            shapes: &[("SubmitCartState", 3)],
+            ..snapshotter
+        };
+
+        let default = StatePart::new(0, SubmitCartCommand { cart_id }.state_query());
+        let loaded = reshaped.load_snapshot(default).await;
+
+        assert_eq!(loaded.version(), 0);
+        assert_eq!(purge_snapshots(&pool, None).await.unwrap(), 0);
+    }
+
+    #[sqlx::test]
+    async fn snapshots_can_be_purged_by_state_query(pool: PgPool) {
+        let snapshotter = snapshotter(&pool, 100).await;
+        snapshotter
+            .store_snapshot(&created_state(CartId::new()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            purge_snapshots(&pool, Some("AddItemState")).await.unwrap(),
+            0
+        );
+        assert_eq!(
+            purge_snapshots(&pool, Some("SubmitCartState"))
+                .await
+                .unwrap(),
+            1
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Events for tests of projections and of queries over the `event` table.
+//!
+//! Events are appended through a Postgres event store, so that the `event` table holds them as
+//! Disintegrate writes them. A projection reads the time an event was appended from its
+//! `inserted_at`.
+
+use std::error::Error as StdError;
+
+use disintegrate::{Event, EventListener, EventStore as _, PersistedEvent};
+use sqlx::PgPool;
+
+use crate::domain::{DomainEvent, EventCodec, EventSerde, KeyStore, create_eventstore};
+
+/// Appends the events one at a time, so that each is appended later than the one before, and
+/// returns them as persisted.
+pub async fn append_events(
+    pool: &PgPool,
+    events: impl IntoIterator<Item = DomainEvent>,
+) -> Vec<PersistedEvent<i64, DomainEvent>> {
+    let serde = EventSerde::new(KeyStore::in_memory(), EventCodec::default());
+    let event_store = create_eventstore(pool, serde)
+        .await
+        .expect("Event store should be created.");
+    let mut appended = Vec::new();
+    for event in events {
+        appended.extend(
+            event_store
+                .append_without_validation(vec![event])
+                .await
+                .expect("Event should be appended."),
+        );
+    }
+    appended
+}
+
+/// Has the listener handle each event, as delivered by the listener's query.
+pub async fn handle_events<L, QE>(listener: &L, events: Vec<PersistedEvent<i64, DomainEvent>>)
+where
+    L: EventListener<i64, QE, Error = anyhow::Error>,
+    QE: TryFrom<DomainEvent> + Event + Clone + Send + Sync + 'static,
+    <QE as TryFrom<DomainEvent>>::Error: StdError + Send + Sync + 'static,
+{
+    for event in events {
+        let event_id = event.id();
+        let event = QE::try_from(event.into_inner())
+            .unwrap_or_else(|e| panic!("Event {event_id} is not selected by the listener. {e}"));
+        listener
+            .handle(PersistedEvent::new(event_id, event))
+            .await
+            .unwrap_or_else(|e| panic!("Event {event_id} should be handled. {e:#}"));
+    }
+}
+
+/// Appends the events and has the listener handle them.
+pub async fn append_and_handle<L, QE>(
+    pool: &PgPool,
+    listener: &L,
+    events: impl IntoIterator<Item = DomainEvent>,
+) -> Vec<i64>
+where
+    L: EventListener<i64, QE, Error = anyhow::Error>,
+    QE: TryFrom<DomainEvent> + Event + Clone + Send + Sync + 'static,
+    <QE as TryFrom<DomainEvent>>::Error: StdError + Send + Sync + 'static,
+{
+    let events = append_events(pool, events).await;
+    let event_ids = events.iter().map(|event| event.id()).collect();
+    handle_events(listener, events).await;
+    event_ids
+}
+
+/// Moves the time the event was appended, e.g. to spread events over several days.
+pub async fn set_appended_at(pool: &PgPool, event_id: i64, appended_at: jiff::Timestamp) {
+    sqlx::query(
+        "UPDATE event SET inserted_at = $2::TIMESTAMPTZ AT TIME ZONE current_setting('TimeZone') WHERE event_id = $1",
+    )
+    .bind(event_id)
+    .bind(appended_at.to_string())
+    .execute(pool)
+    .await
+    .expect("Event should be backdated.");
+}
+
+/// Sets the listener's Disintegrate checkpoint, creating the `event_listener` table as
+/// Disintegrate does if no listener has run.
+pub async fn set_checkpoint(pool: &PgPool, listener_id: &str, last_processed_event_id: i64) {
+    sqlx::query(
+        r#"CREATE TABLE IF NOT EXISTS event_listener (
+               id TEXT PRIMARY KEY,
+               last_processed_event_id BIGINT,
+               updated_at TIMESTAMP DEFAULT now())"#,
+    )
+    .execute(pool)
+    .await
+    .expect("event_listener table should be created.");
+    sqlx::query(
+        r#"INSERT INTO event_listener (id, last_processed_event_id) VALUES ($1, $2)
+           ON CONFLICT (id) DO UPDATE SET last_processed_event_id = $2"#,
+    )
+    .bind(listener_id)
+    .bind(last_processed_event_id)
+    .execute(pool)
+    .await
+    .expect("Checkpoint should be set.");
+}
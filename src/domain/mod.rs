@@ -2,35 +2,233 @@ pub mod cart;
 mod events;
 mod helpers;
 
-pub use events::{CartStream, DomainEvent, EmptyStream, InventoryStream, PricingStream};
+pub use events::{
+    CartStream, DomainEvent, EmptyStream, ForgottenStream, InventoryStream, LowStockStream,
+    PricingStream, PublicationStream, PublishedStream, RepublishCartStream, RepublishStream,
+    StockLevelStream,
+};
 pub use helpers::{
     PublishError,
+    archive::{ArchivedCarts, EventArchive},
+    command_bus::{Command, CommandBus, CommandEnvelope, CommandOrigin, Middleware, Next},
+    command_log::{
+        CommandLog, CommandLogEntry, CommandLogFilter, CommandOutcome, command_log_endpoint,
+    },
+    command_middleware::{CommandCounts, CommandMetrics, ConflictCounts},
+    crypto_shredding::{EventSerde, KeyStore, REDACTED},
+    decision_maker::{
+        DecisionMaker, DecisionResult, DryRun, DryRunParam, ExpectedEventId,
+        is_concurrency_conflict,
+    },
     device_fingerprint_calculator::default_fingerprint,
+    event_codec::{EventCodec, reencode_events},
     fake,
+    in_memory::InMemoryEventStore,
+    listener_monitor::{
+        ListenerError, ListenerMonitor, ListenerStatus, Monitored, listener_status_endpoint,
+    },
     live_read_models::{EventReadingError, read_from_events},
+    projection_failures::{
+        ProjectionFailure, ProjectionFailureFilter, find_projection_failures, park_event,
+        projection_failures_endpoint, replay_projection_failure,
+        replay_projection_failure_endpoint,
+    },
+    projections::{
+        ProjectionTransaction, RebuildableProjection, rebuild_projection, replay_projection,
+        reset_projection,
+    },
+    read_your_writes::{MinEventId, has_caught_up, wait_for_projection},
+    snapshots::{Snapshotter, purge_snapshots},
 };
 
-use disintegrate::{WithSnapshot, serde::json::Json};
-use disintegrate_postgres::{Error, PgEventStore, PgSnapshotter, decision_maker};
+use std::error::Error as StdError;
+
+use async_trait::async_trait;
+use disintegrate::{Event, PersistedEvent, StreamQuery};
+use disintegrate_postgres::{Error, PgEventId, PgEventStore};
+use futures::{
+    StreamExt, TryStreamExt, future,
+    stream::{self, BoxStream},
+};
 use sqlx::PgPool;
 
-pub type DecisionMaker = disintegrate_postgres::PgDecisionMaker<
-    events::DomainEvent,
-    Json<events::DomainEvent>,
-    disintegrate_postgres::WithPgSnapshot,
->;
+use crate::infra::SnapshotSettings;
+use helpers::crypto_shredding;
+
+/// The event store is Postgres, except in tests and the no-database dev mode where events are
+/// only held in memory.
+///
+/// The EventSerde encrypts and decrypts personal data synchronously, so it cannot wait on the
+/// `cart_key` table. Instead carts are given keys before their events are appended, and a stream
+/// that reads an event whose key is not cached loads the key and carries on from that event.
+#[derive(Clone)]
+pub struct EventStore {
+    events: Events,
+    key_store: KeyStore,
+}
+
+#[derive(Clone)]
+enum Events {
+    Postgres(PgEventStore<DomainEvent, EventSerde>),
+    InMemory(InMemoryEventStore<DomainEvent, EventSerde>),
+}
+
+impl EventStore {
+    /// The Postgres event store, which is needed by anything that works directly on its tables,
+    /// e.g. event listeners.
+    pub fn as_postgres(&self) -> Option<&PgEventStore<DomainEvent, EventSerde>> {
+        match &self.events {
+            Events::Postgres(event_store) => Some(event_store),
+            Events::InMemory(_) => None,
+        }
+    }
+
+    pub fn is_in_memory(&self) -> bool {
+        matches!(self.events, Events::InMemory(_))
+    }
+
+    fn stream_events<'a, QE>(
+        &'a self,
+        query: &'a StreamQuery<PgEventId, QE>,
+    ) -> BoxStream<'a, Result<PersistedEvent<PgEventId, QE>, Error>>
+    where
+        QE: TryFrom<DomainEvent> + Event + 'static + Clone + Send + Sync,
+        <QE as TryFrom<DomainEvent>>::Error: StdError + 'static + Send + Sync,
+    {
+        match &self.events {
+            Events::Postgres(event_store) => disintegrate::EventStore::stream(event_store, query),
+            Events::InMemory(event_store) => disintegrate::EventStore::stream(event_store, query),
+        }
+    }
+}
+
+#[async_trait]
+impl disintegrate::EventStore<PgEventId, DomainEvent> for EventStore {
+    type Error = Error;
+
+    fn stream<'a, QE>(
+        &'a self,
+        query: &'a StreamQuery<PgEventId, QE>,
+    ) -> BoxStream<'a, Result<PersistedEvent<PgEventId, QE>, Self::Error>>
+    where
+        QE: TryFrom<DomainEvent> + Event + 'static + Clone + Send + Sync,
+        <QE as TryFrom<DomainEvent>>::Error: StdError + 'static + Send + Sync,
+    {
+        stream::unfold(
+            Some((self.stream_events(query), 0)),
+            move |state| async move {
+                let (mut events, last_event_id) = state?;
+                loop {
+                    let error = match events.next().await? {
+                        Ok(event) => {
+                            let event_id = event.id();
+                            return Some((Ok(event), Some((events, event_id))));
+                        }
+                        Err(error) => error,
+                    };
+                    let cart_id = match &error {
+                        Error::Deserialization(e) => crypto_shredding::missing_key(e),
+                        _ => None,
+                    };
+                    let Some(cart_id) = cart_id else {
+                        return Some((Err(error), None));
+                    };
+                    match self.key_store.prefetch(&cart_id).await {
+                        Ok(true) => {}
+                        Ok(false) => return Some((Err(error), None)),
+                        Err(e) => return Some((Err(e.into()), None)),
+                    }
+                    // The event that failed is read again, now that its key is cached.
+                    events = self
+                        .stream_events(query)
+                        .try_filter(move |event| future::ready(event.id() > last_event_id))
+                        .boxed();
+                }
+            },
+        )
+        .boxed()
+    }
 
-pub type EventStore = PgEventStore<DomainEvent, Json<DomainEvent>>;
+    async fn append<QE>(
+        &self,
+        events: Vec<DomainEvent>,
+        query: StreamQuery<PgEventId, QE>,
+        version: PgEventId,
+    ) -> Result<Vec<PersistedEvent<PgEventId, DomainEvent>>, Self::Error>
+    where
+        DomainEvent: Clone + 'async_trait,
+        QE: Event + 'static + Clone + Send + Sync,
+    {
+        self.key_store.ensure_keys(&events).await?;
+        match &self.events {
+            Events::Postgres(event_store) => event_store.append(events, query, version).await,
+            Events::InMemory(event_store) => event_store.append(events, query, version).await,
+        }
+    }
 
-pub async fn create_eventstore(pool: &PgPool) -> Result<EventStore, Error> {
-    PgEventStore::new(pool.clone(), Json::<DomainEvent>::default()).await
+    async fn append_without_validation(
+        &self,
+        events: Vec<DomainEvent>,
+    ) -> Result<Vec<PersistedEvent<PgEventId, DomainEvent>>, Self::Error>
+    where
+        DomainEvent: Clone + 'async_trait,
+    {
+        self.key_store.ensure_keys(&events).await?;
+        match &self.events {
+            Events::Postgres(event_store) => event_store.append_without_validation(events).await,
+            Events::InMemory(event_store) => event_store.append_without_validation(events).await,
+        }
+    }
+}
+
+pub async fn create_eventstore(pool: &PgPool, serde: EventSerde) -> Result<EventStore, Error> {
+    Ok(EventStore {
+        key_store: serde.key_store().clone(),
+        events: Events::Postgres(PgEventStore::new(pool.clone(), serde).await?),
+    })
+}
+
+/// Creates an event store and decider which hold events in memory only. Nothing is persisted and
+/// no database is needed.
+pub fn create_in_memory_eventstore_and_decider() -> (EventStore, DecisionMaker) {
+    let serde = EventSerde::new(KeyStore::in_memory(), EventCodec::default());
+    create_in_memory_eventstore_and_decider_with(serde)
+}
+
+/// Use when the EventSerde, and with it the KeyStore, must be shared with other parts of the
+/// application.
+pub fn create_in_memory_eventstore_and_decider_with(
+    serde: EventSerde,
+) -> (EventStore, DecisionMaker) {
+    let event_store = EventStore {
+        key_store: serde.key_store().clone(),
+        events: Events::InMemory(InMemoryEventStore::new(serde)),
+    };
+    let decider = decision_maker(event_store.clone(), Snapshotter::disabled());
+    (event_store, decider)
+}
+
+fn decision_maker(event_store: EventStore, snapshotter: Snapshotter) -> DecisionMaker {
+    DecisionMaker::new(event_store, snapshotter)
 }
 
 pub async fn create_eventstore_and_decider(
     pool: &PgPool,
 ) -> Result<(EventStore, DecisionMaker), Error> {
-    let event_store = create_eventstore(pool).await?;
-    let snapshotter = PgSnapshotter::new(pool.clone(), 100).await?;
-    let decider = decision_maker(event_store.clone(), WithSnapshot::new(snapshotter));
+    let key_store = KeyStore::load(pool).await?;
+    let serde = EventSerde::new(key_store, EventCodec::default());
+    create_eventstore_and_decider_with(pool, serde, &SnapshotSettings::default()).await
+}
+
+/// Use when the EventSerde, and with it the KeyStore, must be shared with other parts of the
+/// application, e.g. the event handler that forgets carts.
+pub async fn create_eventstore_and_decider_with(
+    pool: &PgPool,
+    serde: EventSerde,
+    snapshot_settings: &SnapshotSettings,
+) -> Result<(EventStore, DecisionMaker), Error> {
+    let event_store = create_eventstore(pool, serde).await?;
+    let snapshotter = Snapshotter::new(pool.clone(), snapshot_settings);
+    let decider = decision_maker(event_store.clone(), snapshotter);
     Ok((event_store, decider))
 }
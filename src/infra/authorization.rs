@@ -0,0 +1,115 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, header::AUTHORIZATION},
+    middleware::Next,
+    response::Response,
+};
+
+use super::{ClientError, Settings};
+
+/// Route middleware for the admin and operational endpoints. A request must carry the admin
+/// token from the settings as `Authorization: Bearer <token>`. Without a token configured every
+/// request is refused.
+pub async fn require_admin_token(
+    State(settings): State<Settings>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ClientError> {
+    match &settings.admin.token {
+        Some(token) if is_authorized(request.headers(), token) => Ok(next.run(request).await),
+        Some(_) => Err(ClientError::Unauthorized(
+            "A valid admin bearer token is required.".to_owned(),
+        )),
+        None => Err(ClientError::Unauthorized(
+            "No admin token is configured, so admin endpoints are disabled.".to_owned(),
+        )),
+    }
+}
+
+/// Route middleware for the event feed. A request must carry the feed token, or the admin token,
+/// from the settings as `Authorization: Bearer <token>`. The feed token gives access to nothing
+/// else. Without either token configured every request is refused.
+pub async fn require_feed_token(
+    State(settings): State<Settings>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ClientError> {
+    authorize_feed(
+        request.headers(),
+        settings.feed.token.as_deref(),
+        settings.admin.token.as_deref(),
+    )?;
+    Ok(next.run(request).await)
+}
+
+fn authorize_feed(
+    headers: &HeaderMap,
+    feed_token: Option<&str>,
+    admin_token: Option<&str>,
+) -> Result<(), ClientError> {
+    if feed_token.is_none() && admin_token.is_none() {
+        return Err(ClientError::Unauthorized(
+            "No feed token is configured, so the feed is disabled.".to_owned(),
+        ));
+    }
+    if [feed_token, admin_token]
+        .into_iter()
+        .flatten()
+        .any(|token| is_authorized(headers, token))
+    {
+        Ok(())
+    } else {
+        Err(ClientError::Unauthorized(
+            "A valid feed bearer token is required.".to_owned(),
+        ))
+    }
+}
+
+fn is_authorized(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()))
+}
+
+/// Compares without returning at the first differing byte, so the time taken doesn't reveal how
+/// much of the token was guessed.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+//-------------------------- Tests -------------------------------
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(authorization).unwrap());
+        headers
+    }
+
+    #[test]
+    fn only_the_admin_bearer_token_is_authorized() {
+        assert!(is_authorized(&headers("Bearer secret"), "secret"));
+        assert!(!is_authorized(&headers("Bearer secre"), "secret"));
+        assert!(!is_authorized(&headers("Bearer secrets"), "secret"));
+        assert!(!is_authorized(&headers("secret"), "secret"));
+        assert!(!is_authorized(&HeaderMap::new(), "secret"));
+    }
+
+    #[test]
+    fn feed_accepts_the_feed_or_admin_token() {
+        let (feed, admin) = (Some("feed"), Some("admin"));
+        assert!(authorize_feed(&headers("Bearer feed"), feed, admin).is_ok());
+        assert!(authorize_feed(&headers("Bearer admin"), feed, admin).is_ok());
+        assert!(authorize_feed(&headers("Bearer admin"), None, admin).is_ok());
+        assert!(authorize_feed(&headers("Bearer other"), feed, admin).is_err());
+        assert!(authorize_feed(&HeaderMap::new(), feed, None).is_err());
+        assert!(authorize_feed(&headers("Bearer feed"), None, None).is_err());
+    }
+}
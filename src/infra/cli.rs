@@ -1,7 +1,65 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
 pub struct Cli {
-    #[arg(short, long)]
-    pub reset_cart_items: bool,
+    /// Runs without a database, holding events in memory only.
+    #[arg(long)]
+    pub in_memory: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Replays every cart history and reports those that violate invariants or would panic.
+    /// The event store is not modified.
+    Verify,
+    /// Manages the snapshots of command state.
+    Snapshots {
+        #[command(subcommand)]
+        command: SnapshotsCommand,
+    },
+    /// Makes a command against the current state without appending its events and prints the
+    /// state it was decided on and the events it would append.
+    DryRun {
+        /// The command, e.g. `SubmitCartCommand`.
+        command: String,
+        /// The command's fields as JSON, e.g. `{"cart_id": "..."}`.
+        payload: String,
+    },
+    /// Manages the projected read models.
+    Projections {
+        #[command(subcommand)]
+        command: ProjectionsCommand,
+    },
+    /// Manages the events held in the event store.
+    Events {
+        #[command(subcommand)]
+        command: EventsCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum EventsCommand {
+    /// Re-encodes existing events, including archived events, with the configured event codec.
+    Reencode,
+}
+
+#[derive(Subcommand)]
+pub enum ProjectionsCommand {
+    /// Replays the events into a fresh copy of a projection's read model and swaps it in, e.g.
+    /// `inventories`.
+    Rebuild { id: String },
+    /// Reports how far behind the event log every event listener is and its last error.
+    Status,
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotsCommand {
+    /// Deletes stored snapshots, e.g. after a state query changed without its shape being bumped.
+    Purge {
+        /// Only delete snapshots of this state query, e.g. `SubmitCartState`.
+        #[arg(long)]
+        query: Option<String>,
+    },
 }
@@ -5,13 +5,19 @@ use axum::{
 };
 use disintegrate::DecisionError;
 
-use crate::domain::cart::CartError;
+use crate::domain::{cart::CartError, is_concurrency_conflict};
 
 #[derive(Debug)]
 pub enum ClientError {
     Decision(DecisionError<CartError>),
     Domain(CartError),
     Payload(String),
+    /// The request cannot be served yet, e.g. a read model has not caught up. Worth retrying.
+    Unavailable(String),
+    /// The request was made against a stale view of the state. Refresh and retry.
+    Conflict(String),
+    /// The request lacks the credentials the endpoint requires.
+    Unauthorized(String),
     Internal(anyhow::Error),
 }
 
@@ -40,6 +46,9 @@ impl IntoResponse for ClientError {
             },
             ClientError::Domain(cart_error) => (StatusCode::BAD_REQUEST, cart_error.to_string()),
             ClientError::Payload(message) => (StatusCode::BAD_REQUEST, message),
+            ClientError::Unavailable(message) => (StatusCode::SERVICE_UNAVAILABLE, message),
+            ClientError::Conflict(message) => (StatusCode::CONFLICT, message),
+            ClientError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message),
             ClientError::Internal(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Please ask your system administrator to check the logs.".to_owned(),
@@ -52,13 +61,30 @@ impl IntoResponse for ClientError {
 
 impl From<DecisionError<CartError>> for ClientError {
     fn from(decision_error: DecisionError<CartError>) -> Self {
-        ClientError::Decision(decision_error)
+        match decision_error {
+            DecisionError::Domain(cart_error @ CartError::CartChanged { .. }) => {
+                ClientError::Conflict(cart_error.to_string())
+            }
+            DecisionError::Domain(cart_error @ CartError::CommandNotAllowed { .. }) => {
+                ClientError::Unauthorized(cart_error.to_string())
+            }
+            decision_error if is_concurrency_conflict(&decision_error) => ClientError::Conflict(
+                "Cart was changed by another request at the same time. Please retry.".to_owned(),
+            ),
+            decision_error => ClientError::Decision(decision_error),
+        }
     }
 }
 
 impl From<CartError> for ClientError {
     fn from(cart_error: CartError) -> Self {
-        ClientError::Domain(cart_error)
+        match cart_error {
+            CartError::CartChanged { .. } => ClientError::Conflict(cart_error.to_string()),
+            CartError::CommandNotAllowed { .. } => {
+                ClientError::Unauthorized(cart_error.to_string())
+            }
+            cart_error => ClientError::Domain(cart_error),
+        }
     }
 }
 
@@ -4,7 +4,9 @@ use config::Config;
 use serde::Deserialize;
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::domain::EventCodec;
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Settings {
@@ -12,6 +14,21 @@ pub struct Settings {
     pub application: ServerSettings,
     pub database: DatabaseSettings,
     pub kafka: KafkaSettings,
+    #[serde(default)]
+    pub snapshots: SnapshotSettings,
+    #[serde(default)]
+    pub archive: ArchiveSettings,
+    #[serde(default)]
+    pub listeners: ListenerSettings,
+    #[serde(default)]
+    pub inventory: InventorySettings,
+    #[serde(default)]
+    pub admin: AdminSettings,
+    #[serde(default)]
+    pub feed: FeedSettings,
+    /// Codec new events are written with. Events written with any codec can be read.
+    #[serde(default)]
+    pub event_codec: EventCodec,
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -20,6 +37,10 @@ pub struct ServerSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub logs_directory: String,
+    /// Holds events in memory rather than in Postgres. For development and demos only: nothing
+    /// is persisted, and projected read models, Kafka and the work queue are not run.
+    #[serde(default)]
+    pub in_memory: bool,
 }
 
 impl ServerSettings {
@@ -68,6 +89,116 @@ pub struct KafkaSettings {
     pub session_timeout_ms: u16,
 }
 
+#[derive(Clone, Deserialize, Debug)]
+pub struct SnapshotSettings {
+    /// Number of events between snapshots of a state query.
+    pub every: u64,
+    /// Overrides of `every` keyed by state query name, e.g. `SubmitCartState`.
+    #[serde(default)]
+    pub queries: HashMap<String, u64>,
+}
+
+impl Default for SnapshotSettings {
+    fn default() -> Self {
+        Self {
+            every: 100,
+            queries: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct ArchiveSettings {
+    /// Events of carts published more than this many days ago are archived.
+    pub after_days: i32,
+    /// Hours between runs of the archival job.
+    pub every_hours: u64,
+}
+
+impl Default for ArchiveSettings {
+    fn default() -> Self {
+        Self {
+            after_days: 30,
+            every_hours: 24,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct InventorySettings {
+    /// A product is low on stock once its inventory falls below this.
+    pub low_stock_threshold: i32,
+}
+
+impl Default for InventorySettings {
+    fn default() -> Self {
+        Self {
+            low_stock_threshold: 10,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct AdminSettings {
+    /// Bearer token the admin and operational endpoints require. Without one they are refused.
+    pub token: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct FeedSettings {
+    /// Read-only bearer token for the event feed, so a downstream consumer doesn't need the admin
+    /// token. The admin token is accepted too.
+    pub token: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct ListenerSettings {
+    /// What an event listener does about an event it fails to handle.
+    #[serde(default)]
+    pub failure_policy: FailurePolicy,
+    /// Overrides of `failure_policy` keyed by listener id, e.g. `cart_forgotten`.
+    #[serde(default)]
+    pub policies: HashMap<String, FailurePolicy>,
+}
+
+impl ListenerSettings {
+    pub fn failure_policy_for(&self, listener_id: &str) -> FailurePolicy {
+        self.policies
+            .get(listener_id)
+            .copied()
+            .unwrap_or(self.failure_policy)
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+pub struct FailurePolicy {
+    /// Number of times a failed event is handled again, with exponential backoff.
+    pub retries: usize,
+    /// Delay before the first retry.
+    pub backoff_ms: u64,
+    /// What is done once the retries are exhausted.
+    pub then: FailureAction,
+}
+
+impl Default for FailurePolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            backoff_ms: 100,
+            then: FailureAction::Park,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureAction {
+    /// Parks the event in `projection_failures` and moves on to the next event.
+    Park,
+    /// Fails the event, so the listener retries it on its next poll and gets no further.
+    Halt,
+}
+
 fn find_config_dir() -> anyhow::Result<PathBuf> {
     let current_dir =
         std::env::current_dir().context("Failed to determine the current directory.")?;
@@ -1,7 +1,13 @@
+mod authorization;
 mod cli;
 mod client_error;
 mod config;
 
-pub use cli::Cli;
+pub use authorization::{require_admin_token, require_feed_token};
+pub use cli::{Cli, Command, EventsCommand, ProjectionsCommand, SnapshotsCommand};
 pub use client_error::ClientError;
-pub use config::{DatabaseSettings, KafkaSettings, Settings, get_config_settings};
+pub use config::{
+    AdminSettings, ArchiveSettings, DatabaseSettings, FailureAction, FailurePolicy, FeedSettings,
+    InventorySettings, KafkaSettings, ListenerSettings, Settings, SnapshotSettings,
+    get_config_settings,
+};
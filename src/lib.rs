@@ -6,7 +6,10 @@ use std::time::Duration;
 
 use anyhow::Context;
 use axum::extract::FromRef;
-use domain::{DecisionMaker, EventStore, create_eventstore_and_decider};
+use domain::{
+    CommandBus, CommandLog, EventArchive, EventSerde, EventStore, KeyStore, ListenerMonitor,
+    create_eventstore_and_decider_with, create_in_memory_eventstore_and_decider_with,
+};
 use infra::{DatabaseSettings, Settings};
 use sqlx::{PgPool, postgres::PgPoolOptions};
 use subsystems::{
@@ -19,12 +22,27 @@ use tracing_appender::non_blocking::WorkerGuard;
 pub struct AppState {
     pub settings: Settings,
     pub pool: PgPool,
-    pub decider: DecisionMaker,
+    pub command_bus: CommandBus,
+    pub command_log: CommandLog,
     pub event_store: EventStore,
+    pub event_archive: EventArchive,
+    pub key_store: KeyStore,
+    pub listener_monitor: ListenerMonitor,
     pub work_queue: WorkQueue,
 }
 
 pub fn build_subsystems(state: AppState) -> Toplevel {
+    // Without a database only the webserver, with its live read models, can run.
+    if state.event_store.is_in_memory() {
+        let webserver = WebServer::new(state);
+        return Toplevel::new(async |s| {
+            s.start(SubsystemBuilder::new(
+                "Webserver",
+                webserver.into_subsystem(),
+            ));
+        });
+    }
+
     let event_listeners = EventListeners::new(state.clone());
     let kafka_listeners = KafkaListeners::new(state.clone());
     let work_queue_subsystem = WorkQueueSubsystem::new(state.clone());
@@ -82,19 +100,61 @@ pub fn configure_tracing(settings: &Settings) -> WorkerGuard {
 }
 
 pub async fn construct_app_state(settings: Settings) -> Result<AppState, anyhow::Error> {
+    if settings.application.in_memory {
+        return Ok(construct_in_memory_app_state(settings));
+    }
+
     let pool = construct_db_pool(&settings.database).await?;
-    let (event_store, decider) = create_eventstore_and_decider(&pool).await?;
+    let key_store = KeyStore::load(&pool)
+        .await
+        .context("Failed to load cart encryption keys.")?;
+    let serde = EventSerde::new(key_store.clone(), settings.event_codec);
+    let (event_store, decider) =
+        create_eventstore_and_decider_with(&pool, serde.clone(), &settings.snapshots).await?;
+    let event_archive = EventArchive::new(pool.clone(), serde);
+    let command_log = CommandLog::new(pool.clone());
+    let listener_monitor = ListenerMonitor::new(pool.clone(), settings.listeners.clone());
     let work_queue = WorkQueue::new(pool.clone());
 
     Ok(AppState {
         settings,
         pool,
         event_store,
-        decider,
+        command_bus: CommandBus::standard(decider, command_log.clone(), event_archive.clone()),
+        event_archive,
+        key_store,
+        command_log,
+        listener_monitor,
         work_queue,
     })
 }
 
+/// AppState whose events are held in memory. The pool never connects unless used, so endpoints
+/// backed by projected read models fail while live read models work.
+pub fn construct_in_memory_app_state(settings: Settings) -> AppState {
+    let pool = PgPoolOptions::new()
+        .acquire_timeout(std::time::Duration::from_secs(2))
+        .connect_lazy_with(settings.database.with_db_name());
+    let key_store = KeyStore::in_memory();
+    let serde = EventSerde::new(key_store.clone(), settings.event_codec);
+    let (event_store, decider) = create_in_memory_eventstore_and_decider_with(serde.clone());
+    let event_archive = EventArchive::in_memory(serde);
+    let command_log = CommandLog::in_memory();
+    let work_queue = WorkQueue::new(pool.clone());
+
+    AppState {
+        settings,
+        pool,
+        event_store,
+        command_bus: CommandBus::standard(decider, command_log.clone(), event_archive.clone()),
+        event_archive,
+        key_store,
+        command_log,
+        listener_monitor: ListenerMonitor::in_memory(),
+        work_queue,
+    }
+}
+
 pub async fn construct_db_pool(settings: &DatabaseSettings) -> Result<PgPool, anyhow::Error> {
     PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_secs(2))
@@ -1,17 +1,26 @@
 use anyhow::Context;
 use cart_server::{
-    configure_tracing, construct_app_state,
-    domain::cart::cart_items_from_db_read_model_reset,
-    infra::{Cli, get_config_settings},
+    AppState, configure_tracing, construct_app_state,
+    domain::{
+        cart::{dry_run_command, rebuild_projection_by_id, verify_carts},
+        purge_snapshots, reencode_events,
+    },
+    infra::{
+        Cli, Command, EventsCommand, ProjectionsCommand, SnapshotsCommand, get_config_settings,
+    },
     start_server,
+    subsystems::build_event_listeners,
 };
 use clap::Parser;
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     let cli = Cli::parse();
 
-    let settings = get_config_settings().context("Could not read application configuration.")?;
+    let mut settings =
+        get_config_settings().context("Could not read application configuration.")?;
+    settings.application.in_memory |= cli.in_memory;
 
     // _worker_guard is pulled back into the scope of main() to ensure all tracing events get
     // written to the log file when the program terminates, which is done when _worker_guard is
@@ -20,9 +29,91 @@ async fn main() -> anyhow::Result<()> {
 
     let app_state = construct_app_state(settings).await?;
 
-    if cli.reset_cart_items {
-        cart_items_from_db_read_model_reset(&app_state.pool).await?;
+    match cli.command {
+        Some(Command::Verify) => return verify(&app_state).await,
+        Some(Command::Snapshots {
+            command: SnapshotsCommand::Purge { query },
+        }) => {
+            let purged = purge_snapshots(&app_state.pool, query.as_deref()).await?;
+            println!("Purged {purged} snapshots.");
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::DryRun { command, payload }) => {
+            let dry_run = dry_run_command(&app_state.command_bus, &command, &payload).await?;
+            println!("{}", serde_json::to_string_pretty(&dry_run)?);
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Projections {
+            command: ProjectionsCommand::Rebuild { id },
+        }) => {
+            let replayed = rebuild_projection_by_id(
+                &app_state.pool,
+                &app_state.event_store,
+                &app_state.event_archive,
+                &id,
+            )
+            .await?;
+            println!("Rebuilt projection {id} from {replayed} events.");
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Projections {
+            command: ProjectionsCommand::Status,
+        }) => {
+            build_event_listeners(&app_state)?;
+            for status in app_state.listener_monitor.status().await? {
+                println!(
+                    "{}: processed up to {} of {}, {} events ({:.1}s) behind.",
+                    status.listener_id,
+                    status.last_processed_event_id,
+                    status.head_event_id,
+                    status.lag_events,
+                    status.lag_seconds
+                );
+                if let Some(last_error) = status.last_error {
+                    println!(
+                        "  Last failed at {} on event {}: {}",
+                        last_error.failed_at, last_error.event_id, last_error.error
+                    );
+                }
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Events {
+            command: EventsCommand::Reencode,
+        }) => {
+            let codec = app_state.settings.event_codec;
+            let reencoded = reencode_events(&app_state.pool, codec).await?;
+            println!("Re-encoded {reencoded} events as {codec:?}.");
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
     }
 
-    start_server(app_state).await
+    start_server(app_state).await?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Fails with exit code 1 when problems are found. The code is returned rather than exiting so
+/// that `_worker_guard` is dropped and the report's log lines are flushed.
+async fn verify(app_state: &AppState) -> anyhow::Result<ExitCode> {
+    // Panics are expected while replaying bad histories and are reported below.
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let result = verify_carts(&app_state.event_store, &app_state.event_archive).await;
+    std::panic::set_hook(default_hook);
+
+    let (cart_count, violations) = result?;
+    for violation in &violations {
+        println!("{violation}");
+    }
+    println!(
+        "Verified {cart_count} carts. {} problems found.",
+        violations.len()
+    );
+
+    if violations.is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
 }
@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+use anyhow::Context;
 use async_trait::async_trait;
 use disintegrate_postgres::{PgEventListener, PgEventListenerConfig};
 use tokio::select;
@@ -9,9 +10,16 @@ use tracing::{error, info};
 
 use crate::{
     AppState,
-    domain::cart::{
-        CartItemsReadModelProjection, CartSubmittedEventHandler,
-        CartsWithProductsReadModelProjection, InventoriesReadModelProjection,
+    domain::{
+        DomainEvent, EventSerde,
+        cart::{
+            CartForgottenEventHandler, CartItemSearchProjection, CartItemsReadModelProjection,
+            CartOverviewProjection, CartRepublishRequestedEventHandler, CartSubmittedEventHandler,
+            CartsWithProductsReadModelProjection, InventoriesReadModelProjection,
+            InventoryHistoryProjection, LowStockDetectedEventHandler, LowStockDetector,
+            PriceChangedEventHandler, PriceHistoryProjection, PublicationStatusProjection,
+            SalesAnalyticsProjection,
+        },
     },
 };
 
@@ -25,29 +33,90 @@ impl EventListeners {
     }
 }
 
+/// Builds the event listeners, registering each with the `ListenerMonitor`.
+pub fn build_event_listeners(
+    state: &AppState,
+) -> Result<PgEventListener<DomainEvent, EventSerde>, anyhow::Error> {
+    let event_store = state
+        .event_store
+        .as_postgres()
+        .cloned()
+        .context("Event listeners need the Postgres event store.")?;
+    let monitor = &state.listener_monitor;
+    Ok(PgEventListener::builder(event_store)
+        .register_listener(
+            monitor.watch(CartItemSearchProjection::new(state.pool.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(CartItemsReadModelProjection::new(state.pool.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(CartOverviewProjection::new(state.pool.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(CartsWithProductsReadModelProjection::new(
+                state.pool.clone(),
+            )),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(PriceChangedEventHandler::new(state.work_queue.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(PriceHistoryProjection::new(state.pool.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(CartSubmittedEventHandler::new(state.work_queue.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(SalesAnalyticsProjection::new(state.pool.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(PublicationStatusProjection::new(state.pool.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(CartRepublishRequestedEventHandler::new(
+                state.work_queue.clone(),
+            )),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(CartForgottenEventHandler::new(state.key_store.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(InventoriesReadModelProjection::new(state.pool.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(InventoryHistoryProjection::new(state.pool.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(LowStockDetector::new(
+                state.command_bus.clone(),
+                state.settings.inventory.low_stock_threshold,
+            )),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        )
+        .register_listener(
+            monitor.watch(LowStockDetectedEventHandler::new(state.work_queue.clone())),
+            PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
+        ))
+}
+
 #[async_trait]
 impl IntoSubsystem<anyhow::Error> for EventListeners {
     async fn run(self, subsys: SubsystemHandle) -> Result<(), anyhow::Error> {
-        let event_listeners = PgEventListener::builder(self.state.event_store)
-            .register_listener(
-                CartItemsReadModelProjection::new(self.state.pool.clone()),
-                PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
-            )
-            .register_listener(
-                CartsWithProductsReadModelProjection::new(
-                    self.state.pool.clone(),
-                    self.state.decider,
-                ),
-                PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
-            )
-            .register_listener(
-                CartSubmittedEventHandler::new(self.state.work_queue.clone()),
-                PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
-            )
-            .register_listener(
-                InventoriesReadModelProjection::new(self.state.pool),
-                PgEventListenerConfig::poller(Duration::from_secs(5)).with_notifier(),
-            );
+        let event_listeners = build_event_listeners(&self.state)?;
 
         info!("Event Listeners starting.");
         let cancellation_token = CancellationToken::new();
@@ -51,7 +51,7 @@ impl IntoSubsystem<anyhow::Error> for KafkaListeners {
             name: "InventoryChangedMessageHander".to_owned(),
             pool: self.state.pool.clone(),
             settings: self.state.settings.kafka.clone(),
-            handler: InventoryChangedTranslator::new(self.state.decider.clone()),
+            handler: InventoryChangedTranslator::new(self.state.command_bus.clone()),
         };
         subsys.start(SubsystemBuilder::new(
             listener.name.clone(),
@@ -62,7 +62,7 @@ impl IntoSubsystem<anyhow::Error> for KafkaListeners {
             name: "PriceChangedMessageHander".to_owned(),
             pool: self.state.pool,
             settings: self.state.settings.kafka,
-            handler: PriceChangeTranslator::new(self.state.decider),
+            handler: PriceChangeTranslator::new(self.state.command_bus),
         };
         subsys.start(SubsystemBuilder::new(
             listener.name.clone(),
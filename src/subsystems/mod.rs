@@ -3,7 +3,7 @@ mod kafka_listeners;
 mod web_server;
 pub mod work_queue;
 
-pub use event_listeners::EventListeners;
+pub use event_listeners::{EventListeners, build_event_listeners};
 pub use kafka_listeners::{KafkaListeners, KafkaMessageHandler};
 pub use web_server::WebServer;
 pub use work_queue::subsystem::WorkQueueSubsystem;
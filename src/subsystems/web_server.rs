@@ -1,7 +1,10 @@
+use std::collections::BTreeMap;
+
 use async_trait::async_trait;
 use axum::{
     Json,
     extract::State,
+    middleware,
     routing::{get, post},
 };
 use futures::FutureExt;
@@ -10,7 +13,14 @@ use tokio_graceful_shutdown::{IntoSubsystem, SubsystemHandle};
 use tower_http::trace::TraceLayer;
 use tracing::{error, info};
 
-use crate::{AppState, domain::cart::carts_with_products_endpoint, infra::ClientError};
+use crate::{
+    AppState,
+    domain::{
+        CommandBus, CommandCounts, ConflictCounts, cart::carts_with_products_endpoint,
+        command_log_endpoint,
+    },
+    infra::{ClientError, require_admin_token, require_feed_token},
+};
 
 pub struct WebServer {
     state: AppState,
@@ -27,11 +37,49 @@ impl IntoSubsystem<anyhow::Error> for WebServer {
     async fn run(self, subsys: SubsystemHandle) -> Result<(), anyhow::Error> {
         let address = self.state.settings.application.address();
 
+        // Admin and operational endpoints, which require the admin token.
+        let admin_router = axum::Router::new()
+            .route(
+                "/admin/projections/{projection_id}/rebuild",
+                post(crate::domain::cart::rebuild_projection_endpoint),
+            )
+            .route(
+                "/admin/listeners",
+                get(crate::domain::listener_status_endpoint),
+            )
+            .route(
+                "/admin/failures",
+                get(crate::domain::projection_failures_endpoint),
+            )
+            .route(
+                "/admin/failures/{projection_failure_id}/replay",
+                post(crate::domain::replay_projection_failure_endpoint),
+            )
+            .route("/commandlog", get(command_log_endpoint))
+            .route("/metrics/commands", get(command_metrics_endpoint))
+            .route("/metrics/conflicts", get(conflict_metrics_endpoint))
+            .route_layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                require_admin_token,
+            ));
+
+        // The event feed, for downstream consumers, which requires the read-only feed token.
+        let feed_router = axum::Router::new()
+            .route("/feed", get(crate::domain::cart::event_feed_endpoint))
+            .route_layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                require_feed_token,
+            ));
+
         let router = axum::Router::new()
             .route(
                 "/additem/{cart_id}",
                 post(crate::domain::cart::add_item_endpoint),
             )
+            .route(
+                "/{cart_id}/events",
+                get(crate::domain::cart::cart_events_endpoint),
+            )
             .route(
                 "/{cart_id}/cartitems",
                 get(crate::domain::cart::cart_items_endpoint),
@@ -40,6 +88,11 @@ impl IntoSubsystem<anyhow::Error> for WebServer {
                 "/{cart_id}/cartitemsfromdb",
                 get(crate::domain::cart::cart_items_from_db_endpoint),
             )
+            .route("/carts", get(crate::domain::cart::cart_overview_endpoint))
+            .route(
+                "/carts/{cart_id}/publication",
+                get(crate::domain::cart::publication_status_endpoint),
+            )
             .route(
                 "/cartswithproducts/{product_id}",
                 get(carts_with_products_endpoint),
@@ -52,19 +105,57 @@ impl IntoSubsystem<anyhow::Error> for WebServer {
                 "/clearcart/{cart_id}",
                 post(crate::domain::cart::clear_cart_endpoint),
             )
+            .route(
+                "/forgetcart/{cart_id}",
+                post(crate::domain::cart::forget_cart_endpoint),
+            )
+            .route(
+                "/inventories/lowstock",
+                get(crate::domain::cart::low_stock_endpoint),
+            )
             .route(
                 "/inventories/{product_id}",
                 get(crate::domain::cart::inventories_endpoint),
             )
+            .route(
+                "/inventories/{product_id}/history",
+                get(crate::domain::cart::inventory_history_endpoint),
+            )
+            .route(
+                "/products/{product_id}/prices",
+                get(crate::domain::cart::price_history_endpoint),
+            )
+            .route(
+                "/analytics/sales/daily",
+                get(crate::domain::cart::daily_sales_endpoint),
+            )
+            .route(
+                "/analytics/sales/products",
+                get(crate::domain::cart::top_products_endpoint),
+            )
+            .route(
+                "/analytics/sales/summary",
+                get(crate::domain::cart::sales_summary_endpoint),
+            )
             .route(
                 "/removeitem/{cart_id}",
                 post(crate::domain::cart::remove_item_endpoint),
             )
+            .route(
+                "/republishcart/{cart_id}",
+                post(crate::domain::cart::republish_cart_endpoint),
+            )
+            .route(
+                "/search/cart-items",
+                get(crate::domain::cart::cart_item_search_endpoint),
+            )
             .route(
                 "/submitcart/{cart_id}",
                 post(crate::domain::cart::submit_cart_endpoint),
             )
             .route("/healthcheck", get(health_check_endpoint))
+            .merge(admin_router)
+            .merge(feed_router)
             .layer(TraceLayer::new_for_http())
             .with_state(self.state);
 
@@ -90,3 +181,15 @@ pub async fn health_check_endpoint(
 ) -> Result<Json<String>, ClientError> {
     Ok(Json("Ok".to_owned()))
 }
+
+pub async fn command_metrics_endpoint(
+    State(command_bus): State<CommandBus>,
+) -> Result<Json<BTreeMap<&'static str, CommandCounts>>, ClientError> {
+    Ok(Json(command_bus.metrics().counts()))
+}
+
+pub async fn conflict_metrics_endpoint(
+    State(command_bus): State<CommandBus>,
+) -> Result<Json<ConflictCounts>, ClientError> {
+    Ok(Json(command_bus.metrics().conflict_counts()))
+}
@@ -49,6 +49,15 @@ pub enum TaskStatus {
     Failed,
 }
 
+/// What became of a task that did not fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskOutcome {
+    Done,
+    /// The task cannot run yet, e.g. because a read model it reads has not caught up. It is run
+    /// again after the delay, which does not count as a failed attempt.
+    Deferred(Duration),
+}
+
 #[derive(Debug, Clone)]
 pub struct TaskArgs {
     pub trigger: TaskTrigger,
@@ -118,6 +127,23 @@ impl WorkQueue {
         Ok(permanent_failure)
     }
 
+    /// Queues the task again to be attempted after `delay`, without counting a failed attempt.
+    pub async fn defer_task(&self, task_id: TaskId, delay: Duration) -> Result<(), anyhow::Error> {
+        let now = jiff::Timestamp::now().to_sqlx();
+        let next_attempt_at = (Zoned::now().datetime() + delay).to_sqlx();
+        sqlx::query!(
+            "UPDATE queue SET status = $1, updated_at = $2, next_attempt_at = $3 WHERE task_id = $4",
+            TaskStatus::Queued as TaskStatus,
+            now as Timestamp,
+            next_attempt_at as DateTime,
+            task_id as TaskId
+        )
+        .execute(&self.pool)
+        .await
+        .with_context(|| format!("Problem deferring Task {task_id}."))?;
+        Ok(())
+    }
+
     pub async fn fetch(&self, task_id: TaskId) -> Result<TaskRow, anyhow::Error> {
         sqlx::query_as!(
             TaskRow,
@@ -141,6 +167,24 @@ impl WorkQueue {
         .with_context(|| format!("Problem retrieving Task {task_id}."))
     }
 
+    /// Returns true if a task of the given type is in the queue and has not permanently failed.
+    pub async fn is_queued(&self, task_type: &str) -> Result<bool, anyhow::Error> {
+        let queued = sqlx::query_scalar!(
+            "SELECT EXISTS(
+                SELECT 1 FROM queue
+                WHERE
+                    task_type = $1
+                    AND failed_attempts < max_attempts
+                    AND next_attempt_at <= timeout_at
+            )",
+            task_type
+        )
+        .fetch_one(&self.pool)
+        .await
+        .with_context(|| format!("Problem checking for queued {task_type} task."))?;
+        Ok(queued.unwrap_or_default())
+    }
+
     pub async fn pull(&self, number_of_tasks: i64) -> Result<Vec<Task>, anyhow::Error> {
         let now_timestamp = jiff::Timestamp::now();
         let now_datetime: jiff::civil::DateTime = now_timestamp.to_zoned(TimeZone::system()).into();
@@ -344,6 +388,30 @@ mod tests {
         assert_eq!(tasks.len(), 2, "There should be two tasks in the queue.");
     }
 
+    #[sqlx::test]
+    async fn permanently_failed_tasks_should_not_be_seen_as_queued(pool: PgPool) {
+        let work_queue = WorkQueue::new(pool);
+        let task_type = TaskDomainArgs::TestingFailure.to_string();
+
+        work_queue
+            .push(TaskArgs {
+                trigger: TaskTrigger::ScheduleNow,
+                limits: TaskLimit::MaxAttempts(1),
+                domain_args: TaskDomainArgs::TestingFailure,
+            })
+            .await
+            .expect("Task should be queued.");
+        assert!(work_queue.is_queued(&task_type).await.unwrap());
+
+        let tasks = work_queue.pull(100).await.expect("Task should be found.");
+        let task = tasks.first().expect("Task should be there.");
+        work_queue
+            .fail_task(task.task_id)
+            .await
+            .expect("Task should be marked as failed.");
+        assert!(!work_queue.is_queued(&task_type).await.unwrap());
+    }
+
     #[sqlx::test]
     async fn failing_task_should_calculate_next_attempt_correctly(pool: PgPool) {
         let work_queue = WorkQueue::new(pool.clone());
@@ -403,4 +471,35 @@ mod tests {
         let difference = (attempt_2_at - attempt_1_at).get_milliseconds();
         assert_eq!(difference, 250);
     }
+
+    #[sqlx::test]
+    async fn deferred_task_should_wait_without_failing(pool: PgPool) {
+        let work_queue = WorkQueue::new(pool);
+        work_queue
+            .push(TaskArgs {
+                trigger: TaskTrigger::ScheduleNow,
+                limits: TaskLimit::MaxAttempts(1),
+                domain_args: TaskDomainArgs::TestingSuccess,
+            })
+            .await
+            .expect("Task should be queued.");
+        let tasks = work_queue.pull(100).await.expect("Task should be found.");
+        let task_id = tasks.first().expect("Task should be there.").task_id;
+
+        work_queue
+            .defer_task(task_id, Duration::from_secs(60))
+            .await
+            .expect("Task should be deferred.");
+
+        let task_row = work_queue
+            .fetch(task_id)
+            .await
+            .expect("TaskRow should be found.");
+        assert_eq!(task_row.status, TaskStatus::Queued);
+        assert_eq!(task_row.failed_attempts, 0);
+        assert!(
+            work_queue.pull(100).await.unwrap().is_empty(),
+            "A deferred task should not be pulled before its delay has passed."
+        );
+    }
 }
@@ -10,7 +10,10 @@ use tracing::{error, info};
 
 use crate::AppState;
 
-use super::tasks::handle_task;
+use super::{
+    queue::TaskOutcome,
+    tasks::{handle_task, schedule_archive_carts},
+};
 
 const CONCURRENCY: usize = 10;
 
@@ -24,6 +27,10 @@ impl WorkQueueSubsystem {
     }
 
     async fn start(&self) {
+        if let Err(err) = schedule_archive_carts(&self.state).await {
+            error!("WorkQueueSubSystem: scheduling archival of carts failed with {err}");
+        }
+
         loop {
             let tasks = match self.state.work_queue.pull(CONCURRENCY as i64).await {
                 Ok(tasks) => tasks,
@@ -40,7 +47,11 @@ impl WorkQueueSubsystem {
                     let success_event = task.domain_args.success_event();
                     let failure_event = task.domain_args.failure_event();
                     let result = match handle_task(&self.state, task).await {
-                        Ok(_) => {
+                        Ok(TaskOutcome::Deferred(delay)) => {
+                            info!("WorkQueueSubSystem: task({task_id}) deferred for {delay:?}");
+                            self.state.work_queue.defer_task(task_id, delay).await
+                        }
+                        Ok(TaskOutcome::Done) => {
                             match self.state.work_queue.delete_task(task_id).await {
                                 Ok(_) => {
                                     if let Some(success_event) = success_event {
@@ -1,18 +1,28 @@
+use std::time::Duration;
+
 use anyhow::bail;
+use jiff::Zoned;
 use strum_macros::Display;
+use tracing::info;
 
 use crate::{
     AppState,
     domain::{
         DomainEvent,
-        cart::{PublishCartProcessorArgs, publish_cart_processor},
+        cart::{
+            ArchiveProductProcessorArgs, LowStockNotification, PublishCartProcessorArgs,
+            archive_product_processor, notify_low_stock_processor, publish_cart_processor,
+        },
     },
 };
 
-use super::queue::Task;
+use super::queue::{Task, TaskArgs, TaskLimit, TaskOutcome, TaskTrigger};
 
 #[derive(Debug, Clone, Display, serde::Serialize, serde::Deserialize)]
 pub enum TaskDomainArgs {
+    ArchiveCarts(ArchiveCartsArgs),
+    ArchiveProduct(ArchiveProductProcessorArgs),
+    NotifyLowStock(LowStockNotification),
     PublishCart(PublishCartProcessorArgs),
     TestingSuccess,
     TestingFailure,
@@ -22,6 +32,9 @@ impl TaskDomainArgs {
     /// Should return a DomainEvent if the work queue is responsible for storing a failure event.
     pub fn failure_event(&self) -> Option<DomainEvent> {
         match self {
+            TaskDomainArgs::ArchiveCarts(_) => None,
+            TaskDomainArgs::ArchiveProduct(_) => None,
+            TaskDomainArgs::NotifyLowStock(_) => None,
             TaskDomainArgs::PublishCart(processor_args) => {
                 Some(DomainEvent::CartPublicationFailed {
                     cart_id: processor_args.message.cart_id,
@@ -36,6 +49,9 @@ impl TaskDomainArgs {
     /// event.
     pub fn success_event(&self) -> Option<DomainEvent> {
         match self {
+            TaskDomainArgs::ArchiveCarts(_) => None,
+            TaskDomainArgs::ArchiveProduct(_) => None,
+            TaskDomainArgs::NotifyLowStock(_) => None,
             TaskDomainArgs::PublishCart(_) => None,
             TaskDomainArgs::TestingSuccess => None,
             TaskDomainArgs::TestingFailure => None,
@@ -43,14 +59,80 @@ impl TaskDomainArgs {
     }
 }
 
-pub async fn handle_task(state: &AppState, task: Task) -> Result<(), anyhow::Error> {
+pub async fn handle_task(state: &AppState, task: Task) -> Result<TaskOutcome, anyhow::Error> {
     match task.domain_args {
+        TaskDomainArgs::ArchiveCarts(args) => {
+            // Until the event listeners are registered it is unknown which events they handle.
+            if state.listener_monitor.event_types().is_empty() {
+                info!("Event listeners have not started, so no events were archived.");
+            } else {
+                let archived = state
+                    .event_archive
+                    .archive_published_carts(args.after_days, &state.listener_monitor)
+                    .await?;
+                info!("Archived {archived} events.");
+            }
+            schedule_next_archive_carts(state).await?;
+            Ok(TaskOutcome::Done)
+        }
+        TaskDomainArgs::ArchiveProduct(args) => {
+            archive_product_processor(&state.pool, &state.command_bus, args).await
+        }
+        TaskDomainArgs::NotifyLowStock(notification) => {
+            notify_low_stock_processor(&state.settings.kafka, notification).await?;
+            Ok(TaskOutcome::Done)
+        }
         TaskDomainArgs::PublishCart(args) => {
-            publish_cart_processor(&state.settings.kafka, &state.event_store, args)
-                .await
-                .map_err(Into::<anyhow::Error>::into)
+            publish_cart_processor(&state.settings.kafka, &state.event_store, args).await?;
+            Ok(TaskOutcome::Done)
         }
-        TaskDomainArgs::TestingSuccess => Ok(()),
+        TaskDomainArgs::TestingSuccess => Ok(TaskOutcome::Done),
         TaskDomainArgs::TestingFailure => bail!("Failed as expected."),
     }
 }
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ArchiveCartsArgs {
+    pub after_days: i32,
+}
+
+/// Queues the recurring archival of the events of long-finished carts, unless already queued.
+/// The task queues its own next run on success. Should it permanently fail, it is queued again
+/// the next time the work queue starts.
+pub async fn schedule_archive_carts(state: &AppState) -> Result<(), anyhow::Error> {
+    let domain_args = archive_carts_args(state);
+    if state.work_queue.is_queued(&domain_args.to_string()).await? {
+        return Ok(());
+    }
+
+    state
+        .work_queue
+        .push(TaskArgs {
+            trigger: TaskTrigger::ScheduleNow,
+            limits: TaskLimit::MaxAttempts(5),
+            domain_args,
+        })
+        .await?;
+    Ok(())
+}
+
+async fn schedule_next_archive_carts(state: &AppState) -> Result<(), anyhow::Error> {
+    let next_run_at =
+        Zoned::now().datetime() + Duration::from_secs(state.settings.archive.every_hours * 3600);
+
+    state
+        .work_queue
+        .push(TaskArgs {
+            trigger: TaskTrigger::ScheduleFor(next_run_at),
+            limits: TaskLimit::MaxAttempts(5),
+            domain_args: archive_carts_args(state),
+        })
+        .await?;
+    Ok(())
+}
+
+fn archive_carts_args(state: &AppState) -> TaskDomainArgs {
+    TaskDomainArgs::ArchiveCarts(ArchiveCartsArgs {
+        after_days: state.settings.archive.after_days,
+    })
+}
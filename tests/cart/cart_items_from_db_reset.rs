@@ -1,7 +1,10 @@
 use axum::http::StatusCode;
-use cart_server::domain::cart::{
-    AddItemPayload, CartId, CartItemsReadModel, cart_items_from_db_read_model,
-    cart_items_from_db_read_model_reset,
+use cart_server::domain::{
+    cart::{
+        AddItemPayload, CartId, CartItemsReadModel, CartItemsReadModelProjection,
+        cart_items_from_db_read_model,
+    },
+    reset_projection,
 };
 use fake::{Fake, Faker};
 use httpc_test::Client;
@@ -73,7 +76,7 @@ async fn it_resets_cart_items_read_model(
         .expect("Expected pool to be created.");
 
     // Reset the read model.
-    cart_items_from_db_read_model_reset(&pool)
+    reset_projection(&pool, &CartItemsReadModelProjection::new(pool.clone()))
         .await
         .expect("Read model should reset.");
 